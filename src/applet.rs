@@ -1,4 +1,5 @@
 use cosmic::app::{Core, Task};
+use cosmic::iced::widget::qr_code;
 use cosmic::iced::window::Id;
 use cosmic::iced::{Length, Rectangle};
 use cosmic::iced_runtime::core::window;
@@ -6,74 +7,279 @@ use cosmic::surface::action::{app_popup, destroy_popup};
 use cosmic::widget::{self, text};
 use cosmic::Element;
 
-use crate::config::{AppConfig, Credentials};
-use crate::tailscale::{self, PeerInfo, TailscaleStatus, VncType};
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use cosmic::iced::futures::{SinkExt, Stream};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+
+use crate::backend::{self, TailscaleBackend};
+use crate::config::{AppConfig, Credentials, Launcher, ProbeSettings, ServiceDef};
+use crate::notifications::{self, EventContext, Notifier, NotifyEvent};
+use crate::portal;
+use crate::probe::{self, ProbeCache, Prober};
+use crate::tailscale::{
+    self, BackendState, ExitNodeSuggestion, FileTarget, IpnNotify, LoginProfile, NetcheckReport,
+    PeerInfo, PingPath, Pong, ServeEndpoint, TailscaleError, TailscalePrefs, TailscaleStatus,
+    VncType, WaitingFile,
+};
 
 const APP_ID: &str = "io.github.reality2_roycdavies.cosmic-tailscale";
-
-enum TailscaleCommand {
+const ADMIN_CONSOLE_URL: &str = "https://login.tailscale.com/admin/machines";
+const INSTALL_URL: &str = "https://tailscale.com/download/linux";
+const START_DAEMON_CMD: &str = "sudo systemctl enable --now tailscaled";
+
+/// How often stale probe results are renewed, and status is polled when the
+/// IPN bus is unavailable.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+/// Delay before reopening the IPN bus after it drops.
+const WATCH_RETRY: Duration = Duration::from_secs(10);
+/// How long after the user connects, disconnects or switches accounts that
+/// tunnel changes are not worth a notification.
+const USER_CHANGE_GRACE: Duration = Duration::from_secs(15);
+
+pub(crate) enum TailscaleCommand {
     Toggle,
+    Login,
+    SetExitNode(Option<PeerInfo>),
+    SuggestExitNode,
+    SetBoolPref(&'static str, bool),
+    SwitchProfile(LoginProfile),
+    AddProfile,
+    Logout,
+    /// Ping a peer (node ID, Tailscale IP) without holding up other commands
+    Ping(String, String),
+    Netcheck,
+    /// Show desktop notifications
+    Notify(Vec<NotifyEvent>),
+    /// Probe peers for these services, within these limits, from now on
+    SetProbeConfig(Vec<ServiceDef>, ProbeSettings),
+    /// Probe every online peer again
+    RefreshServices,
+    /// Send files to a peer over Taildrop, one after another, reporting
+    /// progress under each file's transfer ID
+    SendFiles(PeerInfo, Vec<(u64, PathBuf)>),
+    /// Save a received file into this folder
+    SaveFile(String, PathBuf),
+    DiscardFile(String),
+    /// List the folders a peer shares with us (node ID, tailnet, peer name)
+    ListDrives(String, String, String),
 }
 
-#[derive(Debug)]
-enum TailscaleEvent {
-    StatusUpdate(Result<TailscaleStatus, String>),
+#[derive(Debug, Clone)]
+pub(crate) enum TailscaleEvent {
+    StatusUpdate(Result<TailscaleStatus, TailscaleError>),
+    PrefsUpdate(TailscalePrefs),
+    ProfilesUpdate(Vec<LoginProfile>),
+    ExitNodeSuggestion(Result<ExitNodeSuggestion, TailscaleError>),
+    ServeUpdate(Vec<ServeEndpoint>),
+    FileTargets(Vec<FileTarget>),
+    /// Bytes of a transfer sent so far
+    TransferProgress(u64, u64),
+    TransferComplete(u64, Result<(), TailscaleError>),
+    WaitingFiles(Vec<WaitingFile>),
+    FileSaved(String, Result<PathBuf, TailscaleError>),
+    /// Folders the peer with this node ID shares with us over Taildrive.
+    PeerDrives(String, Result<Vec<String>, TailscaleError>),
+    /// tailscaled wants the user to visit this URL to log in.
+    AuthUrl(String),
     ToggleStarted,
-    ToggleComplete(Result<String, String>),
+    ToggleComplete(Result<String, TailscaleError>),
+    /// A one-off command (e.g. changing exit node) finished.
+    CommandComplete(Result<String, TailscaleError>),
+    /// A ping reply from the peer with this node ID.
+    Pong(String, Pong),
+    PingComplete(String, Result<(), TailscaleError>),
+    Netcheck(Result<NetcheckReport, TailscaleError>),
 }
 
-/// Which service a credential dialog is for.
+/// Which view the popup shows.
 #[derive(Debug, Clone, PartialEq)]
-pub(crate) enum CredService {
+pub(crate) enum Page {
+    Main,
+    ExitNodeLocations,
+    /// Details of one peer, by stable node ID
+    PeerDetail(String),
+    Netcheck,
+}
+
+/// A service on a peer, as picked from one of its service buttons.
+#[derive(Debug, Clone)]
+pub(crate) struct ServiceTarget {
+    /// [`ServiceDef::id`] in the registry
+    service: String,
+    /// DNS name, or the IP when the peer has none
+    host: String,
+    ip: String,
+    port: u16,
+    vnc_type: VncType,
+}
+
+/// Quick filters for the peer list.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum QuickFilter {
+    Online,
     Ssh,
-    Rdp,
-    Vnc,
-    NoMachine,
+    ExitNodes,
+    /// Untagged nodes owned by the logged-in user
+    Mine,
+    /// Nodes owned by other users or by tags
+    Shared,
 }
 
-impl CredService {
+impl QuickFilter {
+    const ALL: [Self; 5] = [
+        Self::Online,
+        Self::Ssh,
+        Self::ExitNodes,
+        Self::Mine,
+        Self::Shared,
+    ];
+
     fn label(&self) -> &'static str {
         match self {
+            Self::Online => "Online",
             Self::Ssh => "SSH",
-            Self::Rdp => "RDP",
-            Self::Vnc => "VNC",
-            Self::NoMachine => "NoMachine",
+            Self::ExitNodes => "Exit nodes",
+            Self::Mine => "Mine",
+            Self::Shared => "Shared",
         }
     }
-    fn key(&self) -> &'static str {
-        match self {
-            Self::Ssh => "ssh",
-            Self::Rdp => "rdp",
-            Self::Vnc => "vnc",
-            Self::NoMachine => "nomachine",
+}
+
+/// Peer list filter. It lives on the applet, so it is kept across popup
+/// opens for the session.
+#[derive(Debug, Clone, Default)]
+struct PeerFilter {
+    text: String,
+    quick: Vec<QuickFilter>,
+}
+
+impl PeerFilter {
+    fn is_active(&self) -> bool {
+        !self.text.trim().is_empty() || !self.quick.is_empty()
+    }
+
+    fn has(&self, filter: QuickFilter) -> bool {
+        self.quick.contains(&filter)
+    }
+
+    /// Turn a quick filter on or off; "Mine" and "Shared" exclude each other.
+    fn toggle(&mut self, filter: QuickFilter) {
+        if self.has(filter) {
+            self.quick.retain(|f| *f != filter);
+            return;
+        }
+        match filter {
+            QuickFilter::Mine => self.quick.retain(|f| *f != QuickFilter::Shared),
+            QuickFilter::Shared => self.quick.retain(|f| *f != QuickFilter::Mine),
+            _ => {}
+        }
+        self.quick.push(filter);
+    }
+
+    /// Whether `peer` passes every quick filter and the text matches its
+    /// name, DNS name, IPs, OS or tags.
+    fn matches(&self, peer: &PeerInfo, self_user_id: i64) -> bool {
+        let mine = peer.tags.is_empty() && peer.user_id == self_user_id;
+        let quick_ok = self.quick.iter().all(|f| match f {
+            QuickFilter::Online => peer.online,
+            QuickFilter::Ssh => peer.ssh_enabled || peer.services.has("ssh"),
+            QuickFilter::ExitNodes => peer.exit_node_option,
+            QuickFilter::Mine => mine,
+            QuickFilter::Shared => !mine,
+        });
+        if !quick_ok {
+            return false;
         }
+
+        let needle = self.text.trim().to_lowercase();
+        needle.is_empty()
+            || std::iter::once(peer.display_name())
+                .chain([peer.dns_name.as_str(), peer.os.as_str()])
+                .chain(peer.tailscale_ips.iter().map(String::as_str))
+                .chain(peer.tags.iter().map(String::as_str))
+                .any(|field| field.to_lowercase().contains(&needle))
     }
 }
 
+/// Replies from the latest ping, shown on that peer's detail page.
+#[derive(Debug, Clone)]
+struct PingRun {
+    peer_id: String,
+    pongs: Vec<Pong>,
+    running: bool,
+    error: Option<TailscaleError>,
+}
+
+/// Folders a peer shares with us, shown on its detail page.
+#[derive(Debug, Clone)]
+struct PeerDrives {
+    peer_id: String,
+    /// None while the list is being fetched
+    drives: Option<Result<Vec<String>, TailscaleError>>,
+}
+
+/// A file being sent to a peer over Taildrop.
+#[derive(Debug, Clone)]
+struct Transfer {
+    id: u64,
+    peer_name: String,
+    file_name: String,
+    size: u64,
+    sent: u64,
+    /// Set once the transfer has finished
+    result: Option<Result<(), TailscaleError>>,
+}
+
 #[derive(Debug, Clone)]
 struct CredDialog {
-    service: CredService,
-    dns_name: String,
-    ip: String,
+    target: ServiceTarget,
     username: String,
     remember: bool,
-    vnc_type: VncType,
 }
 
 #[derive(Debug, Clone)]
 pub enum Message {
-    PollStatus,
+    Tick,
+    WorkerReady(UnboundedSender<TailscaleCommand>),
+    Event(TailscaleEvent),
     ToggleConnection,
+    Login,
+    // Accounts (profile ID to switch to)
+    ToggleAccountMenu,
+    SwitchProfile(String),
+    AddProfile,
+    Logout,
+    // Exit node (peer ID, or None to stop using one)
+    ToggleExitNodeMenu,
+    SetExitNode(Option<String>),
+    SetExitNodeAllowLan(bool),
+    ToggleCountry(String),
+    ShowPage(Page),
+    // Peer list filter
+    PeerFilterText(String),
+    TogglePeerFilter(QuickFilter),
+    /// Pin or unpin a peer (stable node ID)
+    ToggleFavorite(String),
+    Ping(String),
+    RunNetcheck,
     CopyToClipboard(String),
-    // Service launches (direct, no dialog)
-    LaunchHttp(String),
+    /// Pick files to send to a peer (stable node ID) over Taildrop
+    SendFiles(String),
+    FilesChosen(String, Result<Vec<PathBuf>, String>),
+    /// Remove finished transfers from the list
+    ClearTransfers,
+    /// Save or discard a received file
+    SaveFile(String),
+    DiscardFile(String),
+    /// Re-probe all peers for services
+    RefreshServices,
+    /// Launch a service, asking for a username first if it needs one
+    OpenService(ServiceTarget),
     // Credential dialog
-    ShowCredDialog {
-        service: CredService,
-        dns_name: String,
-        ip: String,
-        vnc_type: VncType,
-    },
     CredUsername(String),
     CredRemember(bool),
     CredConnect,
@@ -81,6 +287,7 @@ pub enum Message {
     // Settings
     OpenSettings,
     OpenAdminConsole,
+    OpenUrl(String),
     // Popup
     PopupClosed(Id),
     Surface(cosmic::surface::Action),
@@ -89,6 +296,8 @@ pub enum Message {
 pub struct TailscaleApplet {
     core: Core,
     popup: Option<Id>,
+    page: Page,
+    backend_state: BackendState,
     connected: bool,
     is_toggling: bool,
     status_message: String,
@@ -98,24 +307,62 @@ pub struct TailscaleApplet {
     self_ip: String,
     self_dns_name: String,
     self_https_url: String,
+    serve_endpoints: Vec<ServeEndpoint>,
+    file_targets: Vec<FileTarget>,
+    transfers: Vec<Transfer>,
+    next_transfer_id: u64,
+    waiting_files: Vec<WaitingFile>,
+    /// Received files being saved
+    saving: HashSet<String>,
+    /// Received files auto-accept already tried to save, kept while they
+    /// are still listed so they are neither saved twice nor retried after
+    /// failing
+    auto_saved: HashSet<String>,
     self_relay: String,
+    self_user_id: i64,
+    self_key_expiry: Option<i64>,
     version: String,
     tailnet_name: String,
+    login_name: String,
+    profiles: Vec<LoginProfile>,
+    account_menu_open: bool,
     exit_node_active: bool,
     exit_node_name: String,
+    exit_node_allow_lan: bool,
+    exit_node_menu_open: bool,
+    exit_node_suggestion: Option<ExitNodeSuggestion>,
+    expanded_country: Option<String>,
     // Peers
     peers: Vec<PeerInfo>,
-    error: Option<String>,
+    peer_filter: PeerFilter,
+    ping: Option<PingRun>,
+    peer_drives: Option<PeerDrives>,
+    netcheck: Option<Result<NetcheckReport, TailscaleError>>,
+    netcheck_running: bool,
+    error: Option<TailscaleError>,
+    // Previous status snapshot and when it arrived, to notify about changes
+    prev_status: Option<TailscaleStatus>,
+    prev_status_at: i64,
+    /// When the user last asked to change connection state
+    user_state_change: Option<Instant>,
+    /// Last failed command, kept while it needs the user to act (e.g. set operator)
+    command_error: Option<TailscaleError>,
+    // Pending interactive login
+    auth_url: Option<String>,
+    auth_qr: Option<qr_code::Data>,
     // Clipboard feedback
     copied_text: Option<String>,
     copied_hold_ticks: u8,
     // Config
     config: AppConfig,
+    /// Service registry and probe settings the worker was last given
+    worker_services: Vec<ServiceDef>,
+    worker_probing: ProbeSettings,
     // Credential dialog
     cred_dialog: Option<CredDialog>,
-    // Background thread channels
-    cmd_tx: std::sync::mpsc::Sender<TailscaleCommand>,
-    event_rx: std::sync::mpsc::Receiver<TailscaleEvent>,
+    backend: Arc<dyn TailscaleBackend>,
+    // Background worker, set once its subscription starts
+    cmd_tx: Option<UnboundedSender<TailscaleCommand>>,
 }
 
 impl cosmic::Application for TailscaleApplet {
@@ -134,95 +381,10 @@ impl cosmic::Application for TailscaleApplet {
     }
 
     fn init(core: Core, _flags: Self::Flags) -> (Self, Task<Self::Message>) {
-        let (cmd_tx, cmd_rx) = std::sync::mpsc::channel();
-        let (event_tx, event_rx) = std::sync::mpsc::channel();
-
-        // Get initial status
-        let (
-            connected,
-            self_hostname,
-            self_ip,
-            self_dns_name,
-            self_https_url,
-            self_relay,
-            version,
-            tailnet_name,
-            peers,
-            exit_node_active,
-            exit_node_name,
-        ) = match tailscale::get_status() {
-            Ok(status) => {
-                let https_url = status.self_node.https_url(&status.cert_domains);
-                (
-                    status.backend_state == "Running",
-                    status.self_node.display_name().to_string(),
-                    status
-                        .self_node
-                        .tailscale_ips
-                        .first()
-                        .cloned()
-                        .unwrap_or_default(),
-                    status.self_node.dns_display(),
-                    https_url,
-                    status.self_node.relay.clone(),
-                    status.version.clone(),
-                    status.tailnet_name.clone(),
-                    status.peers.clone(),
-                    status.exit_node_active,
-                    status.exit_node_name.clone(),
-                )
-            }
-            Err(_) => (
-                false,
-                String::new(),
-                String::new(),
-                String::new(),
-                String::new(),
-                String::new(),
-                String::new(),
-                String::new(),
-                vec![],
-                false,
-                String::new(),
-            ),
-        };
-
-        std::thread::spawn(move || {
-            let rt = tokio::runtime::Runtime::new().expect("Failed to create tokio runtime");
-            rt.block_on(run_background(cmd_rx, event_tx));
-        });
-
-        let applet = Self {
-            core,
-            popup: None,
-            connected,
-            is_toggling: false,
-            status_hold_ticks: 0,
-            status_message: if connected {
-                "Connected".to_string()
-            } else {
-                "Disconnected".to_string()
-            },
-            self_hostname,
-            self_ip,
-            self_dns_name,
-            self_https_url,
-            self_relay,
-            version,
-            tailnet_name,
-            peers,
-            exit_node_active,
-            exit_node_name,
-            error: None,
-            copied_text: None,
-            copied_hold_ticks: 0,
-            config: AppConfig::load(),
-            cred_dialog: None,
-            cmd_tx,
-            event_rx,
-        };
-
-        (applet, Task::none())
+        (
+            Self::new(core, backend::default_backend(), backend::default_config()),
+            Task::none(),
+        )
     }
 
     fn on_close_requested(&self, id: window::Id) -> Option<Message> {
@@ -231,7 +393,11 @@ impl cosmic::Application for TailscaleApplet {
 
     fn update(&mut self, message: Self::Message) -> Task<Self::Message> {
         match message {
-            Message::PollStatus => {
+            Message::Tick => {
+                // Pick up changes saved by the settings app
+                self.config.reload_if_changed();
+                self.sync_probe_config();
+
                 // Decrement copied feedback timer
                 if self.copied_hold_ticks > 0 {
                     self.copied_hold_ticks -= 1;
@@ -240,73 +406,144 @@ impl cosmic::Application for TailscaleApplet {
                     }
                 }
 
-                while let Ok(event) = self.event_rx.try_recv() {
-                    match event {
-                        TailscaleEvent::StatusUpdate(result) => match result {
-                            Ok(status) => {
-                                self.connected = status.backend_state == "Running";
-                                self.self_hostname =
-                                    status.self_node.display_name().to_string();
-                                self.self_ip = status
-                                    .self_node
-                                    .tailscale_ips
-                                    .first()
-                                    .cloned()
-                                    .unwrap_or_default();
-                                self.self_dns_name = status.self_node.dns_display();
-                                self.self_https_url =
-                                    status.self_node.https_url(&status.cert_domains);
-                                self.self_relay = status.self_node.relay.clone();
-                                self.version = status.version.clone();
-                                self.tailnet_name = status.tailnet_name;
-                                self.peers = status.peers;
-                                self.exit_node_active = status.exit_node_active;
-                                self.exit_node_name = status.exit_node_name;
-                                self.error = None;
-
-                                if self.status_hold_ticks > 0 {
-                                    self.status_hold_ticks -= 1;
-                                } else if !self.is_toggling {
-                                    self.status_message = if self.connected {
-                                        "Connected".to_string()
-                                    } else {
-                                        "Disconnected".to_string()
-                                    };
-                                }
-                            }
-                            Err(e) => {
-                                self.connected = false;
-                                self.error = Some(e);
-                                if self.status_hold_ticks > 0 {
-                                    self.status_hold_ticks -= 1;
-                                } else if !self.is_toggling {
-                                    self.status_message = "Not running".to_string();
-                                }
-                            }
-                        },
-                        TailscaleEvent::ToggleStarted => {
-                            self.is_toggling = true;
-                            self.status_message = if self.connected {
-                                "Disconnecting...".to_string()
-                            } else {
-                                "Connecting...".to_string()
-                            };
+                // Keep a toggle result on screen briefly before showing state again
+                if self.status_hold_ticks > 0 {
+                    self.status_hold_ticks -= 1;
+                    if self.status_hold_ticks == 0 && !self.is_toggling {
+                        self.status_message = self.state_message();
+                    }
+                }
+            }
+
+            Message::WorkerReady(cmd_tx) => {
+                self.cmd_tx = Some(cmd_tx);
+            }
+
+            Message::Event(event) => match event {
+                TailscaleEvent::StatusUpdate(result) => {
+                    match result {
+                        Ok(status) => {
+                            self.notify_changes(&status);
+                            self.apply_status(status);
                         }
-                        TailscaleEvent::ToggleComplete(result) => {
-                            self.is_toggling = false;
-                            self.status_hold_ticks = 3;
-                            match result {
-                                Ok(msg) => self.status_message = msg,
-                                Err(e) => self.status_message = format!("Error: {e}"),
-                            }
+                        Err(e) => {
+                            self.backend_state = BackendState::NoState;
+                            self.connected = false;
+                            self.error = Some(e);
                         }
                     }
+                    if self.status_hold_ticks == 0 && !self.is_toggling {
+                        self.status_message = self.state_message();
+                    }
                 }
-            }
+                TailscaleEvent::ProfilesUpdate(profiles) => {
+                    self.profiles = profiles;
+                }
+                TailscaleEvent::PrefsUpdate(prefs) => {
+                    self.login_name = prefs.login_name;
+                    self.exit_node_allow_lan = prefs.exit_node_allow_lan_access;
+                }
+                TailscaleEvent::ExitNodeSuggestion(result) => {
+                    self.exit_node_suggestion = result.ok();
+                }
+                TailscaleEvent::ServeUpdate(endpoints) => {
+                    self.serve_endpoints = endpoints;
+                }
+                TailscaleEvent::FileTargets(targets) => {
+                    self.file_targets = targets;
+                }
+                TailscaleEvent::TransferProgress(id, sent) => {
+                    if let Some(t) = self.transfers.iter_mut().find(|t| t.id == id) {
+                        t.sent = sent;
+                    }
+                }
+                TailscaleEvent::WaitingFiles(files) => {
+                    self.waiting_files = files;
+                    let waiting = &self.waiting_files;
+                    self.auto_saved.retain(|name| waiting.iter().any(|f| f.name == *name));
+                    if self.config.taildrop.auto_accept {
+                        let names: Vec<String> = self
+                            .waiting_files
+                            .iter()
+                            .filter(|f| !self.auto_saved.contains(&f.name))
+                            .map(|f| f.name.clone())
+                            .collect();
+                        for name in names {
+                            self.auto_saved.insert(name.clone());
+                            self.save_file(name);
+                        }
+                    }
+                }
+                TailscaleEvent::FileSaved(name, result) => {
+                    self.saving.remove(&name);
+                    match result {
+                        Ok(path) => {
+                            self.waiting_files.retain(|f| f.name != name);
+                            self.apply_command_result(Ok(format!("Saved {}", path.display())));
+                        }
+                        Err(e) => self.apply_command_result(Err(e)),
+                    }
+                }
+                TailscaleEvent::TransferComplete(id, result) => {
+                    if let Some(t) = self.transfers.iter_mut().find(|t| t.id == id) {
+                        if result.is_ok() {
+                            t.sent = t.size;
+                        }
+                        t.result = Some(result);
+                    }
+                }
+                TailscaleEvent::AuthUrl(url) => {
+                    if !self.connected {
+                        self.set_auth_url(Some(url));
+                    }
+                }
+                TailscaleEvent::ToggleStarted => {
+                    self.is_toggling = true;
+                    self.status_message = if self.connected {
+                        "Disconnecting...".to_string()
+                    } else {
+                        "Connecting...".to_string()
+                    };
+                }
+                TailscaleEvent::ToggleComplete(result) => {
+                    self.is_toggling = false;
+                    self.apply_command_result(result);
+                }
+                TailscaleEvent::CommandComplete(result) => {
+                    self.apply_command_result(result);
+                }
+                TailscaleEvent::Pong(peer_id, pong) => {
+                    if let Some(ref mut run) = self.ping {
+                        if run.peer_id == peer_id {
+                            run.pongs.push(pong);
+                        }
+                    }
+                }
+                TailscaleEvent::Netcheck(result) => {
+                    self.netcheck_running = false;
+                    self.netcheck = Some(result);
+                }
+                TailscaleEvent::PeerDrives(peer_id, result) => {
+                    if let Some(ref mut list) = self.peer_drives {
+                        if list.peer_id == peer_id {
+                            list.drives = Some(result);
+                        }
+                    }
+                }
+                TailscaleEvent::PingComplete(peer_id, result) => {
+                    if let Some(ref mut run) = self.ping {
+                        if run.peer_id == peer_id {
+                            run.running = false;
+                            run.error = result.err();
+                        }
+                    }
+                }
+            },
 
             Message::PopupClosed(id) => {
                 if self.popup == Some(id) {
                     self.popup = None;
+                    self.page = Page::Main;
                 }
             }
 
@@ -317,7 +554,10 @@ impl cosmic::Application for TailscaleApplet {
             }
 
             Message::ToggleConnection => {
-                let _ = self.cmd_tx.send(TailscaleCommand::Toggle);
+                if let Some(ref cmd_tx) = self.cmd_tx {
+                    let _ = cmd_tx.send(TailscaleCommand::Toggle);
+                }
+                self.user_state_change = Some(Instant::now());
                 self.is_toggling = true;
                 self.status_message = if self.connected {
                     "Disconnecting...".to_string()
@@ -326,6 +566,126 @@ impl cosmic::Application for TailscaleApplet {
                 };
             }
 
+            Message::Login => {
+                if let Some(ref cmd_tx) = self.cmd_tx {
+                    let _ = cmd_tx.send(TailscaleCommand::Login);
+                }
+                self.user_state_change = Some(Instant::now());
+                self.status_message = "Starting login...".to_string();
+            }
+
+            Message::ToggleAccountMenu => {
+                self.account_menu_open = !self.account_menu_open;
+            }
+
+            Message::SwitchProfile(id) => {
+                self.account_menu_open = false;
+                let profile = self.profiles.iter().find(|p| p.id == id).cloned();
+                if let (Some(profile), Some(ref cmd_tx)) = (profile, &self.cmd_tx) {
+                    let _ = cmd_tx.send(TailscaleCommand::SwitchProfile(profile));
+                }
+                self.user_state_change = Some(Instant::now());
+            }
+
+            Message::AddProfile => {
+                self.account_menu_open = false;
+                if let Some(ref cmd_tx) = self.cmd_tx {
+                    let _ = cmd_tx.send(TailscaleCommand::AddProfile);
+                }
+                self.user_state_change = Some(Instant::now());
+            }
+
+            Message::Logout => {
+                self.account_menu_open = false;
+                if let Some(ref cmd_tx) = self.cmd_tx {
+                    let _ = cmd_tx.send(TailscaleCommand::Logout);
+                }
+                self.user_state_change = Some(Instant::now());
+            }
+
+            Message::ToggleExitNodeMenu => {
+                self.exit_node_menu_open = !self.exit_node_menu_open;
+                if self.exit_node_menu_open {
+                    if let Some(ref cmd_tx) = self.cmd_tx {
+                        let _ = cmd_tx.send(TailscaleCommand::SuggestExitNode);
+                    }
+                }
+            }
+
+            Message::ToggleCountry(country) => {
+                if self.expanded_country.as_ref() == Some(&country) {
+                    self.expanded_country = None;
+                } else {
+                    self.expanded_country = Some(country);
+                }
+            }
+
+            Message::ShowPage(page) => {
+                let first_netcheck = page == Page::Netcheck && self.netcheck.is_none();
+                if let Page::PeerDetail(ref id) = page {
+                    self.list_drives(id);
+                }
+                self.page = page;
+                if first_netcheck {
+                    return self.update(Message::RunNetcheck);
+                }
+            }
+
+            Message::RunNetcheck => {
+                if let Some(ref cmd_tx) = self.cmd_tx {
+                    if !self.netcheck_running {
+                        let _ = cmd_tx.send(TailscaleCommand::Netcheck);
+                        self.netcheck_running = true;
+                    }
+                }
+            }
+
+            Message::PeerFilterText(text) => {
+                self.peer_filter.text = text;
+            }
+
+            Message::TogglePeerFilter(filter) => {
+                self.peer_filter.toggle(filter);
+            }
+
+            Message::ToggleFavorite(node_id) => {
+                self.config.toggle_favorite(&self.tailnet_name, &node_id);
+            }
+
+            Message::Ping(node_id) => {
+                let ip = self
+                    .peers
+                    .iter()
+                    .find(|p| p.id == node_id)
+                    .and_then(|p| p.tailscale_ips.first().cloned());
+                if let (Some(ip), Some(ref cmd_tx)) = (ip, &self.cmd_tx) {
+                    let _ = cmd_tx.send(TailscaleCommand::Ping(node_id.clone(), ip));
+                    self.ping = Some(PingRun {
+                        peer_id: node_id,
+                        pongs: Vec::new(),
+                        running: true,
+                        error: None,
+                    });
+                }
+            }
+
+            Message::SetExitNode(peer_id) => {
+                let peer = peer_id.and_then(|id| self.peers.iter().find(|p| p.id == id).cloned());
+                if let Some(ref cmd_tx) = self.cmd_tx {
+                    let _ = cmd_tx.send(TailscaleCommand::SetExitNode(peer));
+                }
+            }
+
+            Message::SetExitNodeAllowLan(allow) => {
+                self.exit_node_allow_lan = allow;
+                if let Some(ref cmd_tx) = self.cmd_tx {
+                    let _ = cmd_tx.send(TailscaleCommand::SetBoolPref(
+                        "exit-node-allow-lan-access",
+                        allow,
+                    ));
+                }
+            }
+
             Message::CopyToClipboard(text_val) => {
                 let text_clone = text_val.clone();
                 std::thread::spawn(move || {
@@ -337,30 +697,96 @@ impl cosmic::Application for TailscaleApplet {
                 self.copied_hold_ticks = 1; // ~3 seconds (1 tick at 3s poll ≈ 3s)
             }
 
-            Message::LaunchHttp(dns_name) => {
-                std::thread::spawn(move || {
-                    let _ = std::process::Command::new("xdg-open")
-                        .arg(&format!("http://{dns_name}"))
-                        .spawn();
-                });
+            Message::SendFiles(peer_id) => {
+                let Some(peer) = self.peers.iter().find(|p| p.id == peer_id) else {
+                    return Task::none();
+                };
+                let title = format!("Send to {}", peer.display_name());
+                return Task::perform(
+                    async move { portal::pick_files(&title).await },
+                    move |result| {
+                        cosmic::Action::App(Message::FilesChosen(
+                            peer_id,
+                            result.map_err(|e| e.to_string()),
+                        ))
+                    },
+                );
+            }
+
+            Message::FilesChosen(peer_id, result) => {
+                let files = match result {
+                    Ok(files) => files,
+                    Err(e) => {
+                        self.apply_command_result(Err(TailscaleError::CommandFailed(format!(
+                            "File chooser failed: {e}"
+                        ))));
+                        return Task::none();
+                    }
+                };
+                let Some(peer) = self.peers.iter().find(|p| p.id == peer_id).cloned() else {
+                    return Task::none();
+                };
+                if files.is_empty() {
+                    return Task::none();
+                }
+                let mut batch = Vec::new();
+                for path in files {
+                    let id = self.next_transfer_id;
+                    self.next_transfer_id += 1;
+                    self.transfers.push(Transfer {
+                        id,
+                        peer_name: peer.display_name().to_string(),
+                        file_name: path
+                            .file_name()
+                            .map(|n| n.to_string_lossy().into_owned())
+                            .unwrap_or_default(),
+                        size: std::fs::metadata(&path).map_or(0, |m| m.len()),
+                        sent: 0,
+                        result: None,
+                    });
+                    batch.push((id, path));
+                }
+                if let Some(ref cmd_tx) = self.cmd_tx {
+                    let _ = cmd_tx.send(TailscaleCommand::SendFiles(peer, batch));
+                }
+            }
+
+            Message::ClearTransfers => {
+                self.transfers.retain(|t| t.result.is_none());
+            }
+
+            Message::SaveFile(name) => self.save_file(name),
+
+            Message::DiscardFile(name) => {
+                self.waiting_files.retain(|f| f.name != name);
+                if let Some(ref cmd_tx) = self.cmd_tx {
+                    let _ = cmd_tx.send(TailscaleCommand::DiscardFile(name));
+                }
+            }
+
+            Message::RefreshServices => {
+                if let Some(ref cmd_tx) = self.cmd_tx {
+                    let _ = cmd_tx.send(TailscaleCommand::RefreshServices);
+                }
             }
 
-            Message::ShowCredDialog {
-                service,
-                dns_name,
-                ip,
-                vnc_type,
-            } => {
-                let saved = self.config.get_creds(service.key(), &dns_name);
+            Message::OpenService(target) => {
+                let Some(def) = self.config.service(&target.service) else {
+                    return Task::none();
+                };
+                if !def.asks_username() {
+                    launch_service(def, target, String::new());
+                    return Task::none();
+                }
+                let saved = self
+                    .config
+                    .get_creds(&self.tailnet_name, &def.id, &target.host);
                 self.cred_dialog = Some(CredDialog {
                     username: saved
                         .map(|c| c.username.clone())
                         .unwrap_or_default(),
                     remember: true,
-                    service,
-                    dns_name,
-                    ip,
-                    vnc_type,
+                    target,
                 });
             }
 
@@ -377,207 +803,38 @@ impl cosmic::Application for TailscaleApplet {
             }
 
             Message::CredConnect => {
-                if let Some(dialog) = self.cred_dialog.take() {
-                    // Save credentials if requested
-                    if dialog.remember {
-                        let creds = Credentials {
-                            username: dialog.username.clone(),
-                        };
-                        self.config
-                            .save_creds(dialog.service.key(), &dialog.dns_name, creds);
+                let Some(dialog) = self.cred_dialog.take() else {
+                    return Task::none();
+                };
+                let Some(def) = self.config.service(&dialog.target.service).cloned() else {
+                    return Task::none();
+                };
 
-                        // Also update ssh_usernames for backwards compat
-                        if dialog.service == CredService::Ssh {
-                            if let Some(peer) = self.peers.iter().find(|p| p.dns_display() == dialog.dns_name) {
+                // Save credentials if requested
+                if dialog.remember {
+                    let creds = Credentials {
+                        username: dialog.username.clone(),
+                    };
+                    self.config
+                        .save_creds(&self.tailnet_name, &def.id, &dialog.target.host, creds);
+
+                    // Also update ssh_usernames for backwards compat
+                    if def.launch == Launcher::Ssh {
+                        if let Some(peer) = self.peers.iter().find(|p| p.dns_display() == dialog.target.host) {
+                            let tailnet = &self.tailnet_name;
+                            self.config.update(|config| {
+                                let scoped = config.tailnet_mut(tailnet);
                                 if !dialog.username.is_empty() {
-                                    self.config.ssh_usernames.insert(peer.hostname.clone(), dialog.username.clone());
-                                } else {
-                                    self.config.ssh_usernames.remove(&peer.hostname);
-                                }
-                                self.config.save();
-                            }
-                        }
-                    }
-
-                    // Launch the service
-                    let username = dialog.username;
-                    let dns_name = dialog.dns_name;
-                    let ip = dialog.ip;
-                    let vnc_type = dialog.vnc_type;
-
-                    match dialog.service {
-                        CredService::Ssh => {
-                            let target = if username.is_empty() {
-                                dns_name
-                            } else {
-                                format!("{username}@{dns_name}")
-                            };
-                            std::thread::spawn(move || {
-                                let terminals = [
-                                    ("cosmic-term", vec!["-e", "ssh", &target]),
-                                    ("gnome-terminal", vec!["--", "ssh", &target]),
-                                    ("konsole", vec!["-e", "ssh", &target]),
-                                    ("xterm", vec!["-e", "ssh", &target]),
-                                ];
-                                for (term, args) in &terminals {
-                                    if std::process::Command::new(term)
-                                        .args(args)
-                                        .spawn()
-                                        .is_ok()
-                                    {
-                                        return;
-                                    }
-                                }
-                                eprintln!("Failed to launch ssh: no terminal emulator found");
-                            });
-                        }
-                        CredService::Rdp => {
-                            std::thread::spawn(move || {
-                                let remmina_dir = dirs::home_dir()
-                                    .unwrap_or_else(|| std::path::PathBuf::from("/tmp"))
-                                    .join(".local/share/remmina");
-                                let _ = std::fs::create_dir_all(&remmina_dir);
-                                let profile = remmina_dir
-                                    .join(format!(
-                                        "tailscale-rdp-{}.remmina",
-                                        dns_name.replace(|c: char| !c.is_alphanumeric(), "_")
-                                    ))
-                                    .to_string_lossy()
-                                    .to_string();
-                                // Only create the file if it doesn't exist yet;
-                                // reuse any existing file so user customisations persist.
-                                if !std::path::Path::new(&profile).exists() {
-                                    let content = format!("\
-[remmina]
-name={dns_name}
-protocol=RDP
-server={dns_name}
-username={username}
-colordepth=32
-quality=2
-glyph-cache=true
-network=lan
-gfx=false
-rfx=false
-disableautoreconnect=0
-");
-                                    if let Err(e) = std::fs::write(&profile, &content) {
-                                        eprintln!("Failed to write remmina profile: {e}");
-                                        return;
-                                    }
-                                }
-                                let native = std::process::Command::new("remmina")
-                                    .args(["-c", &profile])
-                                    .spawn();
-                                if native.is_err() {
-                                    if let Err(e) = std::process::Command::new("flatpak")
-                                        .args([
-                                            "run",
-                                            "org.remmina.Remmina",
-                                            "-c",
-                                            &profile,
-                                        ])
-                                        .spawn()
-                                    {
-                                        eprintln!("Failed to launch remmina: {e}");
-                                    }
-                                }
-                            });
-                        }
-                        CredService::Vnc => {
-                            std::thread::spawn(move || {
-                                if vnc_type == VncType::RealVnc {
-                                    if let Err(e) = std::process::Command::new("vncviewer")
-                                        .arg(&dns_name)
-                                        .spawn()
-                                    {
-                                        eprintln!("Failed to launch vncviewer: {e}");
-                                    }
+                                    scoped.ssh_usernames.insert(peer.hostname.clone(), dialog.username.clone());
                                 } else {
-                                    let target = if username.is_empty() {
-                                        format!("vnc://{dns_name}")
-                                    } else {
-                                        format!("vnc://{username}@{dns_name}")
-                                    };
-                                    let native =
-                                        std::process::Command::new("remmina").arg(&target).spawn();
-                                    if native.is_err() {
-                                        if let Err(e) = std::process::Command::new("flatpak")
-                                            .args([
-                                                "run",
-                                                "org.remmina.Remmina",
-                                                &target,
-                                            ])
-                                            .spawn()
-                                        {
-                                            eprintln!("Failed to launch remmina: {e}");
-                                        }
-                                    }
-                                }
-                            });
-                        }
-                        CredService::NoMachine => {
-                            std::thread::spawn(move || {
-                                let nx_dir = dirs::home_dir()
-                                    .unwrap_or_else(|| std::path::PathBuf::from("/tmp"))
-                                    .join(".nx");
-                                let _ = std::fs::create_dir_all(&nx_dir);
-                                let nxs_file = nx_dir
-                                    .join(format!(
-                                        "tailscale-nx-{}.nxs",
-                                        dns_name.replace(|c: char| !c.is_alphanumeric(), "_")
-                                    ))
-                                    .to_string_lossy()
-                                    .to_string();
-                                // Only create the file if it doesn't exist yet;
-                                // reuse any existing file so user customisations persist.
-                                if !std::path::Path::new(&nxs_file).exists() {
-                                    let nxs_content = format!("\
-<!DOCTYPE NXClientSettings>
-<NXClientSettings version=\"2.3\" application=\"nxclient\" >
- <group name=\"General\" >
-  <option key=\"Connection service\" value=\"nx\" />
-  <option key=\"NoMachine daemon port\" value=\"4000\" />
- </group>
- <group name=\"Local Settings\" >
-  <option key=\"Server name\" value=\"{dns_name}\" />
-  <option key=\"List of hosts\" value=\"{ip}\" />
-  <option key=\"List of ports\" value=\"4000\" />
-  <option key=\"List of protocols\" value=\"nx\" />
- </group>
- <group name=\"Login\" >
-  <option key=\"Server authentication method\" value=\"system\" />
-  <option key=\"System login method\" value=\"password\" />
-  <option key=\"User\" value=\"{username}\" />
- </group>
-</NXClientSettings>
-");
-                                    if let Err(e) = std::fs::write(&nxs_file, &nxs_content) {
-                                        eprintln!("Failed to write .nxs file: {e}");
-                                        return;
-                                    }
-                                }
-                                let native = std::process::Command::new("nxplayer")
-                                    .args(["--session", &nxs_file])
-                                    .spawn();
-                                if native.is_err() {
-                                    if let Err(e) = std::process::Command::new("flatpak")
-                                        .args([
-                                            "run",
-                                            "--nosocket=wayland",
-                                            "com.nomachine.nxplayer",
-                                            "--session",
-                                            &nxs_file,
-                                        ])
-                                        .spawn()
-                                    {
-                                        eprintln!("Failed to launch nxplayer: {e}");
-                                    }
+                                    scoped.ssh_usernames.remove(&peer.hostname);
                                 }
                             });
                         }
                     }
                 }
+
+                launch_service(&def, dialog.target, dialog.username);
             }
 
             Message::CredCancel => {
@@ -603,10 +860,12 @@ disableautoreconnect=0
             }
 
             Message::OpenAdminConsole => {
-                std::thread::spawn(|| {
-                    let _ = std::process::Command::new("xdg-open")
-                        .arg("https://login.tailscale.com/admin/machines")
-                        .spawn();
+                return self.update(Message::OpenUrl(ADMIN_CONSOLE_URL.to_string()));
+            }
+
+            Message::OpenUrl(url) => {
+                std::thread::spawn(move || {
+                    let _ = std::process::Command::new("xdg-open").arg(&url).spawn();
                 });
             }
         }
@@ -615,12 +874,27 @@ disableautoreconnect=0
     }
 
     fn subscription(&self) -> cosmic::iced::Subscription<Self::Message> {
-        cosmic::iced::time::every(std::time::Duration::from_secs(3))
-            .map(|_| Message::PollStatus)
+        struct Worker;
+
+        cosmic::iced::Subscription::batch([
+            cosmic::iced::time::every(Duration::from_secs(3)).map(|_| Message::Tick),
+            cosmic::iced::Subscription::run_with_id(
+                std::any::TypeId::of::<Worker>(),
+                worker(
+                    self.backend.clone(),
+                    self.worker_services.clone(),
+                    self.worker_probing.clone(),
+                ),
+            ),
+        ])
     }
 
     fn view(&self) -> Element<'_, Message> {
-        let icon_name = if self.connected {
+        let icon_name = if self.connected && self.key_expiring() {
+            "io.github.reality2_roycdavies.cosmic-tailscale-warning-symbolic"
+        } else if self.connected && !self.waiting_files.is_empty() {
+            "io.github.reality2_roycdavies.cosmic-tailscale-inbox-symbolic"
+        } else if self.connected {
             "io.github.reality2_roycdavies.cosmic-tailscale-connected-symbolic"
         } else {
             "io.github.reality2_roycdavies.cosmic-tailscale-disconnected-symbolic"
@@ -697,20 +971,228 @@ disableautoreconnect=0
 }
 
 impl TailscaleApplet {
-    fn popup_content(&self) -> widget::Column<'_, Message> {
-        use cosmic::iced::widget::{column, container, horizontal_space, row, Space};
-        use cosmic::iced::{Alignment, Color};
+    /// Build the applet against `backend` with `config`. The background
+    /// worker starts with the applet's subscription.
+    pub(crate) fn new(core: Core, backend: Arc<dyn TailscaleBackend>, config: AppConfig) -> Self {
+        let initial = backend.status();
 
-        let divider = || {
-            container(Space::new(Length::Fill, Length::Fixed(1.0))).style(
-                |theme: &cosmic::Theme| {
-                    let cosmic = theme.cosmic();
-                    container::Style {
-                        background: Some(cosmic::iced::Background::Color(Color::from(
-                            cosmic.palette.neutral_5,
-                        ))),
-                        ..Default::default()
-                    }
+        let mut applet = Self {
+            core,
+            popup: None,
+            page: Page::Main,
+            backend_state: BackendState::NoState,
+            connected: false,
+            is_toggling: false,
+            status_hold_ticks: 0,
+            status_message: String::new(),
+            self_hostname: String::new(),
+            self_ip: String::new(),
+            self_dns_name: String::new(),
+            self_https_url: String::new(),
+            serve_endpoints: Vec::new(),
+            file_targets: Vec::new(),
+            transfers: Vec::new(),
+            next_transfer_id: 0,
+            waiting_files: Vec::new(),
+            saving: HashSet::new(),
+            auto_saved: HashSet::new(),
+            self_relay: String::new(),
+            self_user_id: 0,
+            self_key_expiry: None,
+            version: String::new(),
+            tailnet_name: String::new(),
+            login_name: String::new(),
+            profiles: vec![],
+            account_menu_open: false,
+            peers: vec![],
+            peer_filter: PeerFilter::default(),
+            ping: None,
+            peer_drives: None,
+            netcheck: None,
+            netcheck_running: false,
+            exit_node_active: false,
+            exit_node_name: String::new(),
+            exit_node_allow_lan: false,
+            exit_node_menu_open: false,
+            exit_node_suggestion: None,
+            expanded_country: None,
+            error: None,
+            prev_status: None,
+            prev_status_at: 0,
+            user_state_change: None,
+            command_error: None,
+            auth_url: None,
+            auth_qr: None,
+            copied_text: None,
+            copied_hold_ticks: 0,
+            worker_services: config.services.clone(),
+            worker_probing: config.probing.clone(),
+            config,
+            cred_dialog: None,
+            backend,
+            cmd_tx: None,
+        };
+
+        match initial {
+            Ok(status) => applet.apply_status(status),
+            Err(e) => applet.error = Some(e),
+        }
+        applet.status_message = applet.state_message();
+        applet
+    }
+
+    /// Give the worker the service registry and probe settings if they
+    /// changed, as that makes it probe every peer again.
+    fn sync_probe_config(&mut self) {
+        if self.worker_services == self.config.services
+            && self.worker_probing == self.config.probing
+        {
+            return;
+        }
+        let Some(ref cmd_tx) = self.cmd_tx else {
+            return;
+        };
+        self.worker_services = self.config.services.clone();
+        self.worker_probing = self.config.probing.clone();
+        let _ = cmd_tx.send(TailscaleCommand::SetProbeConfig(
+            self.worker_services.clone(),
+            self.worker_probing.clone(),
+        ));
+    }
+
+    /// Whether our own node key expires within the configured warning period.
+    fn key_expiring(&self) -> bool {
+        let threshold = i64::from(self.config.key_expiry_warning_days) * 86_400;
+        self.self_key_expiry
+            .is_some_and(|expiry| expiry - unix_now() < threshold)
+    }
+
+    /// Diff `status` against the previous snapshot and hand any events worth
+    /// a notification to the worker.
+    fn notify_changes(&mut self, status: &TailscaleStatus) {
+        let now = unix_now();
+        let favorites = self
+            .config
+            .tailnets
+            .get(&status.tailnet_name)
+            .map(|t| t.favorites.clone())
+            .unwrap_or_default();
+        let ctx = EventContext {
+            settings: &self.config.notifications,
+            favorites: &favorites,
+            key_warning_secs: i64::from(self.config.key_expiry_warning_days) * 86_400,
+            prev_at: self.prev_status_at,
+            now,
+        };
+        let mut events = notifications::detect_events(self.prev_status.as_ref(), status, &ctx);
+
+        // Don't announce a connect or disconnect the user just asked for
+        let user_initiated = self
+            .user_state_change
+            .is_some_and(|at| at.elapsed() < USER_CHANGE_GRACE);
+        if user_initiated {
+            events.retain(|e| !matches!(e, NotifyEvent::TunnelDown | NotifyEvent::TunnelUp));
+        }
+
+        self.prev_status = Some(status.clone());
+        self.prev_status_at = now;
+
+        if !events.is_empty() {
+            if let Some(ref cmd_tx) = self.cmd_tx {
+                let _ = cmd_tx.send(TailscaleCommand::Notify(events));
+            }
+        }
+    }
+
+    fn apply_status(&mut self, status: TailscaleStatus) {
+        self.backend_state = status.backend_state;
+        self.connected = status.backend_state == BackendState::Running;
+        if self.connected {
+            self.set_auth_url(None);
+        } else if !status.auth_url.is_empty() {
+            self.set_auth_url(Some(status.auth_url.clone()));
+        }
+        self.self_hostname = status.self_node.display_name().to_string();
+        self.self_ip = status
+            .self_node
+            .tailscale_ips
+            .first()
+            .cloned()
+            .unwrap_or_default();
+        self.self_dns_name = status.self_node.dns_display();
+        self.self_https_url = status.self_node.https_url(&status.cert_domains);
+        self.self_relay = status.self_node.relay.clone();
+        self.self_user_id = status.self_node.user_id;
+        self.self_key_expiry = status.self_node.key_expiry;
+        self.version = status.version.clone();
+        if !status.tailnet_name.is_empty() && status.tailnet_name != self.tailnet_name {
+            self.config.adopt_legacy(&status.tailnet_name);
+        }
+        self.tailnet_name = status.tailnet_name;
+        self.peers = status.peers;
+        self.exit_node_active = status.exit_node_active;
+        self.exit_node_name = status.exit_node_name;
+        self.error = None;
+    }
+
+    fn set_auth_url(&mut self, url: Option<String>) {
+        if url == self.auth_url {
+            return;
+        }
+        self.auth_qr = url.as_ref().and_then(|u| qr_code::Data::new(u).ok());
+        self.auth_url = url;
+    }
+
+    /// Status line for the current state when no transient message is shown.
+    fn state_message(&self) -> String {
+        match self.error {
+            Some(ref e) => e.to_string(),
+            None => self.backend_state.label().to_string(),
+        }
+    }
+
+    /// Save a received file to the configured folder, unless already saving it.
+    fn save_file(&mut self, name: String) {
+        if !self.saving.insert(name.clone()) {
+            return;
+        }
+        if let Some(ref cmd_tx) = self.cmd_tx {
+            let dir = self.config.taildrop.save_dir();
+            let _ = cmd_tx.send(TailscaleCommand::SaveFile(name, dir));
+        }
+    }
+
+    /// Record a command result, keeping errors the user has to fix on screen.
+    fn apply_command_result(&mut self, result: Result<String, TailscaleError>) {
+        self.status_hold_ticks = 3;
+        match result {
+            Ok(msg) => {
+                self.command_error = None;
+                self.status_message = msg;
+            }
+            Err(e) => {
+                self.status_message = format!("Error: {e}");
+                if let TailscaleError::PermissionDenied(_) = e {
+                    self.command_error = Some(e);
+                }
+            }
+        }
+    }
+
+    fn popup_content(&self) -> widget::Column<'_, Message> {
+        use cosmic::iced::widget::{column, container, horizontal_space, row, Space};
+        use cosmic::iced::{Alignment, Color};
+
+        let divider = || {
+            container(Space::new(Length::Fill, Length::Fixed(1.0))).style(
+                |theme: &cosmic::Theme| {
+                    let cosmic = theme.cosmic();
+                    container::Style {
+                        background: Some(cosmic::iced::Background::Color(Color::from(
+                            cosmic.palette.neutral_5,
+                        ))),
+                        ..Default::default()
+                    }
                 },
             )
         };
@@ -720,6 +1202,13 @@ impl TailscaleApplet {
             return self.cred_dialog_view(dialog);
         }
 
+        match self.page {
+            Page::Main => {}
+            Page::ExitNodeLocations => return self.exit_node_locations_view(),
+            Page::PeerDetail(ref id) => return self.peer_detail_view(id),
+            Page::Netcheck => return self.netcheck_view(),
+        }
+
         // Title row
         let title_row = row![
             text::body("Tailscale"),
@@ -777,6 +1266,53 @@ impl TailscaleApplet {
                         .class(cosmic::theme::Button::MenuItem),
                 );
             }
+            // Public (Funnel) URLs, then tailnet-only Serve mappings (clickable to copy)
+            let public = self.serve_endpoints.iter().filter(|e| e.funnel);
+            let private = self.serve_endpoints.iter().filter(|e| !e.funnel);
+            for endpoint in public.chain(private) {
+                let kind = if endpoint.funnel { "Public" } else { "Serve" };
+                let is_copied = self.copied_text.as_deref() == Some(&endpoint.url);
+                let label = if is_copied {
+                    format!("{kind}: Copied!")
+                } else {
+                    format!("{kind}: {} → {}", endpoint.url, endpoint.target)
+                };
+                info_col = info_col.push(
+                    widget::button::custom(text::caption(label))
+                        .on_press(Message::CopyToClipboard(endpoint.url.clone()))
+                        .padding([0, 0])
+                        .class(cosmic::theme::Button::MenuItem),
+                );
+            }
+            // Why peers' Serve endpoints are missing, once rather than per peer
+            if let Some(e) = self.peers.iter().find_map(|p| p.services.served_error.as_deref()) {
+                info_col = info_col.push(text::caption(format!("Serve: {e}")));
+            }
+            // Key expiry
+            if let Some(expiry) = self.self_key_expiry {
+                let now = unix_now();
+                let label = if expiry <= now {
+                    "Key expired".to_string()
+                } else {
+                    format!("Key expires {}", format_relative(expiry, now))
+                };
+                if self.key_expiring() {
+                    info_col = info_col.push(
+                        row![
+                            widget::icon::from_name("dialog-warning-symbolic")
+                                .size(16)
+                                .symbolic(true),
+                            text::caption(label),
+                            horizontal_space(),
+                            widget::button::standard("Re-authenticate").on_press(Message::Login),
+                        ]
+                        .spacing(4)
+                        .align_y(Alignment::Center),
+                    );
+                } else {
+                    info_col = info_col.push(text::caption(label));
+                }
+            }
             // Relay
             if !self.self_relay.is_empty() {
                 info_col =
@@ -801,11 +1337,11 @@ impl TailscaleApplet {
             }
         }
 
-        if let Some(ref err) = self.error {
-            info_col = info_col.push(text::caption(format!("Error: {err}")));
+        if let Some(hint) = self.error_hint() {
+            info_col = info_col.push(hint);
         }
 
-        // Toggle button
+        // Toggle button, or whatever the current state needs instead
         let toggle_btn: Element<Message> = if self.is_toggling {
             widget::button::standard(if self.connected {
                 "Disconnecting..."
@@ -813,54 +1349,891 @@ impl TailscaleApplet {
                 "Connecting..."
             })
             .into()
-        } else if self.connected {
-            widget::button::destructive("Disconnect")
-                .on_press(Message::ToggleConnection)
+        } else if let Some(ref err) = self.error {
+            match err {
+                TailscaleError::NotInstalled => widget::button::suggested("Install")
+                    .on_press(Message::OpenUrl(INSTALL_URL.to_string()))
+                    .into(),
+                _ => widget::button::standard("Connect").into(),
+            }
+        } else {
+            match self.backend_state {
+                BackendState::Running => widget::button::destructive("Disconnect")
+                    .on_press(Message::ToggleConnection)
+                    .into(),
+                BackendState::Stopped => widget::button::suggested("Connect")
+                    .on_press(Message::ToggleConnection)
+                    .into(),
+                BackendState::NeedsLogin => widget::button::suggested("Log in")
+                    .on_press(Message::Login)
+                    .into(),
+                BackendState::NeedsMachineAuth => widget::button::standard("Admin Console")
+                    .on_press(Message::OpenAdminConsole)
+                    .into(),
+                BackendState::Starting | BackendState::NoState => {
+                    widget::button::standard("Connecting...").into()
+                }
+            }
+        };
+
+        let toggle_row = row![text::body("VPN"), horizontal_space(), toggle_btn,]
+            .spacing(8)
+            .align_y(Alignment::Center);
+
+        // Peers section
+        let online_count = self.peers.iter().filter(|p| p.online).count();
+        let total_count = self.peers.len();
+        let peers_header = row![
+            text::body(format!("Peers ({online_count}/{total_count} online)")),
+            horizontal_space(),
+            Self::icon_btn("view-refresh-symbolic", true, Message::RefreshServices),
+        ]
+        .align_y(Alignment::Center);
+
+        let peers_col = column![peers_header, self.peer_filter_bar(), self.peer_list()].spacing(4);
+
+        // Bottom actions row
+        let actions_row = row![
+            widget::button::standard("Admin Console").on_press(Message::OpenAdminConsole),
+            horizontal_space(),
+            widget::button::standard("Diagnostics").on_press(Message::ShowPage(Page::Netcheck)),
+            widget::button::standard("Settings...").on_press(Message::OpenSettings),
+        ]
+        .spacing(8)
+        .align_y(Alignment::Center);
+
+        // Assemble
+        let mut content = column![title_row].spacing(8).padding(12);
+
+        if !self.login_name.is_empty() || !self.profiles.is_empty() {
+            content = content.push(self.account_section());
+        }
+
+        content = content
+            .push(divider())
+            .push(info_col)
+            .push(divider())
+            .push(toggle_row);
+
+        if self.connected && self.peers.iter().any(|p| p.exit_node_option) {
+            content = content.push(divider()).push(self.exit_node_section());
+        }
+
+        if self.connected && !self.waiting_files.is_empty() {
+            content = content.push(divider()).push(self.inbox_section());
+        }
+
+        if !self.transfers.is_empty() {
+            content = content.push(divider()).push(self.transfers_section());
+        }
+
+        if self.connected && !self.peers.is_empty() {
+            content = content.push(divider()).push(peers_col);
+        }
+
+        content = content.push(divider()).push(actions_row);
+
+        content
+    }
+
+    /// Files received over Taildrop that are waiting to be saved.
+    fn inbox_section(&self) -> Element<'_, Message> {
+        use cosmic::iced::widget::{column, horizontal_space, row};
+        use cosmic::iced::Alignment;
+
+        let dir = self.config.taildrop.save_dir();
+        let mut section = column![
+            text::body("Received files"),
+            text::caption(format!("Saved to {}", dir.display())),
+        ]
+        .spacing(2);
+        for file in &self.waiting_files {
+            let saving = self.saving.contains(&file.name);
+            section = section.push(
+                row![
+                    text::caption(format!("{} ({})", file.name, format_bytes(file.size))),
+                    horizontal_space(),
+                    widget::button::standard(if saving { "Saving..." } else { "Save" })
+                        .on_press_maybe((!saving).then(|| Message::SaveFile(file.name.clone()))),
+                    widget::button::standard("Discard")
+                        .on_press_maybe((!saving).then(|| Message::DiscardFile(file.name.clone()))),
+                ]
+                .spacing(4)
+                .align_y(Alignment::Center),
+            );
+        }
+        section.into()
+    }
+
+    /// Files being sent over Taildrop, with progress or how they ended.
+    fn transfers_section(&self) -> Element<'_, Message> {
+        use cosmic::iced::widget::{column, horizontal_space, row};
+        use cosmic::iced::Alignment;
+
+        let finished = self.transfers.iter().any(|t| t.result.is_some());
+        let header = row![
+            text::body("Sending files"),
+            horizontal_space(),
+            widget::button::standard("Clear")
+                .on_press_maybe(finished.then_some(Message::ClearTransfers)),
+        ]
+        .spacing(8)
+        .align_y(Alignment::Center);
+
+        let mut section = column![header].spacing(2);
+        for t in &self.transfers {
+            let state = match t.result {
+                None if t.size > 0 => format!(
+                    "{}%, {} of {}",
+                    t.sent * 100 / t.size,
+                    format_bytes(t.sent),
+                    format_bytes(t.size)
+                ),
+                None => "Sending...".to_string(),
+                Some(Ok(())) => "Sent".to_string(),
+                Some(Err(ref e)) => format!("Failed: {e}"),
+            };
+            section = section.push(text::caption(format!(
+                "{} → {}: {state}",
+                t.file_name, t.peer_name
+            )));
+        }
+        section.into()
+    }
+
+    /// Explanation and fix for an error or state the user has to resolve.
+    fn error_hint(&self) -> Option<Element<'_, Message>> {
+        use cosmic::iced::widget::column;
+
+        let err = self.error.as_ref().or(self.command_error.as_ref());
+        let hint: Element<Message> = match err {
+            Some(TailscaleError::NotInstalled) => {
+                text::caption("Install Tailscale to use this applet.").into()
+            }
+            Some(TailscaleError::DaemonNotRunning) => {
+                self.copy_command_row("Start it with", START_DAEMON_CMD.to_string())
+            }
+            Some(TailscaleError::PermissionDenied(_)) => {
+                let user = std::env::var("USER").unwrap_or_else(|_| "$USER".to_string());
+                column![
+                    text::caption("Changing settings needs operator rights."),
+                    self.copy_command_row("Run", format!("sudo tailscale set --operator={user}")),
+                ]
+                .spacing(2)
+                .into()
+            }
+            Some(TailscaleError::Parse(_)) | Some(TailscaleError::CommandFailed(_)) => {
+                return None;
+            }
+            None => match self.backend_state {
+                BackendState::NeedsLogin => match self.login_view() {
+                    Some(view) => view,
+                    None => text::caption("Log in to join your tailnet.").into(),
+                },
+                BackendState::NeedsMachineAuth => {
+                    text::caption("An admin must approve this device in the Admin Console.")
+                        .into()
+                }
+                _ => return None,
+            },
+        };
+        Some(hint)
+    }
+
+    /// Auth URL of a pending login with open/copy buttons and a QR code for
+    /// logging in from a phone.
+    fn login_view(&self) -> Option<Element<'_, Message>> {
+        use cosmic::iced::widget::{column, container, horizontal_space, row};
+        use cosmic::iced::Alignment;
+
+        let url = self.auth_url.as_ref()?;
+        let is_copied = self.copied_text.as_deref() == Some(url.as_str());
+
+        let mut col = column![
+            text::caption("Visit this URL to log in, or scan the code with your phone:"),
+            text::caption(url.clone()),
+            row![
+                widget::button::suggested("Open in browser")
+                    .on_press(Message::OpenUrl(url.clone())),
+                horizontal_space(),
+                widget::button::standard(if is_copied { "Copied!" } else { "Copy" })
+                    .on_press(Message::CopyToClipboard(url.clone())),
+            ]
+            .spacing(8)
+            .align_y(Alignment::Center),
+        ]
+        .spacing(6);
+
+        if let Some(ref data) = self.auth_qr {
+            col = col.push(
+                container(qr_code(data).cell_size(4)).center_x(Length::Fill),
+            );
+        }
+
+        Some(col.into())
+    }
+
+    /// A shell command to run, with a button copying it to the clipboard.
+    fn copy_command_row(&self, label: &str, cmd: String) -> Element<'_, Message> {
+        use cosmic::iced::widget::row;
+        use cosmic::iced::Alignment;
+
+        let is_copied = self.copied_text.as_deref() == Some(cmd.as_str());
+        row![
+            text::caption(format!("{label} `{cmd}`")).width(Length::Fill),
+            widget::button::standard(if is_copied { "Copied!" } else { "Copy" })
+                .on_press(Message::CopyToClipboard(cmd)),
+        ]
+        .spacing(8)
+        .align_y(Alignment::Center)
+        .into()
+    }
+
+    /// Current account, expanding to switch, add or log out of accounts.
+    fn account_section(&self) -> Element<'_, Message> {
+        use cosmic::iced::widget::{column, horizontal_space, row};
+        use cosmic::iced::Alignment;
+
+        let current = if self.login_name.is_empty() {
+            "Not logged in".to_string()
+        } else {
+            self.login_name.clone()
+        };
+        let arrow = if self.account_menu_open {
+            "go-up-symbolic"
+        } else {
+            "go-down-symbolic"
+        };
+
+        let header = widget::button::custom(
+            row![
+                widget::icon::from_name("avatar-default-symbolic")
+                    .symbolic(true)
+                    .size(16),
+                text::caption(current),
+                horizontal_space(),
+                widget::icon::from_name(arrow).symbolic(true).size(16),
+            ]
+            .spacing(8)
+            .align_y(Alignment::Center),
+        )
+        .on_press(Message::ToggleAccountMenu)
+        .padding([4, 0])
+        .class(cosmic::theme::Button::MenuItem)
+        .width(Length::Fill);
+
+        let mut section = column![header].spacing(2);
+        if !self.account_menu_open {
+            return section.into();
+        }
+
+        for profile in &self.profiles {
+            let label = if profile.tailnet.is_empty() {
+                profile.account.clone()
+            } else {
+                format!("{} ({})", profile.account, profile.tailnet)
+            };
+            let switch = (!profile.current).then(|| Message::SwitchProfile(profile.id.clone()));
+            section = section.push(Self::menu_choice(label, profile.current, switch));
+        }
+
+        section = section.push(
+            row![
+                widget::button::standard("Add account").on_press(Message::AddProfile),
+                horizontal_space(),
+                widget::button::destructive("Log out").on_press_maybe(
+                    (!self.login_name.is_empty()).then_some(Message::Logout)
+                ),
+            ]
+            .spacing(8)
+            .align_y(Alignment::Center),
+        );
+
+        section.into()
+    }
+
+    fn exit_node_section(&self) -> Element<'_, Message> {
+        use cosmic::iced::widget::{column, horizontal_space, row};
+        use cosmic::iced::Alignment;
+
+        let current = if self.exit_node_active && !self.exit_node_name.is_empty() {
+            self.exit_node_name.clone()
+        } else if self.exit_node_active {
+            "Active".to_string()
+        } else {
+            "None".to_string()
+        };
+        let arrow = if self.exit_node_menu_open {
+            "go-up-symbolic"
+        } else {
+            "go-down-symbolic"
+        };
+
+        let header = widget::button::custom(
+            row![
+                text::body("Exit node"),
+                horizontal_space(),
+                text::caption(current),
+                widget::icon::from_name(arrow).symbolic(true).size(16),
+            ]
+            .spacing(8)
+            .align_y(Alignment::Center),
+        )
+        .on_press(Message::ToggleExitNodeMenu)
+        .padding([4, 0])
+        .class(cosmic::theme::Button::MenuItem)
+        .width(Length::Fill);
+
+        let mut section = column![header].spacing(2);
+        if !self.exit_node_menu_open {
+            return section.into();
+        }
+
+        // Recommended node, one click away
+        if let Some(suggested) = self.suggested_exit_node() {
+            let use_btn: Element<Message> = if suggested.exit_node {
+                text::caption("In use").into()
+            } else {
+                widget::button::suggested("Use")
+                    .on_press(Message::SetExitNode(Some(suggested.id.clone())))
+                    .into()
+            };
+            section = section.push(
+                row![
+                    widget::icon::from_name("starred-symbolic")
+                        .symbolic(true)
+                        .size(16),
+                    text::caption(format!("Recommended: {}", Self::exit_node_label(suggested))),
+                    horizontal_space(),
+                    use_btn,
+                ]
+                .spacing(8)
+                .padding([4, 8])
+                .align_y(Alignment::Center),
+            );
+        }
+
+        section = section.push(Self::menu_choice(
+            "None".to_string(),
+            !self.exit_node_active,
+            Some(Message::SetExitNode(None)),
+        ));
+
+        // The tailnet's own exit nodes; location-based ones are on their own page
+        for peer in self
+            .peers
+            .iter()
+            .filter(|p| p.exit_node_option && p.location.is_none())
+        {
+            let (label, msg) = if peer.online {
+                (
+                    peer.display_name().to_string(),
+                    Some(Message::SetExitNode(Some(peer.id.clone()))),
+                )
+            } else {
+                (format!("{} (offline)", peer.display_name()), None)
+            };
+            section = section.push(Self::menu_choice(label, peer.exit_node, msg));
+        }
+
+        if self.peers.iter().any(|p| p.exit_node_option && p.location.is_some()) {
+            let in_use = self
+                .peers
+                .iter()
+                .find(|p| p.exit_node && p.location.is_some())
+                .map(Self::exit_node_label);
+            let label = match in_use {
+                Some(name) => format!("Locations... ({name})"),
+                None => "Locations...".to_string(),
+            };
+            section = section.push(
+                widget::button::custom(text::caption(label))
+                    .on_press(Message::ShowPage(Page::ExitNodeLocations))
+                    .padding([4, 8])
+                    .class(cosmic::theme::Button::MenuItem)
+                    .width(Length::Fill),
+            );
+        }
+
+        let allow_lan: Element<Message> = widget::toggler(self.exit_node_allow_lan)
+            .on_toggle(Message::SetExitNodeAllowLan)
+            .into();
+        section = section.push(
+            row![text::caption("Allow LAN access"), horizontal_space(), allow_lan]
+                .spacing(8)
+                .padding([4, 8])
+                .align_y(Alignment::Center),
+        );
+
+        section.into()
+    }
+
+    fn suggested_exit_node(&self) -> Option<&PeerInfo> {
+        let suggestion = self.exit_node_suggestion.as_ref()?;
+        self.peers.iter().find(|p| suggestion.matches(p))
+    }
+
+    /// "City, Country" for location-based exit nodes, otherwise the peer name.
+    fn exit_node_label(peer: &PeerInfo) -> String {
+        match peer.location {
+            Some(ref loc) if !loc.city.is_empty() => format!("{}, {}", loc.city, loc.country),
+            _ => peer.display_name().to_string(),
+        }
+    }
+
+    fn exit_node_locations_view(&self) -> widget::Column<'_, Message> {
+        use cosmic::iced::widget::{column, horizontal_space, row};
+        use cosmic::iced::Alignment;
+
+        let header = row![
+            Self::icon_btn(
+                "go-previous-symbolic",
+                true,
+                Message::ShowPage(Page::Main)
+            ),
+            text::body("Exit node locations"),
+        ]
+        .spacing(8)
+        .align_y(Alignment::Center);
+
+        let suggested = self.suggested_exit_node().map(|p| p.id.as_str());
+        let mut list = column![].spacing(2);
+
+        for country in tailscale::group_exit_node_locations(&self.peers) {
+            let expanded = self.expanded_country.as_deref() == Some(country.name.as_str());
+            let in_use = country.cities.iter().any(|c| c.best.exit_node);
+            let arrow = if expanded {
+                "go-up-symbolic"
+            } else {
+                "go-down-symbolic"
+            };
+            let label = if in_use {
+                format!("{} {} (in use)", country.flag(), country.name)
+            } else {
+                format!("{} {}", country.flag(), country.name)
+            };
+
+            list = list.push(
+                widget::button::custom(
+                    row![
+                        text::body(label),
+                        horizontal_space(),
+                        text::caption(format!("{}", country.cities.len())),
+                        widget::icon::from_name(arrow).symbolic(true).size(16),
+                    ]
+                    .spacing(8)
+                    .align_y(Alignment::Center),
+                )
+                .on_press(Message::ToggleCountry(country.name.clone()))
+                .padding([4, 8])
+                .class(cosmic::theme::Button::MenuItem)
+                .width(Length::Fill),
+            );
+
+            if !expanded {
+                continue;
+            }
+
+            for city in country.cities {
+                let mut label = if city.node_count > 1 {
+                    format!("{} ({} nodes)", city.name, city.node_count)
+                } else {
+                    city.name.clone()
+                };
+                if suggested == Some(city.best.id.as_str()) {
+                    label.push_str(" - recommended");
+                }
+                list = list.push(Self::menu_choice(
+                    label,
+                    city.best.exit_node,
+                    Some(Message::SetExitNode(Some(city.best.id.clone()))),
+                ));
+            }
+        }
+
+        column![
+            header,
+            widget::scrollable(list).height(Length::Fill),
+        ]
+        .spacing(8)
+        .padding(12)
+    }
+
+    /// Everything tailscaled reports about one peer: how we reach it, traffic,
+    /// liveness and ownership.
+    fn peer_detail_view(&self, id: &str) -> widget::Column<'_, Message> {
+        use cosmic::iced::widget::{column, row};
+        use cosmic::iced::Alignment;
+
+        let peer = self.peers.iter().find(|p| p.id == id);
+        let title = peer.map_or("Peer", |p| p.display_name());
+        let header = row![
+            Self::icon_btn(
+                "go-previous-symbolic",
+                true,
+                Message::ShowPage(Page::Main)
+            ),
+            text::body(title.to_string()),
+        ]
+        .spacing(8)
+        .align_y(Alignment::Center);
+
+        let mut content = column![header].spacing(8).padding(12);
+        let Some(peer) = peer else {
+            return content.push(text::caption("This peer is no longer in the tailnet."));
+        };
+
+        let now = unix_now();
+        let mut rows: Vec<(&str, String)> = Vec::new();
+
+        let status = if peer.online {
+            "Online".to_string()
+        } else {
+            match peer.last_seen {
+                Some(ts) => format!("Offline, last seen {}", format_relative(ts, now)),
+                None => "Offline".to_string(),
+            }
+        };
+        rows.push(("Status", status));
+
+        let path = if !peer.cur_addr.is_empty() {
+            format!("Direct ({})", peer.cur_addr)
+        } else if !peer.relay.is_empty() {
+            format!("Relayed via DERP ({})", peer.relay)
+        } else {
+            "No connection".to_string()
+        };
+        rows.push(("Connection", path));
+        if !peer.relay.is_empty() {
+            rows.push(("Home relay", peer.relay.clone()));
+        }
+        rows.push((
+            "Traffic",
+            format!(
+                "↓ {}  ↑ {}",
+                format_bytes(peer.rx_bytes),
+                format_bytes(peer.tx_bytes)
+            ),
+        ));
+        if let Some(ts) = peer.last_handshake {
+            rows.push(("Last handshake", format_relative(ts, now)));
+        }
+        rows.push((
+            "Key expiry",
+            match peer.key_expiry {
+                Some(ts) => format_relative(ts, now),
+                None => "Never".to_string(),
+            },
+        ));
+
+        rows.push(("Tailscale IPs", peer.tailscale_ips.join(", ")));
+        if !peer.dns_name.is_empty() {
+            rows.push(("DNS name", peer.dns_display()));
+        }
+        rows.push(("OS", peer.os.clone()));
+        if !peer.primary_routes.is_empty() {
+            rows.push(("Subnet routes", peer.primary_routes.join(", ")));
+        }
+        if !peer.tags.is_empty() {
+            rows.push(("Tags", peer.tags.join(", ")));
+        }
+        let owner = if peer.owner.is_empty() {
+            format!("User {}", peer.user_id)
+        } else {
+            format!("{} (user {})", peer.owner, peer.user_id)
+        };
+        rows.push(("Owner", owner));
+        if peer.services.funnel {
+            rows.push(("Funnel", "On, served ports are public".to_string()));
+        }
+        for found in &peer.services.found {
+            let Some(def) = self.config.service(&found.id) else {
+                continue;
+            };
+            let about = found.details.as_deref().or(found.app.as_deref());
+            rows.push((
+                def.name.as_str(),
+                match about {
+                    Some(about) => format!("Port {} · {about}", found.port),
+                    None => format!("Port {}", found.port),
+                },
+            ));
+        }
+
+        let mut details = column![].spacing(4);
+        for (label, value) in rows {
+            details = details.push(
+                row![
+                    text::caption(label).width(Length::Fixed(110.0)),
+                    text::caption(value).width(Length::Fill),
+                ]
+                .spacing(8),
+            );
+        }
+
+        content
+            .push(details)
+            .push(self.ping_section(peer))
+            .push(self.drives_section(peer))
+    }
+
+    /// Ask the worker for the folders an online peer shares with us.
+    fn list_drives(&mut self, peer_id: &str) {
+        let Some(peer) = self.peers.iter().find(|p| p.id == peer_id && p.online) else {
+            self.peer_drives = None;
+            return;
+        };
+        if let Some(ref cmd_tx) = self.cmd_tx {
+            let _ = cmd_tx.send(TailscaleCommand::ListDrives(
+                peer.id.clone(),
+                self.tailnet_name.clone(),
+                peer.display_name().to_string(),
+            ));
+            self.peer_drives = Some(PeerDrives {
+                peer_id: peer.id.clone(),
+                drives: None,
+            });
+        }
+    }
+
+    /// Taildrive folders the peer shares with us, each opening in the file
+    /// manager.
+    fn drives_section(&self, peer: &PeerInfo) -> Element<'_, Message> {
+        use cosmic::iced::widget::{column, horizontal_space, row};
+        use cosmic::iced::Alignment;
+
+        let mut section = column![text::body("Shared folders")].spacing(2);
+        let list = self.peer_drives.as_ref().filter(|l| l.peer_id == peer.id);
+        let line = match list.map(|l| &l.drives) {
+            None => "Peer is offline".to_string(),
+            Some(None) => "Looking for shared folders...".to_string(),
+            Some(Some(Err(e))) => format!("Error: {e}"),
+            Some(Some(Ok(drives))) if drives.is_empty() => "None shared with you".to_string(),
+            Some(Some(Ok(drives))) => {
+                for name in drives {
+                    let url = tailscale::drive_url(&self.tailnet_name, peer.display_name(), name);
+                    section = section.push(
+                        row![
+                            text::caption(name.clone()),
+                            horizontal_space(),
+                            widget::button::standard("Open").on_press(Message::OpenUrl(url)),
+                        ]
+                        .spacing(8)
+                        .align_y(Alignment::Center),
+                    );
+                }
+                return section.into();
+            }
+        };
+        section.push(text::caption(line)).into()
+    }
+
+    /// Ping button and the replies so far, noting when the path goes direct.
+    fn ping_section(&self, peer: &PeerInfo) -> Element<'_, Message> {
+        use cosmic::iced::widget::{column, horizontal_space, row};
+        use cosmic::iced::Alignment;
+
+        let run = self.ping.as_ref().filter(|r| r.peer_id == peer.id);
+        let running = run.is_some_and(|r| r.running);
+        let ping_btn = widget::button::standard(if running { "Pinging..." } else { "Ping" })
+            .on_press_maybe((peer.online && !running).then(|| Message::Ping(peer.id.clone())));
+
+        let mut section = column![row![text::body("Ping"), horizontal_space(), ping_btn]
+            .spacing(8)
+            .align_y(Alignment::Center)]
+        .spacing(2);
+
+        let Some(run) = run else {
+            return section.into();
+        };
+
+        let mut relayed = false;
+        for pong in &run.pongs {
+            let line = match pong.path {
+                PingPath::Derp(ref region) => {
+                    relayed = true;
+                    format!("{:.0} ms via DERP ({region})", pong.latency_ms)
+                }
+                PingPath::Direct(ref endpoint) => {
+                    format!("{:.0} ms direct ({endpoint})", pong.latency_ms)
+                }
+            };
+            section = section.push(text::caption(line));
+            if relayed && matches!(pong.path, PingPath::Direct(_)) {
+                section = section.push(text::caption("Path upgraded to direct"));
+                relayed = false;
+            }
+        }
+
+        if let Some(ref err) = run.error {
+            section = section.push(text::caption(format!("Error: {err}")));
+        } else if !run.running && relayed {
+            section = section.push(text::caption("No direct path; traffic is relayed"));
+        }
+
+        section.into()
+    }
+
+    /// Netcheck results: what the network allows and DERP latencies, with a
+    /// button copying the raw report for support tickets.
+    fn netcheck_view(&self) -> widget::Column<'_, Message> {
+        use cosmic::iced::widget::{column, container, horizontal_space, row};
+        use cosmic::iced::Alignment;
+
+        let header = row![
+            Self::icon_btn(
+                "go-previous-symbolic",
+                true,
+                Message::ShowPage(Page::Main)
+            ),
+            text::body("Diagnostics"),
+            horizontal_space(),
+            widget::button::standard(if self.netcheck_running {
+                "Running..."
+            } else {
+                "Run again"
+            })
+            .on_press_maybe((!self.netcheck_running).then_some(Message::RunNetcheck)),
+        ]
+        .spacing(8)
+        .align_y(Alignment::Center);
+
+        let mut content = column![header].spacing(8).padding(12);
+
+        let report = match self.netcheck {
+            Some(Ok(ref report)) => report,
+            Some(Err(ref e)) => return content.push(text::caption(format!("Error: {e}"))),
+            None => return content.push(text::caption("Checking the network...")),
+        };
+
+        let mut summary = column![].spacing(4);
+        for (label, value) in report.summary() {
+            summary = summary.push(
+                row![
+                    text::caption(label).width(Length::Fixed(110.0)),
+                    text::caption(value).width(Length::Fill),
+                ]
+                .spacing(8),
+            );
+        }
+
+        let preferred = report.preferred_derp.as_ref().map(|r| r.id);
+        let mut regions = column![].spacing(2);
+        for (region, latency) in &report.region_latency {
+            let name = if region.code.is_empty() {
+                region.name.clone()
+            } else {
+                format!("{} ({})", region.name, region.code)
+            };
+            let latency = if preferred == Some(region.id) {
+                format!("{latency:.1} ms *")
+            } else {
+                format!("{latency:.1} ms")
+            };
+            regions = regions.push(row![
+                text::caption(name).width(Length::Fill),
+                text::caption(latency),
+            ]);
+        }
+
+        let copied = self.copied_text.as_deref() == Some(report.json.as_str());
+        content
+            .push(summary)
+            .push(text::body("DERP latency"))
+            .push(container(widget::scrollable(regions)).max_height(200.0))
+            .push(
+                widget::button::standard(if copied { "Copied!" } else { "Copy JSON" })
+                    .on_press(Message::CopyToClipboard(report.json.clone())),
+            )
+    }
+
+    /// One selectable row in a popup menu; `msg` is `None` when disabled.
+    fn menu_choice(
+        label: String,
+        selected: bool,
+        msg: Option<Message>,
+    ) -> Element<'static, Message> {
+        use cosmic::iced::widget::row;
+        use cosmic::iced::Alignment;
+
+        let mark: Element<Message> = if selected {
+            widget::icon::from_name("object-select-symbolic")
+                .symbolic(true)
+                .size(16)
                 .into()
         } else {
-            widget::button::suggested("Connect")
-                .on_press(Message::ToggleConnection)
-                .into()
+            cosmic::iced::widget::Space::with_width(Length::Fixed(16.0)).into()
         };
 
-        let toggle_row = row![text::body("VPN"), horizontal_space(), toggle_btn,]
-            .spacing(8)
-            .align_y(Alignment::Center);
+        widget::button::custom(
+            row![mark, text::caption(label)]
+                .spacing(8)
+                .align_y(Alignment::Center),
+        )
+        .on_press_maybe(msg)
+        .padding([4, 8])
+        .class(cosmic::theme::Button::MenuItem)
+        .width(Length::Fill)
+        .into()
+    }
 
-        // Peers section
-        let online_count = self.peers.iter().filter(|p| p.online).count();
-        let total_count = self.peers.len();
-        let peers_header = text::body(format!("Peers ({online_count}/{total_count} online)"));
+    /// Filter text box and quick filter toggles above the peer list.
+    fn peer_filter_bar(&self) -> Element<'_, Message> {
+        use cosmic::iced::widget::{column, row};
 
-        let mut peers_col = column![peers_header].spacing(2);
+        let input = widget::text_input("Filter by name, IP, OS or tag", &self.peer_filter.text)
+            .on_input(Message::PeerFilterText)
+            .width(Length::Fill);
 
-        if self.connected {
-            for peer in &self.peers {
-                peers_col = peers_col.push(self.peer_row(peer));
-            }
+        let mut chips = row![].spacing(4);
+        for filter in QuickFilter::ALL {
+            let class = if self.peer_filter.has(filter) {
+                cosmic::theme::Button::Suggested
+            } else {
+                cosmic::theme::Button::Standard
+            };
+            chips = chips.push(
+                widget::button::custom(text::caption(filter.label()))
+                    .on_press(Message::TogglePeerFilter(filter))
+                    .padding([2, 8])
+                    .class(class),
+            );
         }
 
-        // Bottom actions row
-        let actions_row = row![
-            widget::button::standard("Admin Console").on_press(Message::OpenAdminConsole),
-            horizontal_space(),
-            widget::button::standard("Settings...").on_press(Message::OpenSettings),
-        ]
-        .spacing(8)
-        .align_y(Alignment::Center);
-
-        // Assemble
-        let mut content = column![title_row, divider(), info_col, divider(), toggle_row,]
-            .spacing(8)
-            .padding(12);
+        column![input, chips].spacing(4).into()
+    }
 
-        if self.connected && !self.peers.is_empty() {
-            content = content.push(divider()).push(peers_col);
+    /// Peers passing the filter, favorites first, scrollable so large
+    /// tailnets fit the popup.
+    fn peer_list(&self) -> Element<'_, Message> {
+        use cosmic::iced::widget::{column, container};
+
+        let (favorites, others): (Vec<&PeerInfo>, Vec<&PeerInfo>) = self
+            .peers
+            .iter()
+            .filter(|p| self.peer_filter.matches(p, self.self_user_id))
+            .partition(|p| self.config.is_favorite(&self.tailnet_name, &p.id));
+
+        let mut list = column![].spacing(2);
+        if !favorites.is_empty() {
+            list = list.push(text::caption("Favorites"));
+            for peer in &favorites {
+                list = list.push(self.peer_row(peer));
+            }
+            if !others.is_empty() {
+                list = list.push(text::caption("All peers"));
+            }
+        }
+        for peer in &others {
+            list = list.push(self.peer_row(peer));
+        }
+        if favorites.is_empty() && others.is_empty() && self.peer_filter.is_active() {
+            list = list.push(text::caption("No peers match the filter"));
         }
 
-        content = content.push(divider()).push(actions_row);
-
-        content
+        container(widget::scrollable(list))
+            .max_height(260.0)
+            .into()
     }
 
     fn peer_row(&self, peer: &PeerInfo) -> Element<'_, Message> {
@@ -898,18 +2271,47 @@ impl TailscaleApplet {
         if peer.exit_node {
             peer_col = peer_col.push(text::caption("  Exit node (active)"));
         }
+        if peer.key_expiry.is_some_and(|t| t <= unix_now()) {
+            peer_col = peer_col.push(
+                row![
+                    text::caption("  "),
+                    widget::icon::from_name("dialog-warning-symbolic")
+                        .size(12)
+                        .symbolic(true),
+                    text::caption("Key expired"),
+                ]
+                .spacing(2)
+                .align_y(Alignment::Center),
+            );
+        }
+        if peer.online && peer.services.not_probed {
+            peer_col = peer_col.push(text::caption("  Services not probed"));
+        }
 
         // Wrap in a clickable button — copies DNS name (or IP if no DNS)
         let copy_val = copy_target.clone();
-        let peer_btn: Element<Message> = widget::button::custom(peer_col)
-            .on_press(Message::CopyToClipboard(copy_val))
-            .padding([4, 8])
-            .class(cosmic::theme::Button::MenuItem)
-            .width(Length::Fill)
-            .into();
+        let pin_icon = if self.config.is_favorite(&self.tailnet_name, &peer.id) {
+            "starred-symbolic"
+        } else {
+            "non-starred-symbolic"
+        };
+        let peer_btn: Element<Message> = row![
+            widget::button::custom(peer_col)
+                .on_press(Message::CopyToClipboard(copy_val))
+                .padding([4, 8])
+                .class(cosmic::theme::Button::MenuItem)
+                .width(Length::Fill),
+            Self::icon_btn(pin_icon, true, Message::ToggleFavorite(peer.id.clone())),
+            Self::icon_btn(
+                "go-next-symbolic",
+                true,
+                Message::ShowPage(Page::PeerDetail(peer.id.clone()))
+            ),
+        ]
+        .align_y(Alignment::Center)
+        .into();
 
         if peer.online {
-            let svc = &peer.services;
             let mut buttons: Vec<Element<Message>> = Vec::new();
 
             let dns_or_ip = if dns_display.is_empty() {
@@ -918,94 +2320,76 @@ impl TailscaleApplet {
                 dns_display.clone()
             };
 
-            // SSH
-            if svc.ssh || peer.ssh_enabled {
-                buttons.push(Self::icon_btn(
-                    "utilities-terminal-symbolic",
-                    true,
-                    Message::ShowCredDialog {
-                        service: CredService::Ssh,
-                        dns_name: dns_or_ip.clone(),
-                        ip: ip_str.clone(),
-                        vnc_type: VncType::None,
-                    },
-                ));
-            }
+            for def in &self.config.services {
+                let found = peer.services.get(&def.id);
+                // Tailscale SSH answers on port 22 whether or not sshd does
+                let tailscale_ssh = def.launch == Launcher::Ssh && peer.ssh_enabled;
+                if found.is_none() && !tailscale_ssh {
+                    continue;
+                }
+                let port = found.map_or(22, |f| f.port);
+                let vnc_type = found.map_or(VncType::None, |f| f.vnc_type.clone());
+
+                if def.launch == Launcher::CopyHttpsUrl {
+                    // Only an HTTPS endpoint Tailscale serves has a usable URL
+                    if let Some(url) = peer.services.served.first().cloned() {
+                        let is_url_copied = self.copied_text.as_deref() == Some(&url);
+                        let icon = if is_url_copied {
+                            "object-select-symbolic"
+                        } else {
+                            def.icon.as_str()
+                        };
+                        buttons.push(Self::icon_btn(
+                            icon,
+                            icon.ends_with("-symbolic"),
+                            Message::CopyToClipboard(url),
+                        ));
+                    }
+                    continue;
+                }
 
-            // VNC
-            if svc.vnc {
-                let icon = if svc.vnc_type == VncType::RealVnc {
+                let icon = if vnc_type == VncType::RealVnc {
                     "io.github.reality2_roycdavies.cosmic-tailscale-realvnc"
                 } else {
-                    "io.github.reality2_roycdavies.cosmic-tailscale-vnc"
+                    def.icon.as_str()
                 };
-                buttons.push(Self::icon_btn(
-                    icon,
-                    false,
-                    Message::ShowCredDialog {
-                        service: CredService::Vnc,
-                        dns_name: dns_or_ip.clone(),
-                        ip: ip_str.clone(),
-                        vnc_type: svc.vnc_type.clone(),
-                    },
-                ));
+                let msg = Message::OpenService(ServiceTarget {
+                    service: def.id.clone(),
+                    host: dns_or_ip.clone(),
+                    ip: ip_str.clone(),
+                    port,
+                    vnc_type,
+                });
+                // Name recognized apps so two web buttons can be told apart
+                buttons.push(match found.and_then(|f| f.app.as_deref()) {
+                    Some(app) => Self::labeled_btn(icon, icon.ends_with("-symbolic"), app, msg),
+                    None => Self::icon_btn(icon, icon.ends_with("-symbolic"), msg),
+                });
             }
 
-            // RDP
-            if svc.rdp {
-                buttons.push(Self::icon_btn(
-                    "folder-remote-symbolic",
+            if self.file_targets.iter().any(|t| t.matches(peer)) {
+                buttons.push(Self::labeled_btn(
+                    "document-send-symbolic",
                     true,
-                    Message::ShowCredDialog {
-                        service: CredService::Rdp,
-                        dns_name: dns_or_ip.clone(),
-                        ip: ip_str.clone(),
-                        vnc_type: VncType::None,
-                    },
-                ));
-            }
-
-            // NoMachine
-            if svc.nomachine {
-                buttons.push(Self::icon_btn(
-                    "io.github.reality2_roycdavies.cosmic-tailscale-nomachine",
-                    false,
-                    Message::ShowCredDialog {
-                        service: CredService::NoMachine,
-                        dns_name: dns_or_ip.clone(),
-                        ip: ip_str.clone(),
-                        vnc_type: VncType::None,
-                    },
+                    "Send file…",
+                    Message::SendFiles(peer.id.clone()),
                 ));
             }
 
-            // HTTP
-            if svc.http {
-                buttons.push(Self::icon_btn(
+            // Open what the peer shares through Tailscale Serve
+            for url in &peer.services.served {
+                let label = match url.rsplit_once(':') {
+                    Some((_, port)) if !port.starts_with('/') => format!("Serve :{port}"),
+                    _ => "Serve".to_string(),
+                };
+                buttons.push(Self::labeled_btn(
                     "web-browser-symbolic",
                     true,
-                    Message::LaunchHttp(dns_or_ip.clone()),
+                    &label,
+                    Message::OpenUrl(url.clone()),
                 ));
             }
 
-            // HTTPS copy
-            if svc.https {
-                let url = peer.https_url();
-                if !url.is_empty() {
-                    let is_url_copied = self.copied_text.as_deref() == Some(&url);
-                    let icon = if is_url_copied {
-                        "object-select-symbolic"
-                    } else {
-                        "edit-copy-symbolic"
-                    };
-                    buttons.push(Self::icon_btn(
-                        icon,
-                        true,
-                        Message::CopyToClipboard(url),
-                    ));
-                }
-            }
-
             if buttons.is_empty() {
                 peer_btn
             } else {
@@ -1020,6 +2404,29 @@ impl TailscaleApplet {
         }
     }
 
+    fn labeled_btn(
+        icon_name: &str,
+        symbolic: bool,
+        label: &str,
+        msg: Message,
+    ) -> Element<'static, Message> {
+        use cosmic::iced::widget::row;
+        use cosmic::iced::Alignment;
+
+        let icon = widget::icon::from_name(icon_name)
+            .symbolic(symbolic)
+            .size(16);
+        widget::button::custom(
+            row![icon, text::caption(label.to_string())]
+                .spacing(4)
+                .align_y(Alignment::Center),
+        )
+        .on_press(msg)
+        .padding([4, 6])
+        .class(cosmic::theme::Button::MenuItem)
+        .into()
+    }
+
     fn icon_btn(icon_name: &str, symbolic: bool, msg: Message) -> Element<'static, Message> {
         let icon: Element<Message> = widget::icon::from_name(icon_name)
             .symbolic(symbolic)
@@ -1036,8 +2443,12 @@ impl TailscaleApplet {
         use cosmic::iced::widget::{column, horizontal_space, row};
         use cosmic::iced::Alignment;
 
-        let title = format!("{} Connect", dialog.service.label());
-        let host_label = dialog.dns_name.clone();
+        let name = self
+            .config
+            .service(&dialog.target.service)
+            .map_or(dialog.target.service.as_str(), |def| def.name.as_str());
+        let title = format!("{name} Connect");
+        let host_label = dialog.target.host.clone();
 
         let username_input: Element<Message> = widget::text_input("username", &dialog.username)
             .on_input(Message::CredUsername)
@@ -1079,54 +2490,667 @@ impl TailscaleApplet {
     }
 }
 
-async fn run_background(
-    cmd_rx: std::sync::mpsc::Receiver<TailscaleCommand>,
-    event_tx: std::sync::mpsc::Sender<TailscaleEvent>,
-) {
-    loop {
-        // Check for commands from the UI
-        if let Ok(cmd) = cmd_rx.try_recv() {
-            match cmd {
-                TailscaleCommand::Toggle => {
-                    let _ = event_tx.send(TailscaleEvent::ToggleStarted);
-
-                    let currently_running = tailscale::get_status()
-                        .map(|s| s.backend_state == "Running")
-                        .unwrap_or(false);
-
-                    let result = if currently_running {
-                        tailscale::disconnect()
+/// Open a service on a peer with its registry launcher.
+fn launch_service(def: &ServiceDef, target: ServiceTarget, username: String) {
+    let ServiceTarget {
+        host: dns_name,
+        ip,
+        port,
+        vnc_type,
+        ..
+    } = target;
+
+    match def.launch {
+        Launcher::Ssh => {
+            let target = if username.is_empty() {
+                dns_name
+            } else {
+                format!("{username}@{dns_name}")
+            };
+            let mut ssh = vec!["ssh".to_string()];
+            if port != 22 {
+                ssh.extend(["-p".to_string(), port.to_string()]);
+            }
+            ssh.push(target);
+            std::thread::spawn(move || {
+                let terminals = [
+                    ("cosmic-term", "-e"),
+                    ("gnome-terminal", "--"),
+                    ("konsole", "-e"),
+                    ("xterm", "-e"),
+                ];
+                for (term, exec_flag) in terminals {
+                    if std::process::Command::new(term)
+                        .arg(exec_flag)
+                        .args(&ssh)
+                        .spawn()
+                        .is_ok()
+                    {
+                        return;
+                    }
+                }
+                eprintln!("Failed to launch ssh: no terminal emulator found");
+            });
+        }
+        Launcher::Rdp => {
+            std::thread::spawn(move || {
+                let remmina_dir = dirs::home_dir()
+                    .unwrap_or_else(|| std::path::PathBuf::from("/tmp"))
+                    .join(".local/share/remmina");
+                let _ = std::fs::create_dir_all(&remmina_dir);
+                let profile = remmina_dir
+                    .join(format!(
+                        "tailscale-rdp-{}.remmina",
+                        dns_name.replace(|c: char| !c.is_alphanumeric(), "_")
+                    ))
+                    .to_string_lossy()
+                    .to_string();
+                // Only create the file if it doesn't exist yet;
+                // reuse any existing file so user customisations persist.
+                if !std::path::Path::new(&profile).exists() {
+                    let content = format!(
+                        "\
+[remmina]
+name={dns_name}
+protocol=RDP
+server={dns_name}
+username={username}
+colordepth=32
+quality=2
+glyph-cache=true
+network=lan
+gfx=false
+rfx=false
+disableautoreconnect=0
+"
+                    );
+                    if let Err(e) = std::fs::write(&profile, &content) {
+                        eprintln!("Failed to write remmina profile: {e}");
+                        return;
+                    }
+                }
+                let native = std::process::Command::new("remmina")
+                    .args(["-c", &profile])
+                    .spawn();
+                if native.is_err() {
+                    if let Err(e) = std::process::Command::new("flatpak")
+                        .args(["run", "org.remmina.Remmina", "-c", &profile])
+                        .spawn()
+                    {
+                        eprintln!("Failed to launch remmina: {e}");
+                    }
+                }
+            });
+        }
+        Launcher::Vnc => {
+            std::thread::spawn(move || {
+                if vnc_type == VncType::RealVnc {
+                    if let Err(e) = std::process::Command::new("vncviewer")
+                        .arg(&dns_name)
+                        .spawn()
+                    {
+                        eprintln!("Failed to launch vncviewer: {e}");
+                    }
+                } else {
+                    let target = if username.is_empty() {
+                        format!("vnc://{dns_name}")
                     } else {
-                        tailscale::connect()
+                        format!("vnc://{username}@{dns_name}")
                     };
-
-                    let _ = event_tx.send(TailscaleEvent::ToggleComplete(result));
+                    let native = std::process::Command::new("remmina").arg(&target).spawn();
+                    if native.is_err() {
+                        if let Err(e) = std::process::Command::new("flatpak")
+                            .args(["run", "org.remmina.Remmina", &target])
+                            .spawn()
+                        {
+                            eprintln!("Failed to launch remmina: {e}");
+                        }
+                    }
+                }
+            });
+        }
+        Launcher::NoMachine => {
+            std::thread::spawn(move || {
+                let nx_dir = dirs::home_dir()
+                    .unwrap_or_else(|| std::path::PathBuf::from("/tmp"))
+                    .join(".nx");
+                let _ = std::fs::create_dir_all(&nx_dir);
+                let nxs_file = nx_dir
+                    .join(format!(
+                        "tailscale-nx-{}.nxs",
+                        dns_name.replace(|c: char| !c.is_alphanumeric(), "_")
+                    ))
+                    .to_string_lossy()
+                    .to_string();
+                // Only create the file if it doesn't exist yet;
+                // reuse any existing file so user customisations persist.
+                if !std::path::Path::new(&nxs_file).exists() {
+                    let nxs_content = format!(
+                        "\
+<!DOCTYPE NXClientSettings>
+<NXClientSettings version=\"2.3\" application=\"nxclient\" >
+ <group name=\"General\" >
+  <option key=\"Connection service\" value=\"nx\" />
+  <option key=\"NoMachine daemon port\" value=\"4000\" />
+ </group>
+ <group name=\"Local Settings\" >
+  <option key=\"Server name\" value=\"{dns_name}\" />
+  <option key=\"List of hosts\" value=\"{ip}\" />
+  <option key=\"List of ports\" value=\"4000\" />
+  <option key=\"List of protocols\" value=\"nx\" />
+ </group>
+ <group name=\"Login\" >
+  <option key=\"Server authentication method\" value=\"system\" />
+  <option key=\"System login method\" value=\"password\" />
+  <option key=\"User\" value=\"{username}\" />
+ </group>
+</NXClientSettings>
+"
+                    );
+                    if let Err(e) = std::fs::write(&nxs_file, &nxs_content) {
+                        eprintln!("Failed to write .nxs file: {e}");
+                        return;
+                    }
+                }
+                let native = std::process::Command::new("nxplayer")
+                    .args(["--session", &nxs_file])
+                    .spawn();
+                if native.is_err() {
+                    if let Err(e) = std::process::Command::new("flatpak")
+                        .args([
+                            "run",
+                            "--nosocket=wayland",
+                            "com.nomachine.nxplayer",
+                            "--session",
+                            &nxs_file,
+                        ])
+                        .spawn()
+                    {
+                        eprintln!("Failed to launch nxplayer: {e}");
+                    }
+                }
+            });
+        }
+        Launcher::Command(_) => {
+            let Some(args) = def.command(&dns_name, &ip, port, &username) else {
+                return;
+            };
+            std::thread::spawn(move || {
+                if let Err(e) = std::process::Command::new(&args[0])
+                    .args(&args[1..])
+                    .spawn()
+                {
+                    eprintln!("Failed to launch {}: {e}", args[0]);
                 }
+            });
+        }
+        // Copied straight from the peer row instead
+        Launcher::CopyHttpsUrl => {}
+    }
+}
+
+/// Start the background worker and forward its events to the UI.
+///
+/// The worker makes blocking daemon calls, so it runs on its own thread and
+/// runtime instead of the applet's executor.
+fn worker(
+    backend: Arc<dyn TailscaleBackend>,
+    registry: Vec<ServiceDef>,
+    probe_settings: ProbeSettings,
+) -> impl Stream<Item = Message> {
+    cosmic::iced::stream::channel(16, move |mut output| async move {
+        let (cmd_tx, cmd_rx) = tokio::sync::mpsc::unbounded_channel();
+        let (event_tx, mut event_rx) = tokio::sync::mpsc::unbounded_channel();
+        let _ = output.send(Message::WorkerReady(cmd_tx)).await;
+
+        std::thread::spawn(move || {
+            let rt = tokio::runtime::Runtime::new().expect("Failed to create tokio runtime");
+            rt.block_on(run_background(
+                backend,
+                registry,
+                probe_settings,
+                cmd_rx,
+                event_tx,
+            ));
+        });
+
+        while let Some(event) = event_rx.recv().await {
+            if output.send(Message::Event(event)).await.is_err() {
+                break;
             }
         }
+    })
+}
+
+fn unix_now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64)
+}
+
+/// Human-readable size, e.g. "45.9 MB".
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}
+
+/// Coarse distance between a Unix timestamp and `now`, e.g. "5 min ago"
+/// or "in 3 days".
+fn format_relative(ts: i64, now: i64) -> String {
+    let delta = now - ts;
+    let secs = delta.abs();
+    let amount = if secs < 60 {
+        return "just now".to_string();
+    } else if secs < 3600 {
+        format!("{} min", secs / 60)
+    } else if secs < 86_400 {
+        let hours = secs / 3600;
+        format!("{hours} hour{}", if hours == 1 { "" } else { "s" })
+    } else {
+        let days = secs / 86_400;
+        format!("{days} day{}", if days == 1 { "" } else { "s" })
+    };
+    if delta > 0 {
+        format!("{amount} ago")
+    } else {
+        format!("in {amount}")
+    }
+}
+
+enum WatchEvent {
+    Notify(IpnNotify),
+    Lost,
+}
+
+/// Keep an IPN bus subscription open, reopening it after failures.
+fn watch_ipn_bus(backend: Arc<dyn TailscaleBackend>, tx: UnboundedSender<WatchEvent>) {
+    loop {
+        let _ = backend.watch_ipn(&mut |notify| {
+            let _ = tx.send(WatchEvent::Notify(notify));
+        });
+        if tx.send(WatchEvent::Lost).is_err() {
+            return;
+        }
+        std::thread::sleep(WATCH_RETRY);
+    }
+}
+
+async fn run_background(
+    backend: Arc<dyn TailscaleBackend>,
+    registry: Vec<ServiceDef>,
+    mut probe_settings: ProbeSettings,
+    mut cmd_rx: UnboundedReceiver<TailscaleCommand>,
+    event_tx: UnboundedSender<TailscaleEvent>,
+) {
+    let (watch_tx, mut watch_rx) = tokio::sync::mpsc::unbounded_channel();
+    let watch_backend = backend.clone();
+    std::thread::spawn(move || watch_ipn_bus(watch_backend, watch_tx));
+
+    let (probe_tx, mut probe_rx) = tokio::sync::mpsc::unbounded_channel();
+    let prober = Prober::new(backend.clone(), probe_tx);
+    let mut probes = ProbeCache::default();
+    let mut registry = Arc::new(registry);
+
+    // While the IPN bus is open it tells us when to refresh status, and the
+    // poll timer only expires probe results. Otherwise the timer polls status too.
+    let mut watching = false;
+    let mut last_status: Option<TailscaleStatus> = None;
+    let mut notifier: Option<Notifier> = None;
+    let mut poll = tokio::time::interval(POLL_INTERVAL);
+
+    loop {
+        let mut refresh = false;
+        let mut probe = false;
+        let mut probed = false;
+
+        tokio::select! {
+            cmd = cmd_rx.recv() => {
+                let Some(cmd) = cmd else {
+                    return;
+                };
+                match cmd {
+                    TailscaleCommand::Toggle => {
+                        let _ = event_tx.send(TailscaleEvent::ToggleStarted);
+
+                        let state = backend
+                            .status()
+                            .map(|s| s.backend_state)
+                            .unwrap_or_default();
+
+                        // `up` would block on a login we never show, so start
+                        // one the applet can display instead
+                        let result = match state {
+                            BackendState::Running => backend.down(),
+                            BackendState::NeedsLogin => {
+                                backend.login().map(|url| waiting_for_login(&event_tx, url))
+                            }
+                            _ => backend.up(),
+                        };
 
-        // Poll current status and probe services on online peers
-        match tailscale::get_status() {
-            Ok(mut status) => {
-                // Probe services for online peers
-                for peer in &mut status.peers {
-                    if peer.online {
-                        if let Some(ip) = peer.tailscale_ips.first() {
-                            peer.services = tailscale::probe_services(ip);
+                        let _ = event_tx.send(TailscaleEvent::ToggleComplete(result));
+                    }
+                    TailscaleCommand::Login => {
+                        let result =
+                            backend.login().map(|url| waiting_for_login(&event_tx, url));
+                        let _ = event_tx.send(TailscaleEvent::CommandComplete(result));
+                    }
+                    TailscaleCommand::SwitchProfile(profile) => {
+                        let result = backend
+                            .switch_profile(&profile.id)
+                            .map(|()| format!("Switched to {}", profile.account));
+                        let _ = event_tx.send(TailscaleEvent::CommandComplete(result));
+                    }
+                    TailscaleCommand::AddProfile => {
+                        let result =
+                            backend.add_profile().map(|url| waiting_for_login(&event_tx, url));
+                        let _ = event_tx.send(TailscaleEvent::CommandComplete(result));
+                    }
+                    TailscaleCommand::Logout => {
+                        let result = backend.logout().map(|()| "Logged out".to_string());
+                        let _ = event_tx.send(TailscaleEvent::CommandComplete(result));
+                    }
+                    TailscaleCommand::Ping(peer_id, ip) => {
+                        // Runs for up to ~10s, so keep it off the command loop
+                        let backend = backend.clone();
+                        let event_tx = event_tx.clone();
+                        std::thread::spawn(move || {
+                            let result = backend.ping(&ip, &mut |pong| {
+                                let _ = event_tx.send(TailscaleEvent::Pong(peer_id.clone(), pong));
+                            });
+                            let _ = event_tx.send(TailscaleEvent::PingComplete(peer_id, result));
+                        });
+                        continue;
+                    }
+                    TailscaleCommand::ListDrives(peer_id, tailnet, peer_name) => {
+                        // Goes out to the peer, so keep it off the command loop
+                        let backend = backend.clone();
+                        let event_tx = event_tx.clone();
+                        std::thread::spawn(move || {
+                            let result = backend.peer_drives(&tailnet, &peer_name);
+                            let _ = event_tx.send(TailscaleEvent::PeerDrives(peer_id, result));
+                        });
+                        continue;
+                    }
+                    TailscaleCommand::Netcheck => {
+                        let backend = backend.clone();
+                        let event_tx = event_tx.clone();
+                        std::thread::spawn(move || {
+                            let _ = event_tx.send(TailscaleEvent::Netcheck(backend.netcheck()));
+                        });
+                        continue;
+                    }
+                    TailscaleCommand::Notify(events) => {
+                        if notifier.is_none() {
+                            notifier = match Notifier::connect().await {
+                                Ok(n) => Some(n),
+                                Err(e) => {
+                                    eprintln!("Failed to connect for notifications: {e}");
+                                    None
+                                }
+                            };
+                        }
+                        if let Some(ref n) = notifier {
+                            for event in &events {
+                                if let Err(e) = n.send(event, unix_now()).await {
+                                    eprintln!("Failed to send notification: {e}");
+                                    // Reconnect next time in case the bus went away
+                                    notifier = None;
+                                    break;
+                                }
+                            }
+                        }
+                        continue;
+                    }
+                    TailscaleCommand::SetProbeConfig(services, settings) => {
+                        // Results stay valid unless what or whom we probe changed
+                        if services == *registry && settings == probe_settings {
+                            continue;
                         }
+                        registry = Arc::new(services);
+                        probe_settings = settings;
+                        probes.clear();
+                        probe = true;
+                    }
+                    TailscaleCommand::RefreshServices => {
+                        probes.clear();
+                        probe = true;
+                    }
+                    TailscaleCommand::SendFiles(peer, files) => {
+                        // Large files take a while, so keep them off the command loop
+                        let backend = backend.clone();
+                        let event_tx = event_tx.clone();
+                        std::thread::spawn(move || send_files(&*backend, &peer, files, &event_tx));
+                        continue;
+                    }
+                    TailscaleCommand::SaveFile(name, dir) => {
+                        let backend = backend.clone();
+                        let event_tx = event_tx.clone();
+                        std::thread::spawn(move || {
+                            let result = backend.save_waiting_file(&name, &dir);
+                            let _ = event_tx.send(TailscaleEvent::FileSaved(name, result));
+                        });
+                        continue;
+                    }
+                    TailscaleCommand::DiscardFile(name) => {
+                        let result = backend
+                            .delete_waiting_file(&name)
+                            .map(|()| format!("Discarded {name}"));
+                        let _ = event_tx.send(TailscaleEvent::CommandComplete(result));
+                    }
+                    TailscaleCommand::SetExitNode(peer) => {
+                        let result = backend.set_exit_node(peer.as_ref()).map(|()| match peer {
+                            Some(p) => format!("Exit node: {}", p.display_name()),
+                            None => "Exit node off".to_string(),
+                        });
+                        let _ = event_tx.send(TailscaleEvent::CommandComplete(result));
+                    }
+                    TailscaleCommand::SuggestExitNode => {
+                        let result = backend.suggest_exit_node();
+                        let _ = event_tx.send(TailscaleEvent::ExitNodeSuggestion(result));
+                    }
+                    TailscaleCommand::SetBoolPref(flag, value) => {
+                        let result = backend
+                            .set_bool_pref(flag, value)
+                            .map(|()| format!("Updated {flag}"));
+                        let _ = event_tx.send(TailscaleEvent::CommandComplete(result));
                     }
                 }
-                let _ = event_tx.send(TailscaleEvent::StatusUpdate(Ok(status)));
+                refresh = true;
             }
-            Err(e) => {
-                let _ = event_tx.send(TailscaleEvent::StatusUpdate(Err(e)));
+            Some(event) = watch_rx.recv() => {
+                // Collapse a burst of notifications into a single refresh
+                let mut next = Some(event);
+                while let Some(event) = next {
+                    match event {
+                        WatchEvent::Notify(n) => {
+                            watching = true;
+                            if let Some(url) = n.browse_to_url {
+                                let _ = event_tx.send(TailscaleEvent::AuthUrl(url));
+                            }
+                            refresh |= n.state.is_some()
+                                || n.prefs_changed
+                                || n.netmap_changed
+                                || n.files_waiting;
+                        }
+                        WatchEvent::Lost => watching = false,
+                    }
+                    next = watch_rx.try_recv().ok();
+                }
+            }
+            Some(result) = probe_rx.recv() => {
+                // Merge every probe that has finished into one update
+                let mut next = Some(result);
+                while let Some(result) = next {
+                    probes.insert(result, Instant::now());
+                    next = probe_rx.try_recv().ok();
+                }
+                probed = true;
+            }
+            _ = poll.tick() => {
+                probe = true;
+                refresh = !watching || last_status.is_none();
+            }
+        }
+
+        if !refresh && !probe && !probed {
+            continue;
+        }
+
+        if refresh {
+            if let Ok(prefs) = backend.prefs() {
+                let _ = event_tx.send(TailscaleEvent::PrefsUpdate(prefs));
+            }
+            if let Ok(profiles) = backend.profiles() {
+                let _ = event_tx.send(TailscaleEvent::ProfilesUpdate(profiles));
+            }
+            match backend.status() {
+                Ok(status) => last_status = Some(status),
+                Err(e) => {
+                    last_status = None;
+                    let _ = event_tx.send(TailscaleEvent::StatusUpdate(Err(e)));
+                    continue;
+                }
+            }
+        }
+
+        let Some(ref status) = last_status else {
+            continue;
+        };
+
+        if refresh || probe {
+            if let Ok(endpoints) = backend.serve_config(&status.self_node.dns_name) {
+                let _ = event_tx.send(TailscaleEvent::ServeUpdate(endpoints));
+            }
+            if let Ok(targets) = backend.file_targets() {
+                let _ = event_tx.send(TailscaleEvent::FileTargets(targets));
+            }
+            if let Ok(files) = backend.waiting_files() {
+                let _ = event_tx.send(TailscaleEvent::WaitingFiles(files));
+            }
+        }
+
+        // Start probes for new, changed or stale peers; results come back
+        // through `probe_rx` without holding up this update
+        let self_user_id = status.self_node.user_id;
+        let allowed = |peer: &PeerInfo| probe::should_probe(&probe_settings, peer, self_user_id);
+        if probe || refresh {
+            let due = probes.due(&status.peers, Instant::now(), allowed);
+            if !due.is_empty() {
+                prober.spawn(&registry, due);
+            }
+        }
+
+        let mut status = status.clone();
+        for peer in &mut status.peers {
+            if !allowed(peer) {
+                peer.services.not_probed = true;
+            } else if let Some(svc) = probes.services(&peer.id) {
+                peer.services = svc.clone();
             }
         }
+        let _ = event_tx.send(TailscaleEvent::StatusUpdate(Ok(status)));
+    }
+}
+
+/// Pass on an auth URL from the CLI; LocalAPI logins publish theirs on the
+/// IPN bus instead.
+fn waiting_for_login(event_tx: &UnboundedSender<TailscaleEvent>, url: Option<String>) -> String {
+    if let Some(url) = url {
+        let _ = event_tx.send(TailscaleEvent::AuthUrl(url));
+    }
+    "Waiting for login...".to_string()
+}
 
-        tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
+/// Send each file in turn, reporting progress at most once per percent.
+fn send_files(
+    backend: &dyn TailscaleBackend,
+    peer: &PeerInfo,
+    files: Vec<(u64, PathBuf)>,
+    event_tx: &UnboundedSender<TailscaleEvent>,
+) {
+    for (id, path) in files {
+        let size = std::fs::metadata(&path).map_or(0, |m| m.len());
+        let step = (size / 100).max(1);
+        let mut reported = 0;
+        let result = backend.send_file(peer, &path, &mut |sent| {
+            if sent - reported >= step || sent == size {
+                reported = sent;
+                let _ = event_tx.send(TailscaleEvent::TransferProgress(id, sent));
+            }
+        });
+        let _ = event_tx.send(TailscaleEvent::TransferComplete(id, result));
     }
 }
 
 pub fn run_applet() -> cosmic::iced::Result {
     cosmic::applet::run::<TailscaleApplet>(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::FakeBackend;
+    use cosmic::Application as _;
+    use std::path::Path;
+
+    /// The applet on the bundled fixtures, with settings kept in memory.
+    fn fixture_applet() -> TailscaleApplet {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("resources/fixtures");
+        let backend = FakeBackend::from_fixture_dir(&dir).unwrap();
+        TailscaleApplet::new(Core::default(), Arc::new(backend), AppConfig::default())
+    }
+
+    #[test]
+    fn status_update_fills_peer_list() {
+        let mut applet = fixture_applet();
+        let status = applet.backend.status().unwrap();
+        applet.peers.clear();
+        applet.connected = false;
+
+        let _ = applet.update(Message::Event(TailscaleEvent::StatusUpdate(Ok(status))));
+        assert!(applet.connected);
+        assert!(applet.peers.iter().any(|p| p.hostname == "nas"));
+    }
+
+    #[test]
+    fn toggle_favorite_stays_in_memory() {
+        let mut applet = fixture_applet();
+        let tailnet = applet.tailnet_name.clone();
+
+        let _ = applet.update(Message::ToggleFavorite("nNas3CNTRL".to_string()));
+        assert!(applet.config.is_favorite(&tailnet, "nNas3CNTRL"));
+        let _ = applet.update(Message::ToggleFavorite("nNas3CNTRL".to_string()));
+        assert!(!applet.config.is_favorite(&tailnet, "nNas3CNTRL"));
+    }
+
+    #[test]
+    fn probe_config_sent_to_worker_only_when_changed() {
+        let mut applet = fixture_applet();
+        let (cmd_tx, mut cmd_rx) = tokio::sync::mpsc::unbounded_channel();
+        let _ = applet.update(Message::WorkerReady(cmd_tx));
+
+        let _ = applet.update(Message::Tick);
+        assert!(cmd_rx.try_recv().is_err());
+
+        applet.config.probing.hosts = vec!["nas".to_string()];
+        let _ = applet.update(Message::Tick);
+        match cmd_rx.try_recv() {
+            Ok(TailscaleCommand::SetProbeConfig(_, settings)) => {
+                assert_eq!(settings.hosts, ["nas"]);
+            }
+            _ => panic!("expected SetProbeConfig"),
+        }
+        let _ = applet.update(Message::Tick);
+        assert!(cmd_rx.try_recv().is_err());
+    }
+}
@@ -0,0 +1,671 @@
+//! Backend abstraction over tailscaled.
+//!
+//! The applet, settings page and settings CLI only talk to Tailscale through
+//! [`TailscaleBackend`], so their logic can run against [`FakeBackend`] on
+//! machines without a daemon.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::config::{AppConfig, ServiceDef};
+use crate::tailscale::{
+    self, BackendState, DriveShare, ExitNodeSuggestion, FileTarget, IpnNotify, LoginProfile,
+    NetcheckReport, PeerInfo, PingPath, Pong, ServeEdit, ServeEndpoint, ServiceInfo,
+    TailscaleError, TailscalePrefs, TailscaleStatus, WaitingFile,
+};
+
+/// Directory of recorded JSON to run against instead of the real daemon.
+const FIXTURES_ENV: &str = "COSMIC_TAILSCALE_FIXTURES";
+
+pub trait TailscaleBackend: Send + Sync {
+    fn status(&self) -> Result<TailscaleStatus, TailscaleError>;
+    fn prefs(&self) -> Result<TailscalePrefs, TailscaleError>;
+    /// Set a boolean pref by its `tailscale set` flag name (e.g. "accept-dns").
+    fn set_bool_pref(&self, flag: &str, value: bool) -> Result<(), TailscaleError>;
+    /// Use `exit_node` for internet traffic, or no exit node with `None`.
+    fn set_exit_node(&self, exit_node: Option<&PeerInfo>) -> Result<(), TailscaleError>;
+    /// Offer subnet routes, and this node as an exit node; see
+    /// [`tailscale::set_advertised_routes`].
+    fn set_advertised_routes(
+        &self,
+        routes: &[String],
+        exit_node: bool,
+    ) -> Result<(), TailscaleError>;
+    fn suggest_exit_node(&self) -> Result<ExitNodeSuggestion, TailscaleError>;
+    fn up(&self) -> Result<String, TailscaleError>;
+    fn down(&self) -> Result<String, TailscaleError>;
+    /// Start an interactive login; see [`tailscale::login`].
+    fn login(&self) -> Result<Option<String>, TailscaleError>;
+    fn profiles(&self) -> Result<Vec<LoginProfile>, TailscaleError>;
+    fn switch_profile(&self, id: &str) -> Result<(), TailscaleError>;
+    /// Add an account and start logging in to it.
+    fn add_profile(&self) -> Result<Option<String>, TailscaleError>;
+    /// Log out of (and forget) the current account.
+    fn logout(&self) -> Result<(), TailscaleError>;
+    /// Check a peer for each service in the registry, and for HTTPS it
+    /// serves at `dns_name` through Tailscale Serve.
+    fn probe_services(&self, ip: &str, dns_name: &str, services: &[ServiceDef]) -> ServiceInfo;
+    /// This node's Serve and Funnel mappings; see [`tailscale::get_serve_config`].
+    fn serve_config(&self, self_dns: &str) -> Result<Vec<ServeEndpoint>, TailscaleError>;
+    /// Add or remove a Serve mapping, or turn Funnel on or off for one.
+    fn edit_serve(&self, self_dns: &str, edit: &ServeEdit) -> Result<(), TailscaleError>;
+    /// Ping a peer until a direct path is found; see [`tailscale::ping`].
+    fn ping(&self, ip: &str, on_pong: &mut dyn FnMut(Pong)) -> Result<(), TailscaleError>;
+    fn netcheck(&self) -> Result<NetcheckReport, TailscaleError>;
+    /// Peers that accept Taildrop files from us.
+    fn file_targets(&self) -> Result<Vec<FileTarget>, TailscaleError>;
+    /// Send a file over Taildrop; see [`tailscale::send_file`].
+    fn send_file(
+        &self,
+        peer: &PeerInfo,
+        path: &Path,
+        on_progress: &mut dyn FnMut(u64),
+    ) -> Result<(), TailscaleError>;
+    /// Taildrop files received and not yet saved.
+    fn waiting_files(&self) -> Result<Vec<WaitingFile>, TailscaleError>;
+    /// Save a waiting file into `dir`, returning where it went.
+    fn save_waiting_file(&self, name: &str, dir: &Path) -> Result<PathBuf, TailscaleError>;
+    fn delete_waiting_file(&self, name: &str) -> Result<(), TailscaleError>;
+    /// Folders this machine shares over Taildrive.
+    fn drive_shares(&self) -> Result<Vec<DriveShare>, TailscaleError>;
+    fn share_folder(&self, name: &str, path: &Path) -> Result<(), TailscaleError>;
+    fn unshare_folder(&self, name: &str) -> Result<(), TailscaleError>;
+    fn rename_share(&self, old: &str, new: &str) -> Result<(), TailscaleError>;
+    /// Folders a peer shares with us; see [`tailscale::peer_drives`].
+    fn peer_drives(&self, tailnet: &str, peer_name: &str) -> Result<Vec<String>, TailscaleError>;
+    /// Follow the IPN notification bus until it ends; see [`tailscale::watch_ipn`].
+    fn watch_ipn(&self, on_notify: &mut dyn FnMut(IpnNotify)) -> Result<(), TailscaleError>;
+}
+
+/// The backend to use for this process: the local daemon, or fixtures when
+/// `COSMIC_TAILSCALE_FIXTURES` points at a directory of recorded JSON.
+pub fn default_backend() -> Arc<dyn TailscaleBackend> {
+    if let Some(dir) = std::env::var_os(FIXTURES_ENV) {
+        match FakeBackend::from_fixture_dir(Path::new(&dir)) {
+            Ok(fake) => return Arc::new(fake),
+            Err(e) => eprintln!("Ignoring {FIXTURES_ENV}: {e}"),
+        }
+    }
+    Arc::new(LocalBackend)
+}
+
+/// The settings to use alongside [`default_backend`]: the user's config file,
+/// or defaults kept in memory on fixtures so a demo never rewrites it.
+pub fn default_config() -> AppConfig {
+    if std::env::var_os(FIXTURES_ENV).is_some() {
+        AppConfig::default()
+    } else {
+        AppConfig::load()
+    }
+}
+
+/// The tailscaled running on this machine (LocalAPI, falling back to the CLI).
+pub struct LocalBackend;
+
+impl TailscaleBackend for LocalBackend {
+    fn status(&self) -> Result<TailscaleStatus, TailscaleError> {
+        tailscale::get_status()
+    }
+
+    fn prefs(&self) -> Result<TailscalePrefs, TailscaleError> {
+        tailscale::get_prefs()
+    }
+
+    fn set_bool_pref(&self, flag: &str, value: bool) -> Result<(), TailscaleError> {
+        tailscale::set_bool_pref(flag, value)
+    }
+
+    fn set_exit_node(&self, exit_node: Option<&PeerInfo>) -> Result<(), TailscaleError> {
+        tailscale::set_exit_node(exit_node)
+    }
+
+    fn set_advertised_routes(
+        &self,
+        routes: &[String],
+        exit_node: bool,
+    ) -> Result<(), TailscaleError> {
+        tailscale::set_advertised_routes(routes, exit_node)
+    }
+
+    fn suggest_exit_node(&self) -> Result<ExitNodeSuggestion, TailscaleError> {
+        tailscale::suggest_exit_node()
+    }
+
+    fn up(&self) -> Result<String, TailscaleError> {
+        tailscale::connect()
+    }
+
+    fn down(&self) -> Result<String, TailscaleError> {
+        tailscale::disconnect()
+    }
+
+    fn login(&self) -> Result<Option<String>, TailscaleError> {
+        tailscale::login()
+    }
+
+    fn profiles(&self) -> Result<Vec<LoginProfile>, TailscaleError> {
+        tailscale::list_profiles()
+    }
+
+    fn switch_profile(&self, id: &str) -> Result<(), TailscaleError> {
+        tailscale::switch_profile(id)
+    }
+
+    fn add_profile(&self) -> Result<Option<String>, TailscaleError> {
+        tailscale::add_profile()
+    }
+
+    fn logout(&self) -> Result<(), TailscaleError> {
+        tailscale::logout()
+    }
+
+    fn probe_services(&self, ip: &str, dns_name: &str, services: &[ServiceDef]) -> ServiceInfo {
+        tailscale::probe_services(ip, dns_name, services)
+    }
+
+    fn serve_config(&self, self_dns: &str) -> Result<Vec<ServeEndpoint>, TailscaleError> {
+        tailscale::get_serve_config(self_dns)
+    }
+
+    fn edit_serve(&self, self_dns: &str, edit: &ServeEdit) -> Result<(), TailscaleError> {
+        tailscale::edit_serve(self_dns, edit)
+    }
+
+    fn ping(&self, ip: &str, on_pong: &mut dyn FnMut(Pong)) -> Result<(), TailscaleError> {
+        tailscale::ping(ip, on_pong)
+    }
+
+    fn netcheck(&self) -> Result<NetcheckReport, TailscaleError> {
+        tailscale::netcheck()
+    }
+
+    fn file_targets(&self) -> Result<Vec<FileTarget>, TailscaleError> {
+        tailscale::file_targets()
+    }
+
+    fn send_file(
+        &self,
+        peer: &PeerInfo,
+        path: &Path,
+        on_progress: &mut dyn FnMut(u64),
+    ) -> Result<(), TailscaleError> {
+        tailscale::send_file(peer, path, on_progress)
+    }
+
+    fn waiting_files(&self) -> Result<Vec<WaitingFile>, TailscaleError> {
+        tailscale::waiting_files()
+    }
+
+    fn save_waiting_file(&self, name: &str, dir: &Path) -> Result<PathBuf, TailscaleError> {
+        tailscale::save_waiting_file(name, dir)
+    }
+
+    fn delete_waiting_file(&self, name: &str) -> Result<(), TailscaleError> {
+        tailscale::delete_waiting_file(name)
+    }
+
+    fn drive_shares(&self) -> Result<Vec<DriveShare>, TailscaleError> {
+        tailscale::drive_shares()
+    }
+
+    fn share_folder(&self, name: &str, path: &Path) -> Result<(), TailscaleError> {
+        tailscale::share_folder(name, path)
+    }
+
+    fn unshare_folder(&self, name: &str) -> Result<(), TailscaleError> {
+        tailscale::unshare_folder(name)
+    }
+
+    fn rename_share(&self, old: &str, new: &str) -> Result<(), TailscaleError> {
+        tailscale::rename_share(old, new)
+    }
+
+    fn peer_drives(&self, tailnet: &str, peer_name: &str) -> Result<Vec<String>, TailscaleError> {
+        tailscale::peer_drives(tailnet, peer_name)
+    }
+
+    fn watch_ipn(&self, on_notify: &mut dyn FnMut(IpnNotify)) -> Result<(), TailscaleError> {
+        tailscale::watch_ipn(on_notify)
+    }
+}
+
+/// In-memory backend built from recorded `status --json` and `debug prefs`
+/// output. Mutating calls update the in-memory state so later reads see them.
+pub struct FakeBackend {
+    state: Mutex<FakeState>,
+}
+
+struct FakeState {
+    status: TailscaleStatus,
+    prefs: TailscalePrefs,
+    /// Probe results keyed by Tailscale IP; unknown IPs have nothing open.
+    services: HashMap<String, ServiceInfo>,
+    profiles: Vec<LoginProfile>,
+    netcheck: Option<NetcheckReport>,
+    /// Recorded `serve status --json`, parsed on each read like the real thing
+    serve_config: Option<String>,
+    /// Received Taildrop files; saving one writes that many zero bytes
+    inbox: Vec<WaitingFile>,
+    shares: Vec<DriveShare>,
+    /// Folders each peer (by display name) shares with us
+    remote_drives: HashMap<String, Vec<String>>,
+}
+
+impl FakeBackend {
+    pub fn from_json(status_json: &str, prefs_json: &str) -> Result<Self, TailscaleError> {
+        let status = tailscale::parse_status(status_json.as_bytes())?;
+        let prefs = tailscale::parse_prefs(prefs_json.as_bytes())?;
+        // The recorded account is the only profile
+        let profiles = vec![LoginProfile {
+            id: "fixture".to_string(),
+            tailnet: status.tailnet_name.clone(),
+            account: prefs.login_name.clone(),
+            current: true,
+        }];
+        Ok(Self {
+            state: Mutex::new(FakeState {
+                status,
+                prefs,
+                services: HashMap::new(),
+                profiles,
+                netcheck: None,
+                serve_config: None,
+                inbox: Vec::new(),
+                shares: Vec::new(),
+                remote_drives: HashMap::new(),
+            }),
+        })
+    }
+
+    /// Load `status.json`, `prefs.json` and the optional `services.json`
+    /// (a map of Tailscale IP to open services), `netcheck.json`,
+    /// `derpmap.json`, `serve.json`, `inbox.json` (LocalAPI `files/`),
+    /// `drive.json` (LocalAPI `drive/shares`) and `remote-drives.json` (peer
+    /// name to the folders it shares with us) from `dir`.
+    pub fn from_fixture_dir(dir: &Path) -> Result<Self, String> {
+        let read = |name: &str| {
+            std::fs::read_to_string(dir.join(name))
+                .map_err(|e| format!("{}: {e}", dir.join(name).display()))
+        };
+
+        let fake = Self::from_json(&read("status.json")?, &read("prefs.json")?)
+            .map_err(|e| e.to_string())?;
+        if dir.join("services.json").exists() {
+            let services = serde_json::from_str(&read("services.json")?)
+                .map_err(|e| format!("Failed to parse services.json: {e}"))?;
+            fake.lock().services = services;
+        }
+        if dir.join("netcheck.json").exists() {
+            let derp_map = read("derpmap.json").ok();
+            let report = tailscale::parse_netcheck(
+                read("netcheck.json")?.as_bytes(),
+                derp_map.as_deref().map(str::as_bytes),
+            )
+            .map_err(|e| e.to_string())?;
+            fake.lock().netcheck = Some(report);
+        }
+        if dir.join("serve.json").exists() {
+            fake.lock().serve_config = Some(read("serve.json")?);
+        }
+        if dir.join("inbox.json").exists() {
+            let inbox = tailscale::parse_waiting_files(read("inbox.json")?.as_bytes())
+                .map_err(|e| e.to_string())?;
+            fake.lock().inbox = inbox;
+        }
+        if dir.join("drive.json").exists() {
+            let shares = tailscale::parse_drive_shares(read("drive.json")?.as_bytes())
+                .map_err(|e| e.to_string())?;
+            fake.lock().shares = shares;
+        }
+        if dir.join("remote-drives.json").exists() {
+            let remote_drives = serde_json::from_str(&read("remote-drives.json")?)
+                .map_err(|e| format!("Failed to parse remote-drives.json: {e}"))?;
+            fake.lock().remote_drives = remote_drives;
+        }
+        Ok(fake)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, FakeState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl TailscaleBackend for FakeBackend {
+    fn status(&self) -> Result<TailscaleStatus, TailscaleError> {
+        Ok(self.lock().status.clone())
+    }
+
+    fn prefs(&self) -> Result<TailscalePrefs, TailscaleError> {
+        Ok(self.lock().prefs.clone())
+    }
+
+    fn set_bool_pref(&self, flag: &str, value: bool) -> Result<(), TailscaleError> {
+        let mut state = self.lock();
+        match flag {
+            "accept-dns" => state.prefs.accept_dns = value,
+            "accept-routes" => state.prefs.accept_routes = value,
+            "exit-node-allow-lan-access" => state.prefs.exit_node_allow_lan_access = value,
+            "shields-up" => state.prefs.shields_up = value,
+            "ssh" => state.prefs.run_ssh = value,
+            "advertise-exit-node" => state.prefs.advertise_exit_node = value,
+            _ => {
+                return Err(TailscaleError::CommandFailed(format!(
+                    "tailscale set --{flag} failed: unknown flag"
+                )))
+            }
+        }
+        Ok(())
+    }
+
+    fn set_exit_node(&self, exit_node: Option<&PeerInfo>) -> Result<(), TailscaleError> {
+        let mut state = self.lock();
+        let id = exit_node.map(|p| p.id.clone()).unwrap_or_default();
+        let mut name = String::new();
+        for peer in &mut state.status.peers {
+            peer.exit_node = !id.is_empty() && peer.id == id;
+            if peer.exit_node {
+                name = peer.display_name().to_string();
+            }
+        }
+        state.status.exit_node_active = !name.is_empty();
+        state.status.exit_node_name = name;
+        state.prefs.exit_node_id = id;
+        Ok(())
+    }
+
+    /// Records the routes; approval stays as `status.json` has it.
+    fn set_advertised_routes(
+        &self,
+        routes: &[String],
+        exit_node: bool,
+    ) -> Result<(), TailscaleError> {
+        let routes = routes
+            .iter()
+            .map(|r| tailscale::parse_route(r))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(TailscaleError::CommandFailed)?;
+        let mut state = self.lock();
+        state.prefs.advertise_routes = routes;
+        state.prefs.advertise_exit_node = exit_node;
+        Ok(())
+    }
+
+    /// Suggests the online exit node with the highest location priority.
+    fn suggest_exit_node(&self) -> Result<ExitNodeSuggestion, TailscaleError> {
+        self.lock()
+            .status
+            .peers
+            .iter()
+            .filter(|p| p.exit_node_option && p.online)
+            .max_by_key(|p| p.location.as_ref().map_or(0, |l| l.priority))
+            .map(|p| ExitNodeSuggestion {
+                id: p.id.clone(),
+                name: p.dns_display(),
+            })
+            .ok_or_else(|| TailscaleError::CommandFailed("No exit node available".to_string()))
+    }
+
+    fn up(&self) -> Result<String, TailscaleError> {
+        self.lock().status.backend_state = BackendState::Running;
+        Ok("Connected".to_string())
+    }
+
+    fn down(&self) -> Result<String, TailscaleError> {
+        self.lock().status.backend_state = BackendState::Stopped;
+        Ok("Disconnected".to_string())
+    }
+
+    /// Logs straight in, as if the user had finished authenticating.
+    fn login(&self) -> Result<Option<String>, TailscaleError> {
+        self.lock().status.backend_state = BackendState::Running;
+        Ok(None)
+    }
+
+    fn profiles(&self) -> Result<Vec<LoginProfile>, TailscaleError> {
+        Ok(self.lock().profiles.clone())
+    }
+
+    fn switch_profile(&self, id: &str) -> Result<(), TailscaleError> {
+        let mut state = self.lock();
+        let Some(profile) = state.profiles.iter().find(|p| p.id == id).cloned() else {
+            return Err(TailscaleError::CommandFailed(format!(
+                "tailscale switch {id} failed: no such profile"
+            )));
+        };
+        for p in &mut state.profiles {
+            p.current = p.id == id;
+        }
+        state.status.tailnet_name = profile.tailnet;
+        state.prefs.login_name = profile.account;
+        Ok(())
+    }
+
+    /// Adds an unnamed profile that still needs to log in.
+    fn add_profile(&self) -> Result<Option<String>, TailscaleError> {
+        let mut state = self.lock();
+        for p in &mut state.profiles {
+            p.current = false;
+        }
+        let id = format!("fixture-{}", state.profiles.len());
+        state.profiles.push(LoginProfile {
+            id,
+            tailnet: String::new(),
+            account: String::new(),
+            current: true,
+        });
+        state.status.backend_state = BackendState::NeedsLogin;
+        state.status.tailnet_name.clear();
+        state.prefs.login_name.clear();
+        Ok(None)
+    }
+
+    fn logout(&self) -> Result<(), TailscaleError> {
+        let mut state = self.lock();
+        state.profiles.retain(|p| !p.current);
+        state.status.backend_state = BackendState::NeedsLogin;
+        state.prefs.login_name.clear();
+        Ok(())
+    }
+
+    fn probe_services(&self, ip: &str, _dns_name: &str, services: &[ServiceDef]) -> ServiceInfo {
+        let mut info = self.lock().services.get(ip).cloned().unwrap_or_default();
+        info.found.retain(|f| services.iter().any(|s| s.id == f.id));
+        info
+    }
+
+    fn serve_config(&self, self_dns: &str) -> Result<Vec<ServeEndpoint>, TailscaleError> {
+        match self.lock().serve_config {
+            Some(ref json) => tailscale::parse_serve_config(json.as_bytes(), self_dns),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    fn edit_serve(&self, self_dns: &str, edit: &ServeEdit) -> Result<(), TailscaleError> {
+        let mut state = self.lock();
+        let json = state.serve_config.as_deref().unwrap_or("null");
+        let edited = tailscale::apply_serve_edit(json.as_bytes(), self_dns, edit)?;
+        state.serve_config = Some(String::from_utf8_lossy(&edited).into_owned());
+        Ok(())
+    }
+
+    fn netcheck(&self) -> Result<NetcheckReport, TailscaleError> {
+        self.lock().netcheck.clone().ok_or_else(|| {
+            TailscaleError::CommandFailed("No netcheck.json in fixtures".to_string())
+        })
+    }
+
+    /// Our own untagged devices, as Taildrop allows without extra ACLs.
+    fn file_targets(&self) -> Result<Vec<FileTarget>, TailscaleError> {
+        let state = self.lock();
+        let user_id = state.status.self_node.user_id;
+        Ok(state
+            .status
+            .peers
+            .iter()
+            .filter(|p| p.online && p.tags.is_empty() && p.user_id == user_id)
+            .map(|p| FileTarget {
+                id: p.id.clone(),
+                name: p.dns_display(),
+                ips: p.tailscale_ips.clone(),
+            })
+            .collect())
+    }
+
+    /// Reads the file and reports it sent in quarters, without sending it.
+    fn send_file(
+        &self,
+        peer: &PeerInfo,
+        path: &Path,
+        on_progress: &mut dyn FnMut(u64),
+    ) -> Result<(), TailscaleError> {
+        let len = std::fs::metadata(path)
+            .map_err(|e| TailscaleError::CommandFailed(format!("{}: {e}", path.display())))?
+            .len();
+        if !self.file_targets()?.iter().any(|t| t.matches(peer)) {
+            return Err(TailscaleError::CommandFailed(format!(
+                "{} is not a Taildrop target",
+                peer.display_name()
+            )));
+        }
+        for quarter in 1..=4 {
+            std::thread::sleep(std::time::Duration::from_millis(250));
+            on_progress(len * quarter / 4);
+        }
+        Ok(())
+    }
+
+    fn waiting_files(&self) -> Result<Vec<WaitingFile>, TailscaleError> {
+        Ok(self.lock().inbox.clone())
+    }
+
+    fn save_waiting_file(&self, name: &str, dir: &Path) -> Result<PathBuf, TailscaleError> {
+        let mut state = self.lock();
+        let Some(pos) = state.inbox.iter().position(|f| f.name == name) else {
+            return Err(TailscaleError::CommandFailed(format!(
+                "{name}: no such waiting file"
+            )));
+        };
+        let io_err = |e: std::io::Error| TailscaleError::CommandFailed(format!("{name}: {e}"));
+        std::fs::create_dir_all(dir).map_err(io_err)?;
+        let (path, file) = tailscale::create_unique(dir, name).map_err(io_err)?;
+        file.set_len(state.inbox[pos].size).map_err(io_err)?;
+        state.inbox.remove(pos);
+        Ok(path)
+    }
+
+    fn delete_waiting_file(&self, name: &str) -> Result<(), TailscaleError> {
+        self.lock().inbox.retain(|f| f.name != name);
+        Ok(())
+    }
+
+    fn drive_shares(&self) -> Result<Vec<DriveShare>, TailscaleError> {
+        Ok(self.lock().shares.clone())
+    }
+
+    /// Checks the name and folder as tailscaled does, without sharing anything.
+    fn share_folder(&self, name: &str, path: &Path) -> Result<(), TailscaleError> {
+        if !tailscale::valid_share_name(name) {
+            return Err(invalid_share_name());
+        }
+        if !path.is_dir() {
+            return Err(TailscaleError::CommandFailed(format!(
+                "{} is not a folder",
+                path.display()
+            )));
+        }
+        let mut state = self.lock();
+        let share = DriveShare {
+            name: name.to_string(),
+            path: path.display().to_string(),
+        };
+        match state.shares.iter_mut().find(|s| s.name == name) {
+            Some(existing) => *existing = share,
+            None => state.shares.push(share),
+        }
+        state.shares.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(())
+    }
+
+    fn unshare_folder(&self, name: &str) -> Result<(), TailscaleError> {
+        let mut state = self.lock();
+        let before = state.shares.len();
+        state.shares.retain(|s| s.name != name);
+        if state.shares.len() == before {
+            return Err(TailscaleError::CommandFailed(format!(
+                "{name}: share not found"
+            )));
+        }
+        Ok(())
+    }
+
+    fn rename_share(&self, old: &str, new: &str) -> Result<(), TailscaleError> {
+        if !tailscale::valid_share_name(new) {
+            return Err(invalid_share_name());
+        }
+        let mut state = self.lock();
+        if state.shares.iter().any(|s| s.name == new) {
+            return Err(TailscaleError::CommandFailed(format!(
+                "{new}: share already exists"
+            )));
+        }
+        let Some(share) = state.shares.iter_mut().find(|s| s.name == old) else {
+            return Err(TailscaleError::CommandFailed(format!(
+                "{old}: share not found"
+            )));
+        };
+        share.name = new.to_string();
+        state.shares.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(())
+    }
+
+    fn peer_drives(&self, _tailnet: &str, peer_name: &str) -> Result<Vec<String>, TailscaleError> {
+        Ok(self
+            .lock()
+            .remote_drives
+            .get(peer_name)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    /// Replies once via the peer's relay, then directly if it has a `CurAddr`.
+    fn ping(&self, ip: &str, on_pong: &mut dyn FnMut(Pong)) -> Result<(), TailscaleError> {
+        let peer = self
+            .lock()
+            .status
+            .peers
+            .iter()
+            .find(|p| p.tailscale_ips.iter().any(|i| i == ip))
+            .cloned();
+        let Some(peer) = peer.filter(|p| p.online) else {
+            return Err(TailscaleError::CommandFailed(format!(
+                "ping \"{ip}\" timed out"
+            )));
+        };
+        on_pong(Pong {
+            latency_ms: 42.0,
+            path: PingPath::Derp(peer.relay.clone()),
+        });
+        if !peer.cur_addr.is_empty() {
+            on_pong(Pong {
+                latency_ms: 3.0,
+                path: PingPath::Direct(peer.cur_addr),
+            });
+        }
+        Ok(())
+    }
+
+    /// Fixtures are static, so there is no bus; callers fall back to polling.
+    fn watch_ipn(&self, _on_notify: &mut dyn FnMut(IpnNotify)) -> Result<(), TailscaleError> {
+        Err(TailscaleError::CommandFailed(
+            "No IPN bus in fixture mode".to_string(),
+        ))
+    }
+}
+
+fn invalid_share_name() -> TailscaleError {
+    TailscaleError::CommandFailed(
+        "Share names may only contain the letters a-z, underscore _, parentheses (), or spaces"
+            .to_string(),
+    )
+}
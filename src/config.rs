@@ -1,19 +1,350 @@
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Days before our node key expires that the panel icon starts warning.
+pub const DEFAULT_KEY_EXPIRY_WARNING_DAYS: u32 = 14;
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Credentials {
     pub username: String,
 }
 
+/// Settings saved for one tailnet, so accounts don't share usernames.
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
-pub struct AppConfig {
+pub struct TailnetConfig {
     #[serde(default)]
     pub ssh_usernames: HashMap<String, String>,
     /// Credentials keyed by "service:dns_name" (e.g. "rdp:myhost.tail1234.ts.net")
     #[serde(default)]
     pub credentials: HashMap<String, Credentials>,
+    /// Stable node IDs of peers pinned to the top of the peer list
+    #[serde(default)]
+    pub favorites: BTreeSet<String>,
+}
+
+/// How a service button opens the service.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Launcher {
+    /// ssh in a terminal emulator
+    Ssh,
+    /// vncviewer for RealVNC servers, otherwise Remmina
+    Vnc,
+    /// Remmina with a generated .remmina profile
+    Rdp,
+    /// nxplayer with a generated .nxs session
+    NoMachine,
+    /// Copy the peer's HTTPS URL
+    CopyHttpsUrl,
+    /// Command line template; `{host}`, `{ip}`, `{port}` and `{user}` are
+    /// filled in, and a `{user}` asks for a username first
+    Command(String),
+}
+
+/// How to find out more about a service once its port is open.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Fingerprint {
+    /// SSH server version from its identification string
+    Ssh,
+    /// Server header, page title and known apps from `GET /`
+    Http,
+    /// As `Http`, over TLS
+    Https,
+}
+
+/// A service to probe peers for and show a button for.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ServiceDef {
+    /// Stable key, also used for saved credentials
+    pub id: String,
+    pub name: String,
+    /// Ports to try in order; the first one open is used
+    pub ports: Vec<u16>,
+    /// Expected start of the greeting for protocols where the server speaks
+    /// first (e.g. "SSH-"); just a TCP connect when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub banner: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fingerprint: Option<Fingerprint>,
+    /// Icon name; names ending in "-symbolic" are drawn as symbolic icons
+    pub icon: String,
+    pub launch: Launcher,
+}
+
+impl ServiceDef {
+    fn new(id: &str, name: &str, ports: &[u16], icon: &str, launch: Launcher) -> Self {
+        Self {
+            id: id.to_string(),
+            name: name.to_string(),
+            ports: ports.to_vec(),
+            banner: None,
+            fingerprint: None,
+            icon: icon.to_string(),
+            launch,
+        }
+    }
+
+    fn with_banner(mut self, banner: &str) -> Self {
+        self.banner = Some(banner.to_string());
+        self
+    }
+
+    fn with_fingerprint(mut self, fingerprint: Fingerprint) -> Self {
+        self.fingerprint = Some(fingerprint);
+        self
+    }
+
+    /// Whether to ask for a username before launching.
+    pub fn asks_username(&self) -> bool {
+        match self.launch {
+            Launcher::Ssh | Launcher::Vnc | Launcher::Rdp | Launcher::NoMachine => true,
+            Launcher::CopyHttpsUrl => false,
+            Launcher::Command(ref template) => template.contains("{user}"),
+        }
+    }
+
+    /// The argument list for a `Command` launcher. A "{user}@" prefix is
+    /// dropped when no username was given.
+    pub fn command(&self, host: &str, ip: &str, port: u16, user: &str) -> Option<Vec<String>> {
+        let Launcher::Command(ref template) = self.launch else {
+            return None;
+        };
+        let args: Vec<String> = template
+            .split_whitespace()
+            .map(|arg| {
+                let arg = if user.is_empty() {
+                    arg.replace("{user}@", "")
+                } else {
+                    arg.to_string()
+                };
+                arg.replace("{host}", host)
+                    .replace("{ip}", ip)
+                    .replace("{port}", &port.to_string())
+                    .replace("{user}", user)
+            })
+            .collect();
+        (!args.is_empty()).then_some(args)
+    }
+}
+
+/// The services probed for unless the config file lists its own.
+pub fn default_services() -> Vec<ServiceDef> {
+    const APP_ICON: &str = "io.github.reality2_roycdavies.cosmic-tailscale";
+    vec![
+        ServiceDef::new(
+            "ssh",
+            "SSH",
+            &[22],
+            "utilities-terminal-symbolic",
+            Launcher::Ssh,
+        )
+        .with_banner("SSH-")
+        .with_fingerprint(Fingerprint::Ssh),
+        ServiceDef::new(
+            "vnc",
+            "VNC",
+            &[5900],
+            &format!("{APP_ICON}-vnc"),
+            Launcher::Vnc,
+        )
+        .with_banner("RFB "),
+        ServiceDef::new(
+            "rdp",
+            "RDP",
+            &[3389],
+            "folder-remote-symbolic",
+            Launcher::Rdp,
+        ),
+        ServiceDef::new(
+            "nomachine",
+            "NoMachine",
+            &[4000],
+            &format!("{APP_ICON}-nomachine"),
+            Launcher::NoMachine,
+        ),
+        ServiceDef::new(
+            "http",
+            "Web",
+            &[80],
+            "web-browser-symbolic",
+            Launcher::Command("xdg-open http://{host}".to_string()),
+        )
+        .with_fingerprint(Fingerprint::Http),
+        ServiceDef::new(
+            "https",
+            "HTTPS URL",
+            &[443],
+            "edit-copy-symbolic",
+            Launcher::CopyHttpsUrl,
+        )
+        .with_fingerprint(Fingerprint::Https),
+        ServiceDef::new(
+            "grafana",
+            "Grafana",
+            &[3000],
+            "utilities-system-monitor-symbolic",
+            Launcher::Command("xdg-open http://{host}:{port}".to_string()),
+        )
+        .with_fingerprint(Fingerprint::Http),
+        ServiceDef::new(
+            "jupyter",
+            "Jupyter",
+            &[8888],
+            "accessories-text-editor-symbolic",
+            Launcher::Command("xdg-open http://{host}:{port}".to_string()),
+        )
+        .with_fingerprint(Fingerprint::Http),
+        ServiceDef::new(
+            "proxmox",
+            "Proxmox",
+            &[8006],
+            "computer-symbolic",
+            Launcher::Command("xdg-open https://{host}:{port}".to_string()),
+        )
+        .with_fingerprint(Fingerprint::Https),
+    ]
+}
+
+fn is_default_services(services: &[ServiceDef]) -> bool {
+    services == default_services().as_slice()
+}
+
+/// Which peers may be port-scanned for services.
+///
+/// With any of `only_mine`, `tags` or `hosts` set, a peer is probed when it
+/// matches at least one of them; otherwise every online peer is.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProbeSettings {
+    pub enabled: bool,
+    /// Untagged peers owned by the logged-in user
+    pub only_mine: bool,
+    /// ACL tags, with or without the "tag:" prefix
+    pub tags: Vec<String>,
+    /// Host names, MagicDNS names or Tailscale IPs
+    pub hosts: Vec<String>,
+}
+
+impl Default for ProbeSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            only_mine: false,
+            tags: Vec::new(),
+            hosts: Vec::new(),
+        }
+    }
+}
+
+/// Which desktop notifications to send.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NotificationSettings {
+    /// The tunnel drops or comes back
+    pub tunnel: bool,
+    /// A favorite peer goes online or offline
+    pub favorite_peers: bool,
+    /// Our node key is about to expire
+    pub key_expiry: bool,
+    /// The exit node in use becomes unreachable
+    pub exit_node: bool,
+}
+
+impl Default for NotificationSettings {
+    fn default() -> Self {
+        Self {
+            tunnel: true,
+            favorite_peers: true,
+            key_expiry: true,
+            exit_node: true,
+        }
+    }
+}
+
+/// Where received Taildrop files go.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TaildropSettings {
+    /// Folder to save files in; the Downloads folder when unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub save_dir: Option<PathBuf>,
+    /// Save files as soon as they arrive instead of waiting for the user
+    pub auto_accept: bool,
+}
+
+impl TaildropSettings {
+    pub fn save_dir(&self) -> PathBuf {
+        self.save_dir
+            .clone()
+            .or_else(dirs::download_dir)
+            .unwrap_or_else(|| {
+                dirs::home_dir()
+                    .unwrap_or_else(|| PathBuf::from("~"))
+                    .join("Downloads")
+            })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AppConfig {
+    /// Pre-account settings, moved to the first tailnet seen.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    ssh_usernames: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    credentials: HashMap<String, Credentials>,
+    /// Per-tailnet settings keyed by tailnet name
+    #[serde(default)]
+    pub tailnets: HashMap<String, TailnetConfig>,
+    #[serde(default = "default_key_expiry_warning_days")]
+    pub key_expiry_warning_days: u32,
+    #[serde(default)]
+    pub notifications: NotificationSettings,
+    /// Services to probe peers for, in button order
+    #[serde(
+        default = "default_services",
+        skip_serializing_if = "is_default_services"
+    )]
+    pub services: Vec<ServiceDef>,
+    #[serde(default)]
+    pub probing: ProbeSettings,
+    #[serde(default)]
+    pub taildrop: TaildropSettings,
+    /// Modification time of the file when it was last read or written
+    #[serde(skip)]
+    loaded_mtime: Option<SystemTime>,
+    /// Why the file could not be parsed when last read. Saving is refused
+    /// until it is fixed, so hand edits are not overwritten.
+    #[serde(skip)]
+    load_error: Option<String>,
+    /// The file these settings are read from and saved to. Without one they
+    /// live in memory only, as for fixture runs and tests.
+    #[serde(skip)]
+    path: Option<PathBuf>,
+}
+
+fn default_key_expiry_warning_days() -> u32 {
+    DEFAULT_KEY_EXPIRY_WARNING_DAYS
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        Self {
+            ssh_usernames: HashMap::new(),
+            credentials: HashMap::new(),
+            tailnets: HashMap::new(),
+            key_expiry_warning_days: DEFAULT_KEY_EXPIRY_WARNING_DAYS,
+            notifications: NotificationSettings::default(),
+            services: default_services(),
+            probing: ProbeSettings::default(),
+            taildrop: TaildropSettings::default(),
+            loaded_mtime: None,
+            load_error: None,
+            path: None,
+        }
+    }
 }
 
 impl AppConfig {
@@ -24,34 +355,262 @@ impl AppConfig {
             .join("config.json")
     }
 
+    fn mtime(&self) -> Option<SystemTime> {
+        std::fs::metadata(self.path.as_ref()?)
+            .and_then(|m| m.modified())
+            .ok()
+    }
+
+    /// Load the user's config file; see [`Self::load_from`].
     pub fn load() -> Self {
-        let path = Self::config_path();
-        match std::fs::read_to_string(&path) {
-            Ok(contents) => serde_json::from_str(&contents).unwrap_or_default(),
-            Err(_) => Self::default(),
+        Self::load_from(Self::config_path())
+    }
+
+    /// Load the config at `path`, using defaults when there is no file yet.
+    /// A file that does not parse is reported and left alone; see
+    /// [`Self::load_error`].
+    pub fn load_from(path: PathBuf) -> Self {
+        let mut config = Self {
+            path: Some(path),
+            ..Self::default()
+        };
+        config.reload();
+        config
+    }
+
+    /// Read the file, or `None` when there is none yet.
+    fn read(path: &Path) -> Result<Option<Self>, String> {
+        match std::fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .map(Some)
+                .map_err(|e| format!("{}: {e}", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(format!("{}: {e}", path.display())),
         }
     }
 
-    pub fn save(&self) {
-        let path = Self::config_path();
+    /// Re-read the file. When it cannot be read the current settings are
+    /// kept and saving is refused until it is fixed.
+    fn reload(&mut self) {
+        let mtime = self.mtime();
+        let Some(path) = self.path.take() else {
+            return;
+        };
+        match Self::read(&path) {
+            Ok(config) => *self = config.unwrap_or_default(),
+            Err(e) => {
+                eprintln!("Failed to load config, keeping current settings: {e}");
+                self.load_error = Some(e);
+            }
+        }
+        self.path = Some(path);
+        self.loaded_mtime = mtime;
+    }
+
+    /// Re-read the file if another process (e.g. the settings app) changed
+    /// it. Returns whether it was reloaded.
+    pub fn reload_if_changed(&mut self) -> bool {
+        let mtime = self.mtime();
+        if mtime.is_none() || mtime == self.loaded_mtime {
+            return false;
+        }
+        self.reload();
+        self.load_error.is_none()
+    }
+
+    /// Re-read the file, apply `change` and save. The applet and the settings
+    /// page both write the file, so this keeps what the other one saved
+    /// since it was last read.
+    pub fn update(&mut self, change: impl FnOnce(&mut Self)) {
+        self.reload();
+        change(self);
+        self.save();
+    }
+
+    /// Why the file could not be read, while it is broken.
+    pub fn load_error(&self) -> Option<&str> {
+        self.load_error.as_deref()
+    }
+
+    pub fn save(&mut self) {
+        if let Some(ref e) = self.load_error {
+            eprintln!("Not saving config over a file that failed to load: {e}");
+            return;
+        }
+        let Some(ref path) = self.path else {
+            return;
+        };
         if let Some(parent) = path.parent() {
             let _ = std::fs::create_dir_all(parent);
         }
         if let Ok(json) = serde_json::to_string_pretty(self) {
-            if let Err(e) = std::fs::write(&path, json) {
+            if let Err(e) = std::fs::write(path, json) {
                 eprintln!("Failed to save config: {e}");
             }
+            self.loaded_mtime = self.mtime();
         }
     }
 
-    pub fn get_creds(&self, service: &str, dns_name: &str) -> Option<&Credentials> {
+    /// Move settings saved before they were per tailnet into `tailnet`.
+    pub fn adopt_legacy(&mut self, tailnet: &str) {
+        if self.ssh_usernames.is_empty() && self.credentials.is_empty() {
+            return;
+        }
+        self.update(|config| {
+            let ssh_usernames = std::mem::take(&mut config.ssh_usernames);
+            let credentials = std::mem::take(&mut config.credentials);
+            let scoped = config.tailnet_mut(tailnet);
+            for (host, user) in ssh_usernames {
+                scoped.ssh_usernames.entry(host).or_insert(user);
+            }
+            for (key, creds) in credentials {
+                scoped.credentials.entry(key).or_insert(creds);
+            }
+        });
+    }
+
+    pub fn tailnet_mut(&mut self, tailnet: &str) -> &mut TailnetConfig {
+        self.tailnets.entry(tailnet.to_string()).or_default()
+    }
+
+    pub fn is_favorite(&self, tailnet: &str, node_id: &str) -> bool {
+        self.tailnets
+            .get(tailnet)
+            .is_some_and(|t| t.favorites.contains(node_id))
+    }
+
+    /// Pin or unpin a peer by stable node ID.
+    pub fn toggle_favorite(&mut self, tailnet: &str, node_id: &str) {
+        self.update(|config| {
+            let favorites = &mut config.tailnet_mut(tailnet).favorites;
+            if !favorites.remove(node_id) {
+                favorites.insert(node_id.to_string());
+            }
+        });
+    }
+
+    pub fn get_creds(&self, tailnet: &str, service: &str, dns_name: &str) -> Option<&Credentials> {
         let key = format!("{service}:{dns_name}");
-        self.credentials.get(&key)
+        self.tailnets.get(tailnet)?.credentials.get(&key)
+    }
+
+    pub fn service(&self, id: &str) -> Option<&ServiceDef> {
+        self.services.iter().find(|s| s.id == id)
     }
 
-    pub fn save_creds(&mut self, service: &str, dns_name: &str, creds: Credentials) {
+    pub fn save_creds(&mut self, tailnet: &str, service: &str, dns_name: &str, creds: Credentials) {
         let key = format!("{service}:{dns_name}");
-        self.credentials.insert(key, creds);
-        self.save();
+        self.update(|config| {
+            config.tailnet_mut(tailnet).credentials.insert(key, creds);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A config file path in a fresh temporary directory.
+    fn config_file(name: &str, contents: Option<&str>) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "cosmic-tailscale-config-{}-{name}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.json");
+        if let Some(contents) = contents {
+            std::fs::write(&path, contents).unwrap();
+        }
+        path
+    }
+
+    fn creds(username: &str) -> Credentials {
+        Credentials {
+            username: username.to_string(),
+        }
+    }
+
+    #[test]
+    fn legacy_settings_move_into_the_first_tailnet() {
+        let path = config_file(
+            "legacy",
+            Some(
+                r#"{
+                    "ssh_usernames": {"nas": "root", "desk": "me"},
+                    "credentials": {"rdp:desk.tail1234.ts.net": {"username": "me"}},
+                    "tailnets": {"work.example": {"ssh_usernames": {"nas": "admin"}}}
+                }"#,
+            ),
+        );
+        let mut config = AppConfig::load_from(path.clone());
+        config.adopt_legacy("work.example");
+
+        for config in [config, AppConfig::load_from(path)] {
+            assert!(config.ssh_usernames.is_empty());
+            assert!(config.credentials.is_empty());
+            let work = &config.tailnets["work.example"];
+            // What the tailnet already had wins
+            assert_eq!(work.ssh_usernames["nas"], "admin");
+            assert_eq!(work.ssh_usernames["desk"], "me");
+            let rdp = config.get_creds("work.example", "rdp", "desk.tail1234.ts.net");
+            assert_eq!(rdp.unwrap().username, "me");
+        }
+    }
+
+    #[test]
+    fn credentials_stay_in_their_tailnet() {
+        let path = config_file("scoped", None);
+        let mut config = AppConfig::load_from(path.clone());
+        config.save_creds("work.example", "rdp", "desk.ts.net", creds("alice"));
+        config.save_creds("home.example", "rdp", "desk.ts.net", creds("bob"));
+
+        let config = AppConfig::load_from(path);
+        let user = |tailnet| {
+            config
+                .get_creds(tailnet, "rdp", "desk.ts.net")
+                .map(|c| c.username.as_str())
+        };
+        assert_eq!(user("work.example"), Some("alice"));
+        assert_eq!(user("home.example"), Some("bob"));
+        assert_eq!(user("other.example"), None);
+        assert!(config.get_creds("work.example", "ssh", "desk.ts.net").is_none());
+    }
+
+    #[test]
+    fn update_keeps_what_another_process_saved() {
+        let path = config_file("merge", None);
+        let mut applet = AppConfig::load_from(path.clone());
+        let mut settings = AppConfig::load_from(path.clone());
+
+        applet.toggle_favorite("work.example", "nNas3CNTRL");
+        settings.update(|config| config.key_expiry_warning_days = 3);
+
+        let config = AppConfig::load_from(path);
+        assert!(config.is_favorite("work.example", "nNas3CNTRL"));
+        assert_eq!(config.key_expiry_warning_days, 3);
+    }
+
+    #[test]
+    fn malformed_file_is_not_overwritten() {
+        let broken = r#"{"key_expiry_warning_days": 7,"#;
+        let path = config_file("malformed", Some(broken));
+        let mut config = AppConfig::load_from(path.clone());
+        assert!(config.load_error().is_some());
+        // Defaults stand in while the file is broken
+        assert_eq!(config.key_expiry_warning_days, DEFAULT_KEY_EXPIRY_WARNING_DAYS);
+
+        config.toggle_favorite("work.example", "nNas3CNTRL");
+        config.save();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), broken);
+        assert!(config.load_error().is_some());
+    }
+
+    #[test]
+    fn in_memory_config_never_touches_disk() {
+        let mut config = AppConfig::default();
+        config.toggle_favorite("work.example", "nNas3CNTRL");
+        assert!(config.is_favorite("work.example", "nNas3CNTRL"));
+        assert!(!config.reload_if_changed());
     }
 }
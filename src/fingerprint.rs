@@ -0,0 +1,327 @@
+//! Identify what is listening on a port from what it sends back.
+//!
+//! These only parse bytes already read from a peer; the network side lives
+//! with the rest of the port probing in `tailscale.rs`.
+
+/// Parts of an SSH identification string ("SSH-2.0-OpenSSH_9.6p1 Ubuntu-3").
+#[derive(Debug, Clone, PartialEq)]
+pub struct SshBanner {
+    pub software: String,
+    pub comments: String,
+}
+
+impl SshBanner {
+    /// Readable server version, e.g. "OpenSSH 9.6p1 (Ubuntu-3)".
+    pub fn label(&self) -> String {
+        let software = self.software.replacen('_', " ", 1);
+        if self.comments.is_empty() {
+            software
+        } else {
+            format!("{software} ({})", self.comments)
+        }
+    }
+}
+
+/// Parse the identification line an SSH server sends on connect (RFC 4253
+/// section 4.2). Servers may send other lines first; those are skipped.
+pub fn parse_ssh_banner(data: &[u8]) -> Option<SshBanner> {
+    let text = String::from_utf8_lossy(data);
+    let line = text.lines().find(|l| l.starts_with("SSH-"))?;
+    let rest = line.trim_end_matches('\r').strip_prefix("SSH-")?;
+    let (_protocol, rest) = rest.split_once('-')?;
+    let (software, comments) = match rest.split_once(' ') {
+        Some((software, comments)) => (software, comments.trim()),
+        None => (rest, ""),
+    };
+    if software.is_empty() {
+        return None;
+    }
+    Some(SshBanner {
+        software: software.to_string(),
+        comments: comments.to_string(),
+    })
+}
+
+/// What an HTTP server said in reply to `GET /`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HttpInfo {
+    pub status: u16,
+    pub server: Option<String>,
+    /// Redirect target, for 3xx replies
+    pub location: Option<String>,
+    pub title: Option<String>,
+    /// Start of the body, for spotting apps that set no useful title
+    pub body: String,
+}
+
+/// Parse a raw HTTP/1.x response (status line, headers and body).
+pub fn parse_http_response(data: &[u8]) -> Option<HttpInfo> {
+    let text = String::from_utf8_lossy(data);
+    let (head, body) = match text.split_once("\r\n\r\n") {
+        Some(parts) => parts,
+        None => text.split_once("\n\n").unwrap_or((&text, "")),
+    };
+    let mut lines = head.lines();
+    let status_line = lines.next()?;
+    if !status_line.starts_with("HTTP/") {
+        return None;
+    }
+    let status = status_line.split_whitespace().nth(1)?.parse().ok()?;
+
+    let mut info = HttpInfo {
+        status,
+        ..Default::default()
+    };
+    for line in lines {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        if value.is_empty() {
+            continue;
+        }
+        if name.eq_ignore_ascii_case("server") {
+            info.server = Some(value.to_string());
+        } else if name.eq_ignore_ascii_case("location") {
+            info.location = Some(value.to_string());
+        }
+    }
+    info.title = html_title(body);
+    info.body = body.chars().take(4096).collect();
+    Some(info)
+}
+
+/// Text of the first `<title>` element, with whitespace collapsed.
+pub fn html_title(html: &str) -> Option<String> {
+    let lower = html.to_ascii_lowercase();
+    let open = lower.find("<title")?;
+    let start = open + lower[open..].find('>')? + 1;
+    let end = start + lower[start..].find("</title")?;
+    let title = decode_entities(&html[start..end])
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+    (!title.is_empty()).then_some(title)
+}
+
+fn decode_entities(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&nbsp;", " ")
+        .replace("&amp;", "&")
+}
+
+/// Well-known self-hosted apps, recognized from their front page.
+pub fn recognize_app(info: &HttpInfo) -> Option<&'static str> {
+    let title = info
+        .title
+        .as_deref()
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    let server = info
+        .server
+        .as_deref()
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    let location = info.location.as_deref().unwrap_or_default();
+    let body = &info.body;
+
+    if title.contains("home assistant") || body.contains("<home-assistant") {
+        Some("Home Assistant")
+    } else if title.contains("jellyfin") || location.ends_with("web/index.html") {
+        Some("Jellyfin")
+    } else if title.contains("proxmox") || server.starts_with("pve-api-daemon") {
+        Some("Proxmox")
+    } else if title.contains("synology") || body.contains("SYNO.SDS") {
+        Some("Synology DSM")
+    } else if title.contains("code-server") || body.contains("code-server") {
+        Some("code-server")
+    } else if title == "grafana" || body.contains("grafana-app") {
+        Some("Grafana")
+    } else if title.contains("jupyter") {
+        Some("Jupyter")
+    } else {
+        None
+    }
+}
+
+/// One-line summary for the detail page, e.g. "nginx/1.24.0 · Welcome".
+pub fn http_label(info: &HttpInfo) -> String {
+    let parts: Vec<&str> = [info.server.as_deref(), info.title.as_deref()]
+        .into_iter()
+        .flatten()
+        .collect();
+    if parts.is_empty() {
+        format!("HTTP {}", info.status)
+    } else {
+        parts.join(" · ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn http(
+        title: Option<&str>,
+        server: Option<&str>,
+        location: Option<&str>,
+        body: &str,
+    ) -> HttpInfo {
+        HttpInfo {
+            status: 200,
+            server: server.map(str::to_string),
+            location: location.map(str::to_string),
+            title: title.map(str::to_string),
+            body: body.to_string(),
+        }
+    }
+
+    #[test]
+    fn openssh_banner_with_comments() {
+        let banner = parse_ssh_banner(b"SSH-2.0-OpenSSH_9.6p1 Ubuntu-3ubuntu13.5\r\n").unwrap();
+        assert_eq!(banner.software, "OpenSSH_9.6p1");
+        assert_eq!(banner.comments, "Ubuntu-3ubuntu13.5");
+        assert_eq!(banner.label(), "OpenSSH 9.6p1 (Ubuntu-3ubuntu13.5)");
+    }
+
+    #[test]
+    fn ssh_banner_without_comments() {
+        let banner = parse_ssh_banner(b"SSH-2.0-OpenSSH_9.8\r\n").unwrap();
+        assert_eq!(banner.comments, "");
+        assert_eq!(banner.label(), "OpenSSH 9.8");
+
+        let banner = parse_ssh_banner(b"SSH-2.0-dropbear_2022.83\n").unwrap();
+        assert_eq!(banner.label(), "dropbear 2022.83");
+    }
+
+    #[test]
+    fn ssh_banner_after_other_lines() {
+        let data = b"Authorized use only\r\nAll access is logged\r\nSSH-2.0-OpenSSH_8.4p1 Debian-5+deb11u3\r\n";
+        let banner = parse_ssh_banner(data).unwrap();
+        assert_eq!(banner.label(), "OpenSSH 8.4p1 (Debian-5+deb11u3)");
+    }
+
+    #[test]
+    fn not_an_ssh_banner() {
+        assert_eq!(parse_ssh_banner(b"HTTP/1.1 400 Bad Request\r\n"), None);
+        assert_eq!(parse_ssh_banner(b"SSH-2.0-\r\n"), None);
+        assert_eq!(parse_ssh_banner(b""), None);
+    }
+
+    #[test]
+    fn http_response_with_crlf() {
+        let data = b"HTTP/1.1 200 OK\r\n\
+            Server: nginx/1.24.0 (Ubuntu)\r\n\
+            Content-Type: text/html\r\n\
+            \r\n\
+            <!DOCTYPE html>\n<html>\n<head>\n<title>Welcome to nginx!</title>\n</head>";
+        let info = parse_http_response(data).unwrap();
+        assert_eq!(info.status, 200);
+        assert_eq!(info.server.as_deref(), Some("nginx/1.24.0 (Ubuntu)"));
+        assert_eq!(info.title.as_deref(), Some("Welcome to nginx!"));
+        assert!(info.body.starts_with("<!DOCTYPE html>"));
+        assert_eq!(
+            http_label(&info),
+            "nginx/1.24.0 (Ubuntu) · Welcome to nginx!"
+        );
+    }
+
+    #[test]
+    fn http_response_with_bare_newlines() {
+        let data = b"HTTP/1.0 302 Found\nLocation: web/index.html\nServer: Kestrel\n\n";
+        let info = parse_http_response(data).unwrap();
+        assert_eq!(info.status, 302);
+        assert_eq!(info.location.as_deref(), Some("web/index.html"));
+        assert_eq!(info.server.as_deref(), Some("Kestrel"));
+        assert_eq!(info.title, None);
+        assert_eq!(recognize_app(&info), Some("Jellyfin"));
+    }
+
+    #[test]
+    fn not_an_http_response() {
+        assert_eq!(parse_http_response(b"SSH-2.0-OpenSSH_9.8\r\n"), None);
+        assert_eq!(parse_http_response(b"HTTP/1.1 OK\r\n\r\n"), None);
+        let info = parse_http_response(b"HTTP/1.1 204 No Content\r\n\r\n").unwrap();
+        assert_eq!(http_label(&info), "HTTP 204");
+    }
+
+    #[test]
+    fn title_with_entities() {
+        let html = "<head><TITLE lang=\"en\">\n  Tom &amp; Jerry&#39;s&nbsp;&lt;NAS&gt; &quot;home&quot;\n</TITLE>";
+        assert_eq!(
+            html_title(html).as_deref(),
+            Some("Tom & Jerry's <NAS> \"home\"")
+        );
+        assert_eq!(html_title("<title>  </title>"), None);
+        assert_eq!(html_title("<title>never closed"), None);
+    }
+
+    #[test]
+    fn recognizes_apps() {
+        let cases = [
+            (
+                http(Some("Home Assistant"), None, None, ""),
+                "Home Assistant",
+            ),
+            (
+                http(None, None, None, "<body><home-assistant></home-assistant>"),
+                "Home Assistant",
+            ),
+            (http(Some("Jellyfin"), None, None, ""), "Jellyfin"),
+            (http(None, None, Some("web/index.html"), ""), "Jellyfin"),
+            (
+                http(Some("pve1 - Proxmox Virtual Environment"), None, None, ""),
+                "Proxmox",
+            ),
+            (http(None, Some("pve-api-daemon/3.0"), None, ""), "Proxmox"),
+            (
+                http(Some("Synology DiskStation"), None, None, ""),
+                "Synology DSM",
+            ),
+            (
+                http(None, None, None, "SYNO.SDS.Session = {};"),
+                "Synology DSM",
+            ),
+            (http(Some("code-server"), None, None, ""), "code-server"),
+            (
+                http(
+                    None,
+                    None,
+                    None,
+                    "<meta id=\"vscode-workbench-web-configuration\"> code-server",
+                ),
+                "code-server",
+            ),
+            (http(Some("Grafana"), None, None, ""), "Grafana"),
+            (
+                http(None, None, None, "<div class=\"grafana-app\">"),
+                "Grafana",
+            ),
+            (
+                http(
+                    Some("Home Page - Select or create a notebook - JupyterLab"),
+                    None,
+                    None,
+                    "",
+                ),
+                "Jupyter",
+            ),
+        ];
+        for (info, app) in cases {
+            assert_eq!(recognize_app(&info), Some(app), "{info:?}");
+        }
+
+        assert_eq!(
+            recognize_app(&http(Some("Welcome to nginx!"), Some("nginx"), None, "")),
+            None
+        );
+        // A dashboard that merely mentions Grafana is not Grafana
+        assert_eq!(
+            recognize_app(&http(Some("Grafana dashboards"), None, None, "")),
+            None
+        );
+    }
+}
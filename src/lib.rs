@@ -1,2 +1,8 @@
+pub mod backend;
+pub mod config;
+pub mod fingerprint;
+mod localapi;
+pub mod notifications;
+pub mod portal;
 pub mod settings_page;
 pub mod tailscale;
@@ -0,0 +1,466 @@
+//! Minimal HTTP client for the tailscaled LocalAPI.
+//!
+//! tailscaled serves its LocalAPI over a Unix socket. Talking to it directly
+//! avoids spawning the `tailscale` binary for every status poll and gives us
+//! the same JSON the CLI is built on.
+
+use std::fmt;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+pub const DEFAULT_SOCKET: &str = "/var/run/tailscale/tailscaled.sock";
+
+/// Host header tailscaled expects for LocalAPI requests.
+const LOCALAPI_HOST: &str = "local-tailscaled.sock";
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long a streaming response may go quiet before reads fail. The IPN bus
+/// watcher reopens the stream afterwards, so a wedged tailscaled costs a
+/// reconnect instead of a hung thread.
+const STREAM_IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+#[derive(Debug)]
+pub enum LocalApiError {
+    /// The socket could not be opened, so the caller may fall back to the CLI.
+    Unavailable(String),
+    /// tailscaled answered with a non-success HTTP status.
+    Status(u16, String),
+    /// The request was sent but the exchange failed or was malformed.
+    Protocol(String),
+}
+
+impl fmt::Display for LocalApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unavailable(e) => write!(f, "LocalAPI unavailable: {e}"),
+            Self::Status(code, body) => write!(f, "LocalAPI returned {code}: {}", body.trim()),
+            Self::Protocol(e) => write!(f, "LocalAPI error: {e}"),
+        }
+    }
+}
+
+pub struct LocalApi {
+    socket_path: PathBuf,
+}
+
+impl Default for LocalApi {
+    fn default() -> Self {
+        Self::new(DEFAULT_SOCKET)
+    }
+}
+
+impl LocalApi {
+    pub fn new(socket_path: impl AsRef<Path>) -> Self {
+        Self {
+            socket_path: socket_path.as_ref().to_path_buf(),
+        }
+    }
+
+    pub fn get(&self, path: &str) -> Result<Vec<u8>, LocalApiError> {
+        self.request("GET", path, None)
+    }
+
+    /// GET `path`, calling `fallback` (e.g. the equivalent CLI command) when
+    /// the socket cannot be opened.
+    pub fn get_or_else<E: From<LocalApiError>>(
+        &self,
+        path: &str,
+        fallback: impl FnOnce() -> Result<Vec<u8>, E>,
+    ) -> Result<Vec<u8>, E> {
+        match self.get(path) {
+            Ok(body) => Ok(body),
+            Err(LocalApiError::Unavailable(_)) => fallback(),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn post(&self, path: &str) -> Result<Vec<u8>, LocalApiError> {
+        self.request("POST", path, Some(&[]))
+    }
+
+    pub fn put(&self, path: &str) -> Result<Vec<u8>, LocalApiError> {
+        self.request("PUT", path, Some(&[]))
+    }
+
+    pub fn delete(&self, path: &str) -> Result<Vec<u8>, LocalApiError> {
+        self.request("DELETE", path, None)
+    }
+
+    pub fn patch_json(
+        &self,
+        path: &str,
+        body: &serde_json::Value,
+    ) -> Result<Vec<u8>, LocalApiError> {
+        let body = serde_json::to_vec(body).map_err(|e| LocalApiError::Protocol(e.to_string()))?;
+        self.request("PATCH", path, Some(&body))
+    }
+
+    /// Send a request and return the response body of a 2xx reply.
+    pub fn request(
+        &self,
+        method: &str,
+        path: &str,
+        body: Option<&[u8]>,
+    ) -> Result<Vec<u8>, LocalApiError> {
+        let mut reader = self.open(method, path, body, REQUEST_TIMEOUT)?;
+        let mut body = Vec::new();
+        reader
+            .read_to_end(&mut body)
+            .map_err(|e| LocalApiError::Protocol(format!("reading body: {e}")))?;
+        Ok(body)
+    }
+
+    /// PUT `len` bytes read from `body` (e.g. a file for `file-put`), calling
+    /// `on_progress` with the running total as they are written.
+    pub fn upload(
+        &self,
+        path: &str,
+        body: &mut dyn Read,
+        len: u64,
+        on_progress: &mut dyn FnMut(u64),
+    ) -> Result<Vec<u8>, LocalApiError> {
+        let stream = self.connect(REQUEST_TIMEOUT)?;
+        let io_err = |e: std::io::Error| LocalApiError::Protocol(format!("sending body: {e}"));
+
+        write_head(
+            &stream,
+            "PUT",
+            path,
+            Some(("application/octet-stream", len)),
+        )?;
+        let mut buf = vec![0u8; 64 * 1024];
+        let mut sent = 0;
+        while sent < len {
+            let n = body.read(&mut buf).map_err(io_err)?;
+            if n == 0 {
+                return Err(LocalApiError::Protocol(format!(
+                    "body ended after {sent} of {len} bytes"
+                )));
+            }
+            (&stream).write_all(&buf[..n]).map_err(io_err)?;
+            sent += n as u64;
+            on_progress(sent);
+        }
+        (&stream).flush().map_err(io_err)?;
+
+        let mut reader = read_response(stream)?;
+        let mut reply = Vec::new();
+        reader
+            .read_to_end(&mut reply)
+            .map_err(|e| LocalApiError::Protocol(format!("reading body: {e}")))?;
+        Ok(reply)
+    }
+
+    /// GET a long-lived streaming endpoint (e.g. `watch-ipn-bus`) and return
+    /// its body as a reader that yields data as tailscaled sends it. Reads
+    /// fail once nothing arrives for [`STREAM_IDLE_TIMEOUT`].
+    pub fn stream(&self, path: &str) -> Result<Box<dyn Read + Send>, LocalApiError> {
+        self.open("GET", path, None, STREAM_IDLE_TIMEOUT)
+    }
+
+    fn open(
+        &self,
+        method: &str,
+        path: &str,
+        body: Option<&[u8]>,
+        read_timeout: Duration,
+    ) -> Result<Box<dyn Read + Send>, LocalApiError> {
+        let stream = self.connect(read_timeout)?;
+        send_request(&stream, method, path, body)?;
+        read_response(stream)
+    }
+
+    fn connect(&self, read_timeout: Duration) -> Result<UnixStream, LocalApiError> {
+        let stream = UnixStream::connect(&self.socket_path).map_err(|e| {
+            LocalApiError::Unavailable(format!("{}: {e}", self.socket_path.display()))
+        })?;
+        let _ = stream.set_read_timeout(Some(read_timeout));
+        let _ = stream.set_write_timeout(Some(REQUEST_TIMEOUT));
+        Ok(stream)
+    }
+}
+
+/// Read the response head and return the body of a 2xx reply.
+fn read_response(stream: UnixStream) -> Result<Box<dyn Read + Send>, LocalApiError> {
+    let mut reader = BufReader::new(stream);
+    let head = read_head(&mut reader)?;
+
+    let mut body: Box<dyn Read + Send> = if head.chunked {
+        Box::new(ChunkedReader::new(reader))
+    } else if let Some(len) = head.content_length {
+        Box::new(reader.take(len as u64))
+    } else {
+        Box::new(reader)
+    };
+
+    if !(200..300).contains(&head.status) {
+        let mut text = Vec::new();
+        let _ = body.read_to_end(&mut text);
+        return Err(LocalApiError::Status(
+            head.status,
+            String::from_utf8_lossy(&text).into_owned(),
+        ));
+    }
+
+    Ok(body)
+}
+
+fn send_request(
+    mut stream: &UnixStream,
+    method: &str,
+    path: &str,
+    body: Option<&[u8]>,
+) -> Result<(), LocalApiError> {
+    let content = body.map(|body| ("application/json", body.len() as u64));
+    write_head(stream, method, path, content)?;
+
+    let io_err = |e: std::io::Error| LocalApiError::Protocol(format!("sending request: {e}"));
+    if let Some(body) = body {
+        stream.write_all(body).map_err(io_err)?;
+    }
+    stream.flush().map_err(io_err)
+}
+
+/// Write the request line and headers; `content` is the body's type and length.
+fn write_head(
+    mut stream: &UnixStream,
+    method: &str,
+    path: &str,
+    content: Option<(&str, u64)>,
+) -> Result<(), LocalApiError> {
+    let mut req = format!(
+        "{method} {path} HTTP/1.1\r\n\
+         Host: {LOCALAPI_HOST}\r\n\
+         Sec-Tailscale: localapi\r\n\
+         Connection: close\r\n"
+    );
+    if let Some((content_type, len)) = content {
+        req.push_str(&format!("Content-Type: {content_type}\r\n"));
+        req.push_str(&format!("Content-Length: {len}\r\n"));
+    }
+    req.push_str("\r\n");
+
+    stream
+        .write_all(req.as_bytes())
+        .map_err(|e| LocalApiError::Protocol(format!("sending request: {e}")))
+}
+
+struct ResponseHead {
+    status: u16,
+    content_length: Option<usize>,
+    chunked: bool,
+}
+
+fn read_head(reader: &mut impl BufRead) -> Result<ResponseHead, LocalApiError> {
+    let mut line = String::new();
+    reader
+        .read_line(&mut line)
+        .map_err(|e| LocalApiError::Protocol(format!("reading status line: {e}")))?;
+
+    // "HTTP/1.1 200 OK"
+    let status = line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|s| s.parse::<u16>().ok())
+        .ok_or_else(|| LocalApiError::Protocol(format!("bad status line: {}", line.trim())))?;
+
+    let mut head = ResponseHead {
+        status,
+        content_length: None,
+        chunked: false,
+    };
+
+    loop {
+        line.clear();
+        let n = reader
+            .read_line(&mut line)
+            .map_err(|e| LocalApiError::Protocol(format!("reading headers: {e}")))?;
+        let header = line.trim_end();
+        if n == 0 || header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            let value = value.trim();
+            if name.eq_ignore_ascii_case("content-length") {
+                head.content_length = value.parse().ok();
+            } else if name.eq_ignore_ascii_case("transfer-encoding") {
+                head.chunked = value.eq_ignore_ascii_case("chunked");
+            }
+        }
+    }
+
+    Ok(head)
+}
+
+/// Decodes an HTTP/1.1 chunked body as a plain byte stream.
+struct ChunkedReader<R> {
+    inner: R,
+    remaining: usize,
+    done: bool,
+}
+
+impl<R: BufRead> ChunkedReader<R> {
+    fn new(inner: R) -> Self {
+        Self {
+            inner,
+            remaining: 0,
+            done: false,
+        }
+    }
+
+    fn next_chunk(&mut self) -> std::io::Result<()> {
+        let mut line = String::new();
+        self.inner.read_line(&mut line)?;
+        // Chunk extensions (";name=value") are allowed after the size.
+        let size = line.trim().split(';').next().unwrap_or("");
+        self.remaining = usize::from_str_radix(size, 16).map_err(|_| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("bad chunk size: {:?}", line.trim()),
+            )
+        })?;
+        if self.remaining == 0 {
+            self.done = true;
+        }
+        Ok(())
+    }
+}
+
+impl<R: BufRead> Read for ChunkedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.done || buf.is_empty() {
+            return Ok(0);
+        }
+        if self.remaining == 0 {
+            self.next_chunk()?;
+            if self.done {
+                return Ok(0);
+            }
+        }
+
+        let max = buf.len().min(self.remaining);
+        let n = self.inner.read(&mut buf[..max])?;
+        if n == 0 {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        self.remaining -= n;
+        if self.remaining == 0 {
+            // Each chunk is followed by CRLF.
+            let mut crlf = [0u8; 2];
+            self.inner.read_exact(&mut crlf)?;
+        }
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixListener;
+    use std::thread::JoinHandle;
+
+    /// A socket path in a fresh temporary directory.
+    fn socket_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "cosmic-tailscale-localapi-{}-{name}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.join("tailscaled.sock")
+    }
+
+    /// Answer one connection with `response`, returning the request head.
+    fn serve_once(path: &Path, response: &'static str) -> JoinHandle<String> {
+        let listener = UnixListener::bind(path).unwrap();
+        std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(&stream);
+            let mut head = String::new();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.is_empty() || line == "\r\n" {
+                    break;
+                }
+                head.push_str(&line);
+            }
+            (&stream).write_all(response.as_bytes()).unwrap();
+            head
+        })
+    }
+
+    #[test]
+    fn reads_content_length_body() {
+        let path = socket_path("content-length");
+        let server = serve_once(
+            &path,
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 11\r\n\r\n{\"a\": true}trailing",
+        );
+
+        let body = LocalApi::new(&path).get("/localapi/v0/status").unwrap();
+        assert_eq!(body, b"{\"a\": true}");
+
+        let head = server.join().unwrap();
+        assert!(head.starts_with("GET /localapi/v0/status HTTP/1.1\r\n"));
+        assert!(head.contains("Host: local-tailscaled.sock\r\n"));
+        assert!(head.contains("Sec-Tailscale: localapi\r\n"));
+    }
+
+    #[test]
+    fn reads_chunked_body() {
+        let path = socket_path("chunked");
+        let server = serve_once(
+            &path,
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+             5\r\nhello\r\n7;name=value\r\n, world\r\n0\r\n\r\n",
+        );
+
+        let body = LocalApi::new(&path).get("/localapi/v0/prefs").unwrap();
+        assert_eq!(body, b"hello, world");
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn maps_error_status() {
+        let path = socket_path("status");
+        let server = serve_once(
+            &path,
+            "HTTP/1.1 403 Forbidden\r\nContent-Length: 14\r\n\r\naccess denied\n",
+        );
+
+        match LocalApi::new(&path).post("/localapi/v0/login-interactive") {
+            Err(LocalApiError::Status(403, body)) => assert_eq!(body, "access denied\n"),
+            other => panic!("expected a 403, got {other:?}"),
+        }
+        let head = server.join().unwrap();
+        assert!(head.starts_with("POST /localapi/v0/login-interactive HTTP/1.1\r\n"));
+        assert!(head.contains("Content-Length: 0\r\n"));
+    }
+
+    #[test]
+    fn missing_socket_falls_back_to_cli() {
+        let api = LocalApi::new(socket_path("missing"));
+        assert!(matches!(
+            api.get("/localapi/v0/status"),
+            Err(LocalApiError::Unavailable(_))
+        ));
+
+        let body: Result<_, LocalApiError> =
+            api.get_or_else("/localapi/v0/status", || Ok(b"from cli".to_vec()));
+        assert_eq!(body.unwrap(), b"from cli");
+    }
+
+    #[test]
+    fn error_status_does_not_fall_back() {
+        let path = socket_path("no-fallback");
+        let server = serve_once(&path, "HTTP/1.1 500 Internal Server Error\r\n\r\n");
+
+        let result: Result<_, LocalApiError> =
+            LocalApi::new(&path).get_or_else("/localapi/v0/status", || panic!("fell back"));
+        assert!(matches!(result, Err(LocalApiError::Status(500, _))));
+        server.join().unwrap();
+    }
+}
@@ -1,5 +1,11 @@
 mod applet;
+mod backend;
 mod config;
+mod fingerprint;
+mod localapi;
+mod notifications;
+mod portal;
+mod probe;
 mod settings;
 mod settings_cli;
 mod settings_page;
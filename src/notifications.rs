@@ -0,0 +1,466 @@
+//! Desktop notifications for connectivity and peer events.
+//!
+//! [`detect_events`] compares two consecutive [`TailscaleStatus`] snapshots,
+//! and [`Notifier`] shows the results through `org.freedesktop.Notifications`.
+//! Point `COSMIC_TAILSCALE_NOTIFY_BUS` at a private bus address to send them
+//! to a fake notification daemon instead of the session bus.
+
+use std::collections::{BTreeSet, HashMap};
+
+use zbus::zvariant::Value;
+use zbus::Connection;
+
+use crate::config::NotificationSettings;
+use crate::tailscale::{BackendState, TailscaleStatus};
+
+/// D-Bus address to send notifications to instead of the session bus.
+const NOTIFY_BUS_ENV: &str = "COSMIC_TAILSCALE_NOTIFY_BUS";
+
+const APP_NAME: &str = "Tailscale";
+const APP_ID: &str = "io.github.reality2_roycdavies.cosmic-tailscale";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NotifyEvent {
+    TunnelDown,
+    TunnelUp,
+    /// A favorite peer came online (display name)
+    PeerOnline(String),
+    /// A favorite peer went offline (display name)
+    PeerOffline(String),
+    /// Our node key expires at this unix time
+    KeyExpiring(i64),
+    /// The exit node in use stopped responding (display name)
+    ExitNodeUnreachable(String),
+}
+
+impl NotifyEvent {
+    pub fn summary(&self) -> String {
+        match self {
+            Self::TunnelDown => "Tailscale disconnected".to_string(),
+            Self::TunnelUp => "Tailscale connected".to_string(),
+            Self::PeerOnline(name) => format!("{name} is online"),
+            Self::PeerOffline(name) => format!("{name} went offline"),
+            Self::KeyExpiring(_) => "Tailscale key expiring".to_string(),
+            Self::ExitNodeUnreachable(name) => format!("Exit node {name} unreachable"),
+        }
+    }
+
+    pub fn body(&self, now: i64) -> String {
+        match self {
+            Self::TunnelDown => "The tunnel to your tailnet dropped.".to_string(),
+            Self::TunnelUp => "The tunnel to your tailnet is back up.".to_string(),
+            Self::PeerOnline(_) | Self::PeerOffline(_) => String::new(),
+            Self::KeyExpiring(expiry) => {
+                let days = (expiry - now).max(0) / 86_400;
+                match days {
+                    0 => "This device's key expires today. Re-authenticate to stay connected."
+                        .to_string(),
+                    1 => "This device's key expires tomorrow. Re-authenticate to stay connected."
+                        .to_string(),
+                    n => format!(
+                        "This device's key expires in {n} days. Re-authenticate to stay connected."
+                    ),
+                }
+            }
+            Self::ExitNodeUnreachable(_) => {
+                "Internet traffic through the exit node is not getting through.".to_string()
+            }
+        }
+    }
+
+    fn icon(&self) -> String {
+        let variant = match self {
+            Self::TunnelUp | Self::PeerOnline(_) => "connected",
+            Self::TunnelDown | Self::PeerOffline(_) => "disconnected",
+            Self::KeyExpiring(_) | Self::ExitNodeUnreachable(_) => "warning",
+        };
+        format!("{APP_ID}-{variant}-symbolic")
+    }
+
+    /// Freedesktop urgency level: 1 normal, 2 critical.
+    fn urgency(&self) -> u8 {
+        match self {
+            Self::TunnelDown | Self::ExitNodeUnreachable(_) => 2,
+            _ => 1,
+        }
+    }
+}
+
+/// What [`detect_events`] needs beyond the two snapshots.
+pub struct EventContext<'a> {
+    pub settings: &'a NotificationSettings,
+    /// Stable node IDs of favorite peers
+    pub favorites: &'a BTreeSet<String>,
+    /// Warn once our key expires within this many seconds
+    pub key_warning_secs: i64,
+    /// When the previous snapshot was taken (unix seconds)
+    pub prev_at: i64,
+    pub now: i64,
+}
+
+/// Events worth a notification between two status snapshots.
+///
+/// With no previous snapshot (e.g. at startup) only a key that is already
+/// about to expire is reported.
+pub fn detect_events(
+    prev: Option<&TailscaleStatus>,
+    next: &TailscaleStatus,
+    ctx: &EventContext,
+) -> Vec<NotifyEvent> {
+    let mut events = Vec::new();
+
+    let expiring = |status: &TailscaleStatus, at: i64| {
+        status.backend_state == BackendState::Running
+            && status
+                .self_node
+                .key_expiry
+                .is_some_and(|expiry| expiry - at < ctx.key_warning_secs)
+    };
+    if ctx.settings.key_expiry
+        && expiring(next, ctx.now)
+        && !prev.is_some_and(|p| expiring(p, ctx.prev_at))
+    {
+        if let Some(expiry) = next.self_node.key_expiry {
+            events.push(NotifyEvent::KeyExpiring(expiry));
+        }
+    }
+
+    let Some(prev) = prev else {
+        return events;
+    };
+
+    let was_up = prev.backend_state == BackendState::Running;
+    let is_up = next.backend_state == BackendState::Running;
+    if ctx.settings.tunnel && was_up != is_up {
+        events.push(if is_up {
+            NotifyEvent::TunnelUp
+        } else {
+            NotifyEvent::TunnelDown
+        });
+    }
+    // Peer states are meaningless while we are disconnected
+    if !was_up || !is_up {
+        return events;
+    }
+
+    let before: HashMap<&str, _> = prev.peers.iter().map(|p| (p.id.as_str(), p)).collect();
+    for peer in &next.peers {
+        let Some(old) = before.get(peer.id.as_str()) else {
+            continue;
+        };
+        if ctx.settings.favorite_peers
+            && ctx.favorites.contains(&peer.id)
+            && old.online != peer.online
+        {
+            let name = peer.display_name().to_string();
+            events.push(if peer.online {
+                NotifyEvent::PeerOnline(name)
+            } else {
+                NotifyEvent::PeerOffline(name)
+            });
+        }
+        if ctx.settings.exit_node && peer.exit_node && !peer.online && old.online {
+            events.push(NotifyEvent::ExitNodeUnreachable(
+                peer.display_name().to_string(),
+            ));
+        }
+    }
+
+    events
+}
+
+#[zbus::proxy(
+    interface = "org.freedesktop.Notifications",
+    default_service = "org.freedesktop.Notifications",
+    default_path = "/org/freedesktop/Notifications"
+)]
+trait Notifications {
+    #[allow(clippy::too_many_arguments)]
+    fn notify(
+        &self,
+        app_name: &str,
+        replaces_id: u32,
+        app_icon: &str,
+        summary: &str,
+        body: &str,
+        actions: &[&str],
+        hints: HashMap<&str, Value<'_>>,
+        expire_timeout: i32,
+    ) -> zbus::Result<u32>;
+}
+
+/// Sends [`NotifyEvent`]s to the notification daemon.
+pub struct Notifier {
+    proxy: NotificationsProxy<'static>,
+}
+
+impl Notifier {
+    /// Connect to the bus named by `COSMIC_TAILSCALE_NOTIFY_BUS`, or the
+    /// session bus.
+    pub async fn connect() -> zbus::Result<Self> {
+        let connection = match std::env::var(NOTIFY_BUS_ENV) {
+            Ok(address) => {
+                zbus::connection::Builder::address(address.as_str())?
+                    .build()
+                    .await?
+            }
+            Err(_) => Connection::session().await?,
+        };
+        Self::with_connection(&connection).await
+    }
+
+    pub async fn with_connection(connection: &Connection) -> zbus::Result<Self> {
+        Ok(Self {
+            proxy: NotificationsProxy::new(connection).await?,
+        })
+    }
+
+    /// Show a notification, returning the daemon's ID for it.
+    pub async fn send(&self, event: &NotifyEvent, now: i64) -> zbus::Result<u32> {
+        let hints = HashMap::from([
+            ("urgency", Value::from(event.urgency())),
+            ("desktop-entry", Value::from(APP_ID)),
+        ]);
+        self.proxy
+            .notify(
+                APP_NAME,
+                0,
+                &event.icon(),
+                &event.summary(),
+                &event.body(now),
+                &[],
+                hints,
+                -1,
+            )
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tailscale::{NodeInfo, PeerInfo};
+
+    const NOW: i64 = 1_700_000_000;
+    const DAY: i64 = 86_400;
+
+    fn peer(id: &str, online: bool) -> PeerInfo {
+        PeerInfo {
+            id: id.to_string(),
+            hostname: id.to_string(),
+            online,
+            ..Default::default()
+        }
+    }
+
+    fn status(state: BackendState, peers: Vec<PeerInfo>) -> TailscaleStatus {
+        TailscaleStatus {
+            backend_state: state,
+            self_node: NodeInfo {
+                hostname: "laptop".to_string(),
+                key_expiry: Some(NOW + 90 * DAY),
+                ..Default::default()
+            },
+            peers,
+            ..Default::default()
+        }
+    }
+
+    fn detect(
+        prev: Option<&TailscaleStatus>,
+        next: &TailscaleStatus,
+        favorites: &[&str],
+        now: i64,
+    ) -> Vec<NotifyEvent> {
+        let settings = NotificationSettings::default();
+        let favorites: BTreeSet<String> = favorites.iter().map(|f| f.to_string()).collect();
+        detect_events(
+            prev,
+            next,
+            &EventContext {
+                settings: &settings,
+                favorites: &favorites,
+                key_warning_secs: 14 * DAY,
+                prev_at: now - 5,
+                now,
+            },
+        )
+    }
+
+    #[test]
+    fn tunnel_down_and_up() {
+        let up = status(BackendState::Running, Vec::new());
+        let down = status(BackendState::Stopped, Vec::new());
+        assert_eq!(
+            detect(Some(&up), &down, &[], NOW),
+            [NotifyEvent::TunnelDown]
+        );
+        assert_eq!(detect(Some(&down), &up, &[], NOW), [NotifyEvent::TunnelUp]);
+        assert!(detect(Some(&up), &up, &[], NOW).is_empty());
+        assert!(detect(None, &down, &[], NOW).is_empty());
+    }
+
+    #[test]
+    fn favorite_peer_online_and_offline() {
+        let before = status(
+            BackendState::Running,
+            vec![
+                peer("nas", true),
+                peer("desktop", false),
+                peer("phone", true),
+            ],
+        );
+        let after = status(
+            BackendState::Running,
+            vec![
+                peer("nas", false),
+                peer("desktop", true),
+                peer("phone", false),
+            ],
+        );
+        assert_eq!(
+            detect(Some(&before), &after, &["nas", "desktop"], NOW),
+            [
+                NotifyEvent::PeerOffline("nas".to_string()),
+                NotifyEvent::PeerOnline("desktop".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn exit_node_unreachable() {
+        let mut exit = peer("exit", true);
+        exit.exit_node = true;
+        let before = status(BackendState::Running, vec![exit.clone()]);
+        exit.online = false;
+        let after = status(BackendState::Running, vec![exit]);
+        assert_eq!(
+            detect(Some(&before), &after, &[], NOW),
+            [NotifyEvent::ExitNodeUnreachable("exit".to_string())]
+        );
+        // Only when it drops, not on every snapshot while it is down
+        assert!(detect(Some(&after), &after, &[], NOW).is_empty());
+    }
+
+    #[test]
+    fn key_expiry_warns_once() {
+        let mut expiring = status(BackendState::Running, Vec::new());
+        expiring.self_node.key_expiry = Some(NOW + 3 * DAY);
+
+        assert_eq!(
+            detect(None, &expiring, &[], NOW),
+            [NotifyEvent::KeyExpiring(NOW + 3 * DAY)]
+        );
+        assert!(detect(Some(&expiring), &expiring, &[], NOW + 5).is_empty());
+
+        // Crossing into the warning period also warns
+        let mut fine = expiring.clone();
+        fine.self_node.key_expiry = Some(NOW + 14 * DAY + 2);
+        assert!(detect(None, &fine, &[], NOW).is_empty());
+        assert_eq!(
+            detect(Some(&fine), &fine, &[], NOW + 5),
+            [NotifyEvent::KeyExpiring(NOW + 14 * DAY + 2)]
+        );
+    }
+
+    #[test]
+    fn no_peer_events_while_disconnected() {
+        let mut exit = peer("exit", true);
+        exit.exit_node = true;
+        let before = status(BackendState::Stopped, vec![peer("nas", true), exit.clone()]);
+        exit.online = false;
+        let after = status(BackendState::Stopped, vec![peer("nas", false), exit]);
+        assert!(detect(Some(&before), &after, &["nas"], NOW).is_empty());
+
+        // Dropping the tunnel reports that alone, not every peer going dark
+        let up = status(BackendState::Running, vec![peer("nas", true)]);
+        assert_eq!(
+            detect(Some(&up), &after, &["nas"], NOW),
+            [NotifyEvent::TunnelDown]
+        );
+    }
+
+    /// Records what the notification daemon was asked to show.
+    struct FakeDaemon {
+        shown: tokio::sync::mpsc::UnboundedSender<(String, String, String)>,
+    }
+
+    #[zbus::interface(name = "org.freedesktop.Notifications")]
+    impl FakeDaemon {
+        #[allow(clippy::too_many_arguments)]
+        fn notify(
+            &self,
+            app_name: String,
+            _replaces_id: u32,
+            _app_icon: String,
+            summary: String,
+            body: String,
+            _actions: Vec<String>,
+            _hints: HashMap<String, zbus::zvariant::OwnedValue>,
+            _expire_timeout: i32,
+        ) -> u32 {
+            let _ = self.shown.send((app_name, summary, body));
+            1
+        }
+    }
+
+    /// Kills the private bus even when an assertion fails.
+    struct BusGuard(std::process::Child);
+
+    impl Drop for BusGuard {
+        fn drop(&mut self) {
+            let _ = self.0.kill();
+            let _ = self.0.wait();
+        }
+    }
+
+    #[tokio::test]
+    async fn sends_to_a_private_bus() {
+        use std::io::BufRead;
+
+        // A private bus, so the test never shows anything on the desktop
+        let Ok(child) = std::process::Command::new("dbus-daemon")
+            .args(["--session", "--nofork", "--print-address=1"])
+            .stdout(std::process::Stdio::piped())
+            .spawn()
+        else {
+            eprintln!("dbus-daemon not found, skipping");
+            return;
+        };
+        let mut bus = BusGuard(child);
+        let mut address = String::new();
+        std::io::BufReader::new(bus.0.stdout.take().unwrap())
+            .read_line(&mut address)
+            .unwrap();
+        let address = address.trim().to_string();
+
+        let (tx, mut shown) = tokio::sync::mpsc::unbounded_channel();
+        let _daemon = zbus::connection::Builder::address(address.as_str())
+            .unwrap()
+            .name("org.freedesktop.Notifications")
+            .unwrap()
+            .serve_at("/org/freedesktop/Notifications", FakeDaemon { shown: tx })
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+
+        let connection = zbus::connection::Builder::address(address.as_str())
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        let notifier = Notifier::with_connection(&connection).await.unwrap();
+        let id = notifier.send(&NotifyEvent::TunnelDown, NOW).await.unwrap();
+
+        assert_eq!(id, 1);
+        assert_eq!(
+            shown.recv().await.unwrap(),
+            (
+                APP_NAME.to_string(),
+                "Tailscale disconnected".to_string(),
+                "The tunnel to your tailnet dropped.".to_string(),
+            )
+        );
+    }
+}
@@ -0,0 +1,138 @@
+//! File chooser dialogs through the XDG desktop portal.
+//!
+//! The portal shows the desktop's own dialog (COSMIC's, under COSMIC) and
+//! works the same whether or not we run sandboxed. Requests are answered
+//! asynchronously with a `Response` signal on a request object, so we
+//! subscribe to it before asking, as the portal documentation recommends.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use cosmic::iced::futures::StreamExt;
+use zbus::zvariant::{OwnedObjectPath, OwnedValue, Value};
+use zbus::Connection;
+
+use crate::tailscale;
+
+/// Makes each request's handle token unique within this process.
+static NEXT_TOKEN: AtomicU32 = AtomicU32::new(0);
+
+#[zbus::proxy(
+    interface = "org.freedesktop.portal.FileChooser",
+    default_service = "org.freedesktop.portal.Desktop",
+    default_path = "/org/freedesktop/portal/desktop"
+)]
+trait FileChooser {
+    fn open_file(
+        &self,
+        parent_window: &str,
+        title: &str,
+        options: HashMap<&str, Value<'_>>,
+    ) -> zbus::Result<OwnedObjectPath>;
+}
+
+#[zbus::proxy(
+    interface = "org.freedesktop.portal.Request",
+    default_service = "org.freedesktop.portal.Desktop"
+)]
+trait Request {
+    #[zbus(signal)]
+    fn response(&self, response: u32, results: HashMap<String, OwnedValue>) -> zbus::Result<()>;
+}
+
+/// Ask the user for one or more files. Returns nothing if they cancel.
+pub async fn pick_files(title: &str) -> zbus::Result<Vec<PathBuf>> {
+    open_file(title, true, false).await
+}
+
+/// Ask the user for a folder, or `None` if they cancel.
+pub async fn pick_folder(title: &str) -> zbus::Result<Option<PathBuf>> {
+    Ok(open_file(title, false, true).await?.into_iter().next())
+}
+
+async fn open_file(title: &str, multiple: bool, directory: bool) -> zbus::Result<Vec<PathBuf>> {
+    let connection = Connection::session().await?;
+
+    // The portal puts the request object at a path derived from our bus
+    // name and the token, so we can listen on it before it exists
+    let token = format!(
+        "cosmic_tailscale_{}",
+        NEXT_TOKEN.fetch_add(1, Ordering::Relaxed)
+    );
+    let sender = connection
+        .unique_name()
+        .map(|name| name.trim_start_matches(':').replace('.', "_"))
+        .ok_or_else(|| zbus::Error::Failure("No unique bus name".to_string()))?;
+    let request = RequestProxy::builder(&connection)
+        .path(format!(
+            "/org/freedesktop/portal/desktop/request/{sender}/{token}"
+        ))?
+        .build()
+        .await?;
+    let mut responses = request.receive_response().await?;
+
+    let options = HashMap::from([
+        ("handle_token", Value::from(token.as_str())),
+        ("multiple", Value::from(multiple)),
+        ("directory", Value::from(directory)),
+    ]);
+    FileChooserProxy::new(&connection)
+        .await?
+        .open_file("", title, options)
+        .await?;
+
+    let Some(response) = responses.next().await else {
+        return Ok(Vec::new());
+    };
+    let args = response.args()?;
+    // 0 is success; 1 means the user cancelled, 2 that the dialog failed
+    match args.response {
+        0 => {}
+        1 => return Ok(Vec::new()),
+        code => {
+            return Err(zbus::Error::Failure(format!(
+                "File chooser failed (response {code})"
+            )))
+        }
+    }
+    let uris = match args.results.get("uris") {
+        Some(value) => Vec::<String>::try_from(value.try_clone()?)?,
+        None => Vec::new(),
+    };
+    Ok(uris
+        .iter()
+        .filter_map(|uri| file_uri_to_path(uri))
+        .collect())
+}
+
+/// Turn a `file://` URI from the portal into a local path.
+pub fn file_uri_to_path(uri: &str) -> Option<PathBuf> {
+    let rest = uri.strip_prefix("file://")?;
+    let (authority, path) = rest.split_at(rest.find('/')?);
+    // Any other host names a file elsewhere, which we can't open
+    if !authority.is_empty() && !authority.eq_ignore_ascii_case("localhost") {
+        return None;
+    }
+
+    use std::os::unix::ffi::OsStringExt;
+    Some(PathBuf::from(std::ffi::OsString::from_vec(
+        tailscale::percent_decode(path),
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_uri_authority_must_be_local() {
+        assert_eq!(
+            file_uri_to_path("file:///home/me/My%20Docs"),
+            Some(PathBuf::from("/home/me/My Docs"))
+        );
+        assert_eq!(file_uri_to_path("file://localhost/tmp/a"), Some(PathBuf::from("/tmp/a")));
+        assert_eq!(file_uri_to_path("file://otherhost/tmp/a"), None);
+        assert_eq!(file_uri_to_path("https://example.com/a"), None);
+    }
+}
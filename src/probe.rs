@@ -0,0 +1,419 @@
+//! Service probing for the background worker.
+//!
+//! Probes run concurrently, at most [`MAX_CONCURRENT_PROBES`] peers at a time,
+//! and report back over a channel so status updates never wait on them.
+//! [`ProbeCache`] decides which peers are due: ones not probed yet, ones whose
+//! results are older than [`PROBE_TTL`], and ones that came back online or
+//! changed address. [`should_probe`] keeps peers the user has ruled out in
+//! [`ProbeSettings`] from being scanned at all.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::Semaphore;
+
+use crate::backend::TailscaleBackend;
+use crate::config::{ProbeSettings, ServiceDef};
+use crate::tailscale::{PeerInfo, ServiceInfo};
+
+/// How long probe results are trusted before a peer is probed again.
+pub const PROBE_TTL: Duration = Duration::from_secs(300);
+/// Peers probed at the same time.
+pub const MAX_CONCURRENT_PROBES: usize = 8;
+
+/// Whether the probe settings allow port-scanning `peer`.
+pub fn should_probe(settings: &ProbeSettings, peer: &PeerInfo, self_user_id: i64) -> bool {
+    if !settings.enabled {
+        return false;
+    }
+    if !settings.only_mine && settings.tags.is_empty() && settings.hosts.is_empty() {
+        return true;
+    }
+
+    let mine = peer.tags.is_empty() && peer.user_id == self_user_id;
+    let tagged = settings.tags.iter().any(|wanted| {
+        let wanted = wanted.trim();
+        let wanted = wanted.strip_prefix("tag:").unwrap_or(wanted);
+        peer.tags
+            .iter()
+            .any(|tag| tag.strip_prefix("tag:").unwrap_or(tag) == wanted)
+    });
+    let listed = settings.hosts.iter().any(|host| {
+        let host = host.trim().trim_end_matches('.');
+        host.eq_ignore_ascii_case(&peer.hostname)
+            || host.eq_ignore_ascii_case(peer.display_name())
+            || host.eq_ignore_ascii_case(&peer.dns_display())
+            || peer.tailscale_ips.iter().any(|ip| ip == host)
+    });
+
+    (settings.only_mine && mine) || tagged || listed
+}
+
+/// A peer due for probing.
+pub struct ProbeTarget {
+    pub peer_id: String,
+    pub ip: String,
+    pub dns_name: String,
+    /// [`ProbeCache`] generation the probe was started in
+    pub generation: u64,
+}
+
+/// Services found on one peer.
+pub struct ProbeResult {
+    pub peer_id: String,
+    pub ip: String,
+    pub services: ServiceInfo,
+    pub generation: u64,
+}
+
+struct Entry {
+    ip: String,
+    services: ServiceInfo,
+    probed_at: Instant,
+}
+
+/// Probe results per peer (stable node ID).
+#[derive(Default)]
+pub struct ProbeCache {
+    entries: HashMap<String, Entry>,
+    in_flight: HashSet<String>,
+    /// Bumped by [`Self::clear`], so probes started before it are dropped
+    generation: u64,
+}
+
+impl ProbeCache {
+    /// Peers to probe now, which are then counted as in flight until their
+    /// result is stored. Offline peers, and peers `allowed`
+    /// rejects, are forgotten so they are probed again when they qualify.
+    pub fn due(
+        &mut self,
+        peers: &[PeerInfo],
+        now: Instant,
+        allowed: impl Fn(&PeerInfo) -> bool,
+    ) -> Vec<ProbeTarget> {
+        let mut due = Vec::new();
+        for peer in peers {
+            if !peer.online || !allowed(peer) {
+                self.entries.remove(&peer.id);
+                continue;
+            }
+            let Some(ip) = peer.tailscale_ips.first() else {
+                continue;
+            };
+            if self.in_flight.contains(&peer.id) {
+                continue;
+            }
+            let fresh = self
+                .entries
+                .get(&peer.id)
+                .is_some_and(|e| e.ip == *ip && now.duration_since(e.probed_at) < PROBE_TTL);
+            if !fresh {
+                self.in_flight.insert(peer.id.clone());
+                due.push(ProbeTarget {
+                    peer_id: peer.id.clone(),
+                    ip: ip.clone(),
+                    dns_name: peer.dns_name.clone(),
+                    generation: self.generation,
+                });
+            }
+        }
+        due
+    }
+
+    /// Store a finished probe, unless it was started before the last
+    /// [`Self::clear`] with the old registry or settings.
+    pub fn insert(&mut self, result: ProbeResult, now: Instant) {
+        if result.generation != self.generation {
+            return;
+        }
+        self.in_flight.remove(&result.peer_id);
+        self.entries.insert(
+            result.peer_id,
+            Entry {
+                ip: result.ip,
+                services: result.services,
+                probed_at: now,
+            },
+        );
+    }
+
+    pub fn services(&self, peer_id: &str) -> Option<&ServiceInfo> {
+        self.entries.get(peer_id).map(|e| &e.services)
+    }
+
+    /// Drop all results so every online peer is probed again, including
+    /// peers whose probe is still running.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.in_flight.clear();
+        self.generation += 1;
+    }
+}
+
+/// Runs probes on the tokio runtime with a shared concurrency cap.
+pub struct Prober {
+    backend: Arc<dyn TailscaleBackend>,
+    permits: Arc<Semaphore>,
+    results: UnboundedSender<ProbeResult>,
+}
+
+impl Prober {
+    pub fn new(backend: Arc<dyn TailscaleBackend>, results: UnboundedSender<ProbeResult>) -> Self {
+        Self {
+            backend,
+            permits: Arc::new(Semaphore::new(MAX_CONCURRENT_PROBES)),
+            results,
+        }
+    }
+
+    /// Probe each target for the services in `registry`. Returns straight
+    /// away; results arrive on the channel as each peer finishes.
+    pub fn spawn(&self, registry: &Arc<Vec<ServiceDef>>, targets: Vec<ProbeTarget>) {
+        for target in targets {
+            let backend = self.backend.clone();
+            let permits = self.permits.clone();
+            let registry = registry.clone();
+            let results = self.results.clone();
+            tokio::spawn(async move {
+                let Ok(_permit) = permits.acquire_owned().await else {
+                    return;
+                };
+                // Port checks block on connect timeouts
+                let ProbeTarget {
+                    peer_id,
+                    ip,
+                    dns_name,
+                    generation,
+                } = target;
+                let probe_ip = ip.clone();
+                let services = tokio::task::spawn_blocking(move || {
+                    backend.probe_services(&probe_ip, &dns_name, &registry)
+                })
+                .await
+                .unwrap_or_default();
+                let _ = results.send(ProbeResult {
+                    peer_id,
+                    ip,
+                    services,
+                    generation,
+                });
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ME: i64 = 1;
+
+    fn peer(id: &str, user_id: i64, tags: &[&str]) -> PeerInfo {
+        PeerInfo {
+            id: id.to_string(),
+            hostname: id.to_string(),
+            dns_name: format!("{id}.tail1234.ts.net."),
+            tailscale_ips: vec![format!("100.64.0.{}", id.len()), "fd7a:115c:a1e0::1".into()],
+            online: true,
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            user_id,
+            ..Default::default()
+        }
+    }
+
+    fn settings(only_mine: bool, tags: &[&str], hosts: &[&str]) -> ProbeSettings {
+        ProbeSettings {
+            enabled: true,
+            only_mine,
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            hosts: hosts.iter().map(|h| h.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn disabled_probes_nothing() {
+        let off = ProbeSettings {
+            enabled: false,
+            ..ProbeSettings::default()
+        };
+        assert!(!should_probe(&off, &peer("laptop", ME, &[]), ME));
+    }
+
+    #[test]
+    fn empty_lists_probe_everyone() {
+        let all = settings(false, &[], &[]);
+        assert!(should_probe(&all, &peer("laptop", ME, &[]), ME));
+        assert!(should_probe(&all, &peer("shared", 2, &[]), ME));
+        assert!(should_probe(&all, &peer("server", 2, &["tag:server"]), ME));
+    }
+
+    #[test]
+    fn only_mine_skips_shared_and_tagged_peers() {
+        let mine = settings(true, &[], &[]);
+        assert!(should_probe(&mine, &peer("laptop", ME, &[]), ME));
+        assert!(!should_probe(&mine, &peer("shared", 2, &[]), ME));
+        assert!(!should_probe(
+            &mine,
+            &peer("server", ME, &["tag:server"]),
+            ME
+        ));
+    }
+
+    #[test]
+    fn tags_match_with_or_without_prefix() {
+        let server = peer("server", 2, &["tag:server"]);
+        assert!(should_probe(
+            &settings(false, &["tag:server"], &[]),
+            &server,
+            ME
+        ));
+        assert!(should_probe(
+            &settings(false, &[" server "], &[]),
+            &server,
+            ME
+        ));
+        assert!(!should_probe(&settings(false, &["nas"], &[]), &server, ME));
+        assert!(!should_probe(
+            &settings(false, &["server"], &[]),
+            &peer("laptop", ME, &[]),
+            ME
+        ));
+    }
+
+    #[test]
+    fn hosts_match_names_and_ips() {
+        let nas = peer("nas", 2, &[]);
+        for host in [
+            "nas",
+            "NAS",
+            "nas.tail1234.ts.net",
+            "nas.tail1234.ts.net.",
+            "100.64.0.3",
+            "fd7a:115c:a1e0::1",
+        ] {
+            assert!(
+                should_probe(&settings(false, &[], &[host]), &nas, ME),
+                "{host}"
+            );
+        }
+        assert!(!should_probe(&settings(false, &[], &["na"]), &nas, ME));
+        assert!(!should_probe(
+            &settings(false, &[], &["100.64.0.4"]),
+            &nas,
+            ME
+        ));
+    }
+
+    #[test]
+    fn any_matching_rule_allows_a_peer() {
+        let rules = settings(true, &["server"], &["nas"]);
+        assert!(should_probe(&rules, &peer("laptop", ME, &[]), ME));
+        assert!(should_probe(
+            &rules,
+            &peer("server", 2, &["tag:server"]),
+            ME
+        ));
+        assert!(should_probe(&rules, &peer("nas", 2, &[]), ME));
+        assert!(!should_probe(&rules, &peer("shared", 2, &[]), ME));
+    }
+
+    fn probed(cache: &mut ProbeCache, target: &ProbeTarget, now: Instant) {
+        cache.insert(
+            ProbeResult {
+                peer_id: target.peer_id.clone(),
+                ip: target.ip.clone(),
+                services: ServiceInfo::default(),
+                generation: target.generation,
+            },
+            now,
+        );
+    }
+
+    #[test]
+    fn cache_honours_ttl() {
+        let peers = vec![peer("laptop", ME, &[])];
+        let mut cache = ProbeCache::default();
+        let start = Instant::now();
+
+        let due = cache.due(&peers, start, |_| true);
+        assert_eq!(due.len(), 1);
+        // In flight until the result arrives
+        assert!(cache.due(&peers, start, |_| true).is_empty());
+
+        probed(&mut cache, &due[0], start);
+        assert!(cache.services("laptop").is_some());
+        let later = start + PROBE_TTL - Duration::from_secs(1);
+        assert!(cache.due(&peers, later, |_| true).is_empty());
+        assert_eq!(cache.due(&peers, start + PROBE_TTL, |_| true).len(), 1);
+    }
+
+    #[test]
+    fn cache_reprobes_after_clear_and_address_change() {
+        let mut peers = vec![peer("laptop", ME, &[])];
+        let mut cache = ProbeCache::default();
+        let now = Instant::now();
+
+        let due = cache.due(&peers, now, |_| true);
+        probed(&mut cache, &due[0], now);
+        cache.clear();
+        let due = cache.due(&peers, now, |_| true);
+        assert_eq!(due.len(), 1);
+
+        probed(&mut cache, &due[0], now);
+        peers[0].tailscale_ips[0] = "100.64.0.99".into();
+        assert_eq!(cache.due(&peers, now, |_| true).len(), 1);
+
+        // Narrowing the scope while a peer it now excludes is being probed
+        peers.push(peer("shared", 2, &[]));
+        let in_flight = cache.due(&peers, now, |_| true);
+        assert_eq!(in_flight.len(), 1);
+        cache.clear();
+        let mine = settings(true, &[], &[]);
+        let due = cache.due(&peers, now, |p| should_probe(&mine, p, ME));
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].peer_id, "laptop");
+        probed(&mut cache, &in_flight[0], now);
+        assert!(cache.services("shared").is_none());
+    }
+
+    #[test]
+    fn cache_drops_probes_started_before_clear() {
+        let peers = vec![peer("laptop", ME, &[])];
+        let mut cache = ProbeCache::default();
+        let now = Instant::now();
+
+        let stale = cache.due(&peers, now, |_| true);
+        cache.clear();
+        // Probed again straight away rather than waiting on the old probe
+        let due = cache.due(&peers, now, |_| true);
+        assert_eq!(due.len(), 1);
+
+        probed(&mut cache, &stale[0], now);
+        assert!(cache.services("laptop").is_none());
+        // The new probe is still in flight
+        assert!(cache.due(&peers, now, |_| true).is_empty());
+
+        probed(&mut cache, &due[0], now);
+        assert!(cache.services("laptop").is_some());
+    }
+
+    #[test]
+    fn cache_forgets_offline_and_disallowed_peers() {
+        let mut peers = vec![peer("laptop", ME, &[])];
+        let mut cache = ProbeCache::default();
+        let now = Instant::now();
+
+        let due = cache.due(&peers, now, |_| true);
+        probed(&mut cache, &due[0], now);
+        assert!(cache.due(&peers, now, |_| false).is_empty());
+        assert!(cache.services("laptop").is_none());
+
+        let due = cache.due(&peers, now, |_| true);
+        probed(&mut cache, &due[0], now);
+        peers[0].online = false;
+        assert!(cache.due(&peers, now, |_| true).is_empty());
+        assert!(cache.services("laptop").is_none());
+    }
+}
@@ -52,8 +52,7 @@ impl Application for SettingsApp {
     }
 
     fn update(&mut self, message: Self::Message) -> Task<Action<Self::Message>> {
-        settings_page::update(&mut self.page, message);
-        Task::none()
+        settings_page::update(&mut self.page, message).map(Action::App)
     }
 }
 
@@ -1,9 +1,11 @@
 //! CLI settings protocol for cosmic-applet-settings hub integration.
 
+use crate::backend::{self, TailscaleBackend};
 use crate::tailscale;
 
 pub fn describe() {
-    let prefs = match tailscale::get_prefs() {
+    let backend = backend::default_backend();
+    let prefs = match backend.prefs() {
         Ok(p) => p,
         Err(e) => {
             eprintln!("Failed to get tailscale prefs: {e}");
@@ -11,6 +13,15 @@ pub fn describe() {
         }
     };
 
+    let approval = match backend.status() {
+        Ok(status) => route_approval(
+            &prefs.advertise_routes,
+            prefs.advertise_exit_node,
+            &status.self_node,
+        ),
+        Err(e) => format!("Unknown ({e})"),
+    };
+
     let schema = serde_json::json!({
         "title": "Tailscale Settings",
         "description": "Basic Tailscale preferences. Use the Admin Console for advanced settings.",
@@ -42,6 +53,30 @@ pub fn describe() {
                         "value": prefs.accept_routes
                     }
                 ]
+            },
+            {
+                "title": "Exit Node and Subnet Routes",
+                "items": [
+                    {
+                        "type": "toggle",
+                        "key": "advertise_exit_node",
+                        "label": "Offer as Exit Node",
+                        "value": prefs.advertise_exit_node
+                    },
+                    {
+                        "type": "text",
+                        "key": "advertise_routes",
+                        "label": "Advertised Routes",
+                        "placeholder": "192.168.1.0/24, 10.0.0.0/16",
+                        "value": prefs.advertise_routes.join(", ")
+                    },
+                    {
+                        "type": "info",
+                        "key": "route_approval",
+                        "label": "Approval",
+                        "value": approval
+                    }
+                ]
             }
         ],
         "actions": [
@@ -54,9 +89,12 @@ pub fn describe() {
 }
 
 pub fn set(key: &str, value: &str) {
+    let backend = backend::default_backend();
     let result = match key {
-        "accept_dns" => set_bool("accept-dns", value),
-        "accept_routes" => set_bool("accept-routes", value),
+        "accept_dns" => set_bool(backend.as_ref(), "accept-dns", value),
+        "accept_routes" => set_bool(backend.as_ref(), "accept-routes", value),
+        "advertise_exit_node" => set_exit_node(backend.as_ref(), value),
+        "advertise_routes" => set_routes(backend.as_ref(), value),
         _ => Err(format!("Unknown key: {key}")),
     };
 
@@ -86,16 +124,120 @@ pub fn action(id: &str) {
     }
 }
 
-fn set_bool(flag: &str, value: &str) -> Result<String, String> {
+fn set_bool(backend: &dyn TailscaleBackend, flag: &str, value: &str) -> Result<String, String> {
     match serde_json::from_str::<bool>(value) {
-        Ok(v) => tailscale::set_bool_pref(flag, v)
+        Ok(v) => backend
+            .set_bool_pref(flag, v)
             .map(|_| format!("Updated {flag}"))
-            .map_err(|e| e),
+            .map_err(|e| e.to_string()),
         Err(e) => Err(format!("Invalid boolean: {e}")),
     }
 }
 
+fn set_exit_node(backend: &dyn TailscaleBackend, value: &str) -> Result<String, String> {
+    let v = serde_json::from_str::<bool>(value).map_err(|e| format!("Invalid boolean: {e}"))?;
+    let prefs = backend.prefs().map_err(|e| e.to_string())?;
+    backend
+        .set_advertised_routes(&prefs.advertise_routes, v)
+        .map(|_| "Updated exit node advertisement".to_string())
+        .map_err(|e| e.to_string())
+}
+
+/// Takes a comma-separated string (or a JSON array) of CIDR prefixes.
+fn set_routes(backend: &dyn TailscaleBackend, value: &str) -> Result<String, String> {
+    let list = match serde_json::from_str::<serde_json::Value>(value) {
+        Ok(serde_json::Value::String(s)) => s,
+        Ok(serde_json::Value::Array(items)) => items
+            .iter()
+            .filter_map(|v| v.as_str())
+            .collect::<Vec<_>>()
+            .join(","),
+        _ => value.to_string(),
+    };
+    let mut routes = Vec::new();
+    for route in list.split(',').map(str::trim).filter(|r| !r.is_empty()) {
+        let route = tailscale::parse_route(route)?;
+        if !routes.contains(&route) {
+            routes.push(route);
+        }
+    }
+    let prefs = backend.prefs().map_err(|e| e.to_string())?;
+    backend
+        .set_advertised_routes(&routes, prefs.advertise_exit_node)
+        .map(|_| "Updated advertised routes".to_string())
+        .map_err(|e| e.to_string())
+}
+
+/// Summarize which advertised routes the tailnet has approved.
+fn route_approval(routes: &[String], exit_node: bool, node: &tailscale::NodeInfo) -> String {
+    let mut awaiting: Vec<&str> = routes
+        .iter()
+        .filter(|r| !node.primary_routes.contains(r))
+        .map(String::as_str)
+        .collect();
+    if exit_node && !node.exit_node_option {
+        awaiting.insert(0, "exit node");
+    }
+    if routes.is_empty() && !exit_node {
+        "Nothing advertised".to_string()
+    } else if awaiting.is_empty() {
+        "All approved".to_string()
+    } else {
+        format!(
+            "Awaiting approval in the admin console: {}",
+            awaiting.join(", ")
+        )
+    }
+}
+
 fn print_response(ok: bool, message: &str) {
     let resp = serde_json::json!({"ok": ok, "message": message});
     println!("{}", resp);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::FakeBackend;
+    use std::path::Path;
+
+    fn fixture_backend() -> FakeBackend {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("resources/fixtures");
+        FakeBackend::from_fixture_dir(&dir).unwrap()
+    }
+
+    #[test]
+    fn set_routes_takes_lists_strings_and_arrays() {
+        let both = ["10.1.0.0/16", "10.2.0.0/16"];
+        for value in [
+            "10.1.0.0/16, 10.2.0.0/16,10.1.0.0/16",
+            r#""10.1.0.0/16,10.2.0.0/16, 10.2.0.0/16""#,
+            r#"["10.1.0.0/16", "10.2.0.0/16", "10.1.0.0/16"]"#,
+        ] {
+            let fake = fixture_backend();
+            assert_eq!(
+                set_routes(&fake, value).as_deref(),
+                Ok("Updated advertised routes"),
+                "{value}"
+            );
+            assert_eq!(fake.prefs().unwrap().advertise_routes, both, "{value}");
+        }
+    }
+
+    #[test]
+    fn set_routes_keeps_exit_node_and_rejects_bad_routes() {
+        let fake = fixture_backend();
+        set_exit_node(&fake, "true").unwrap();
+        set_routes(&fake, "10.1.0.0/16").unwrap();
+        let prefs = fake.prefs().unwrap();
+        assert!(prefs.advertise_exit_node);
+        assert_eq!(prefs.advertise_routes, ["10.1.0.0/16"]);
+
+        let e = set_routes(&fake, "10.2.0.0/16, 10.3.0.1/16").unwrap_err();
+        assert!(e.contains("host bits set"), "{e}");
+        assert_eq!(fake.prefs().unwrap().advertise_routes, ["10.1.0.0/16"]);
+
+        set_routes(&fake, "").unwrap();
+        assert!(fake.prefs().unwrap().advertise_routes.is_empty());
+    }
+}
@@ -1,62 +1,492 @@
-use cosmic::widget::{button, settings, text, toggler};
-use cosmic::Element;
+use std::path::PathBuf;
+use std::sync::Arc;
 
-use crate::tailscale;
+use cosmic::iced::futures::channel::oneshot;
+use cosmic::iced::widget::row;
+use cosmic::iced::Alignment;
+use cosmic::widget::{button, dropdown, settings, text, text_input, toggler};
+use cosmic::{Element, Task};
+
+use crate::backend::{self, TailscaleBackend};
+use crate::config::{AppConfig, NotificationSettings, ProbeSettings, TaildropSettings};
+use crate::portal;
+use crate::tailscale::{
+    self, DriveShare, LoginProfile, NetcheckReport, NodeInfo, ServeEdit, ServeEndpoint,
+    ServeKind, ServeMapping, TailscaleError,
+};
+
+/// Choices offered for the key expiry warning, in days.
+const KEY_EXPIRY_WARNING_DAYS: [u32; 5] = [1, 3, 7, 14, 30];
+static KEY_EXPIRY_WARNING_LABELS: [&str; 5] = ["1 day", "3 days", "7 days", "14 days", "30 days"];
+/// Kinds of Serve mapping that can be added.
+const SERVE_KINDS: [ServeKind; 2] = [ServeKind::Https, ServeKind::Tcp];
+static SERVE_KIND_LABELS: [&str; 2] = ["HTTPS", "TCP"];
 
 pub struct State {
+    backend: Arc<dyn TailscaleBackend>,
+    config: AppConfig,
     pub accept_dns: bool,
     pub accept_routes: bool,
+    pub advertise_exit_node: bool,
+    pub advertise_routes: Vec<String>,
+    /// Subnet route to advertise, as typed
+    pub new_route: String,
+    /// Advertised routes the tailnet sends through this device
+    pub approved_routes: Vec<String>,
+    pub exit_node_approved: bool,
     pub login_name: String,
+    pub profiles: Vec<LoginProfile>,
+    pub netcheck: Option<Result<NetcheckReport, TailscaleError>>,
+    pub netcheck_running: bool,
+    /// Comma-separated tags and hosts, as typed
+    pub probe_tags: String,
+    pub probe_hosts: String,
+    /// Taildrop save folder, as typed
+    pub taildrop_dir: String,
+    /// Taildrive shares, or why they could not be listed
+    pub shares: Result<Vec<DriveShare>, TailscaleError>,
+    /// Share being renamed, and its new name as typed
+    pub renaming: Option<(String, String)>,
+    /// Folder picked to share, and its name as typed
+    pub new_share: Option<(PathBuf, String)>,
+    /// This device's MagicDNS name, where Serve mappings are reached
+    pub self_dns: String,
+    pub serve: Result<Vec<ServeEndpoint>, TailscaleError>,
+    pub serve_form: ServeForm,
     pub status_message: String,
 }
 
+/// A Serve mapping to add, as typed.
+#[derive(Debug, Clone, Default)]
+pub struct ServeForm {
+    /// Index into [`SERVE_KINDS`]
+    pub kind: usize,
+    pub port: String,
+    pub mount: String,
+    pub target: String,
+}
+
+/// An account change made from the settings page.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProfileChange {
+    Switched,
+    Added,
+    LoggedOut,
+}
+
+/// Everything the page shows from the backend, read off the UI thread.
+#[derive(Debug, Clone)]
+pub struct BackendSnapshot {
+    prefs: tailscale::TailscalePrefs,
+    profiles: Option<Vec<LoginProfile>>,
+    shares: Result<Vec<DriveShare>, TailscaleError>,
+    self_node: Option<NodeInfo>,
+    serve: Result<Vec<ServeEndpoint>, TailscaleError>,
+}
+
 #[derive(Debug, Clone)]
 pub enum Message {
     ToggleAcceptDns(bool),
+    AcceptDnsSet(Result<bool, TailscaleError>),
     ToggleAcceptRoutes(bool),
+    AcceptRoutesSet(Result<bool, TailscaleError>),
+    ToggleAdvertiseExitNode(bool),
+    NewRoute(String),
+    AddRoute,
+    RemoveRoute(String),
+    /// Advertising these routes, and the exit node if set, finished
+    RoutesSet(Result<(Vec<String>, bool), TailscaleError>),
+    SetKeyExpiryWarning(usize),
+    NotifyTunnel(bool),
+    NotifyFavoritePeers(bool),
+    NotifyKeyExpiry(bool),
+    NotifyExitNode(bool),
+    ToggleProbing(bool),
+    ToggleProbeOnlyMine(bool),
+    ProbeTags(String),
+    ProbeHosts(String),
+    /// Save the tag and host lists as typed
+    CommitProbeLists,
+    ToggleAutoAccept(bool),
+    TaildropDir(String),
+    AddShare,
+    ShareFolderChosen(Result<Option<PathBuf>, String>),
+    NewShareName(String),
+    ConfirmShare,
+    StartRename(String),
+    RenameText(String),
+    ConfirmRename,
+    /// Stop adding or renaming a share
+    CancelShareEdit,
+    Unshare(String),
+    /// A share change finished, with what to report and the new share list
+    SharesEdited(String, Result<Vec<DriveShare>, TailscaleError>),
+    SetServeKind(usize),
+    ServePort(String),
+    ServeMount(String),
+    ServeTarget(String),
+    AddServe,
+    RemoveServe(ServeEndpoint),
+    ToggleFunnel(ServeEndpoint, bool),
+    /// A Serve change finished, with the mappings as they now are
+    ServeEdited(ServeEdit, Result<Vec<ServeEndpoint>, TailscaleError>),
+    SwitchProfile(String),
+    AddProfile,
+    Logout,
+    RunNetcheck,
+    NetcheckDone(Result<NetcheckReport, TailscaleError>),
+    CopyNetcheckJson,
     OpenAdminConsole,
     Reload,
+    /// An account change finished, and the settings were read again
+    ProfileChanged(ProfileChange, Result<Box<BackendSnapshot>, TailscaleError>),
+    Reloaded(Result<Box<BackendSnapshot>, TailscaleError>),
 }
 
 pub fn init() -> State {
-    let (prefs, status_message) = match tailscale::get_prefs() {
+    init_with(backend::default_backend(), backend::default_config())
+}
+
+/// Build the page state against a specific backend and config.
+pub fn init_with(backend: Arc<dyn TailscaleBackend>, config: AppConfig) -> State {
+    let (prefs, status_message) = match backend.prefs() {
         Ok(p) => (p, String::new()),
         Err(e) => (
             tailscale::TailscalePrefs {
                 accept_dns: true,
-                accept_routes: false,
-                login_name: String::new(),
+                ..Default::default()
             },
             format!("Failed to load preferences: {e}"),
         ),
     };
 
+    let profiles = backend.profiles().unwrap_or_default();
+    let shares = backend.drive_shares();
+    let self_node = backend.status().map(|s| s.self_node).ok();
+    let self_dns = self_node
+        .as_ref()
+        .map(|n| n.dns_name.clone())
+        .unwrap_or_default();
+    let serve = backend.serve_config(&self_dns);
+
+    let status_message = match config.load_error() {
+        Some(e) => format!("Settings will not be saved until the config file is fixed: {e}"),
+        None => status_message,
+    };
     State {
+        backend,
+        probe_tags: config.probing.tags.join(", "),
+        probe_hosts: config.probing.hosts.join(", "),
+        taildrop_dir: config
+            .taildrop
+            .save_dir
+            .as_ref()
+            .map(|dir| dir.display().to_string())
+            .unwrap_or_default(),
+        config,
         accept_dns: prefs.accept_dns,
         accept_routes: prefs.accept_routes,
+        advertise_exit_node: prefs.advertise_exit_node,
+        advertise_routes: prefs.advertise_routes,
+        new_route: String::new(),
+        approved_routes: self_node
+            .as_ref()
+            .map(|n| n.primary_routes.clone())
+            .unwrap_or_default(),
+        exit_node_approved: self_node.is_some_and(|n| n.exit_node_option),
         login_name: prefs.login_name,
+        profiles,
+        netcheck: None,
+        netcheck_running: false,
+        shares,
+        renaming: None,
+        new_share: None,
+        self_dns,
+        serve,
+        serve_form: ServeForm::default(),
         status_message,
     }
 }
 
-pub fn update(state: &mut State, message: Message) {
+pub fn update(state: &mut State, message: Message) -> Task<Message> {
     match message {
         Message::ToggleAcceptDns(val) => {
-            match tailscale::set_bool_pref("accept-dns", val) {
-                Ok(()) => {
-                    state.accept_dns = val;
-                    state.status_message = "Accept DNS updated".to_string();
+            return run_backend(
+                state,
+                move |b| b.set_bool_pref("accept-dns", val).map(|()| val),
+                Message::AcceptDnsSet,
+            );
+        }
+        Message::AcceptDnsSet(result) => match result {
+            Ok(val) => {
+                state.accept_dns = val;
+                state.status_message = "Accept DNS updated".to_string();
+            }
+            Err(e) => state.status_message = format!("Error: {e}"),
+        },
+        Message::ToggleAcceptRoutes(val) => {
+            return run_backend(
+                state,
+                move |b| b.set_bool_pref("accept-routes", val).map(|()| val),
+                Message::AcceptRoutesSet,
+            );
+        }
+        Message::AcceptRoutesSet(result) => match result {
+            Ok(val) => {
+                state.accept_routes = val;
+                state.status_message = "Accept routes updated".to_string();
+            }
+            Err(e) => state.status_message = format!("Error: {e}"),
+        },
+        Message::ToggleAdvertiseExitNode(val) => {
+            return set_routes(state, state.advertise_routes.clone(), val);
+        }
+        Message::NewRoute(val) => state.new_route = val,
+        Message::AddRoute => {
+            let route = match tailscale::parse_route(&state.new_route) {
+                Ok(route) => route,
+                Err(e) => {
+                    state.status_message = format!("Error: {e}");
+                    return Task::none();
                 }
-                Err(e) => state.status_message = format!("Error: {e}"),
+            };
+            if !state.advertise_routes.contains(&route) {
+                let mut routes = state.advertise_routes.clone();
+                routes.push(route);
+                return set_routes(state, routes, state.advertise_exit_node);
             }
+            state.new_route.clear();
+            state.status_message = format!("Advertising {route}");
         }
-        Message::ToggleAcceptRoutes(val) => {
-            match tailscale::set_bool_pref("accept-routes", val) {
-                Ok(()) => {
-                    state.accept_routes = val;
-                    state.status_message = "Accept routes updated".to_string();
+        Message::RemoveRoute(route) => {
+            let routes = state
+                .advertise_routes
+                .iter()
+                .filter(|r| **r != route)
+                .cloned()
+                .collect();
+            return set_routes(state, routes, state.advertise_exit_node);
+        }
+        Message::RoutesSet(result) => match result {
+            Ok((routes, exit_node)) => {
+                state.status_message = routes_done(state, &routes, exit_node);
+                // The route typed in was added
+                if tailscale::parse_route(&state.new_route).is_ok_and(|r| routes.contains(&r)) {
+                    state.new_route.clear();
                 }
-                Err(e) => state.status_message = format!("Error: {e}"),
+                state.advertise_routes = routes;
+                state.advertise_exit_node = exit_node;
+            }
+            Err(e) => state.status_message = format!("Error: {e}"),
+        },
+        Message::SetKeyExpiryWarning(idx) => {
+            if let Some(&days) = KEY_EXPIRY_WARNING_DAYS.get(idx) {
+                state.config.update(|config| config.key_expiry_warning_days = days);
+                state.status_message = "Key expiry warning updated".to_string();
+            }
+        }
+        Message::NotifyTunnel(val) => {
+            save_notifications(state, |n| n.tunnel = val);
+        }
+        Message::NotifyFavoritePeers(val) => {
+            save_notifications(state, |n| n.favorite_peers = val);
+        }
+        Message::NotifyKeyExpiry(val) => {
+            save_notifications(state, |n| n.key_expiry = val);
+        }
+        Message::NotifyExitNode(val) => {
+            save_notifications(state, |n| n.exit_node = val);
+        }
+        Message::ToggleProbing(val) => {
+            save_probing(state, |p| p.enabled = val);
+        }
+        Message::ToggleProbeOnlyMine(val) => {
+            save_probing(state, |p| p.only_mine = val);
+        }
+        Message::ProbeTags(val) => state.probe_tags = val,
+        Message::ProbeHosts(val) => state.probe_hosts = val,
+        Message::CommitProbeLists => {
+            // Only once typing is done, as each saved list is probed at once
+            let tags = split_list(&state.probe_tags);
+            let hosts = split_list(&state.probe_hosts);
+            if tags != state.config.probing.tags || hosts != state.config.probing.hosts {
+                save_probing(state, |p| {
+                    p.tags = tags;
+                    p.hosts = hosts;
+                });
+            }
+        }
+        Message::ToggleAutoAccept(val) => {
+            save_taildrop(state, |t| t.auto_accept = val);
+        }
+        Message::TaildropDir(val) => {
+            let dir = val.trim();
+            let save_dir: Option<PathBuf> = (!dir.is_empty()).then(|| dir.into());
+            state.taildrop_dir = val;
+            save_taildrop(state, |t| t.save_dir = save_dir);
+        }
+        Message::AddShare => {
+            return Task::perform(
+                async { portal::pick_folder("Share a Folder").await },
+                |result| Message::ShareFolderChosen(result.map_err(|e| e.to_string())),
+            );
+        }
+        Message::ShareFolderChosen(result) => match result {
+            Ok(Some(path)) => {
+                let name = tailscale::suggest_share_name(&path);
+                state.renaming = None;
+                state.new_share = Some((path, name));
+            }
+            Ok(None) => {}
+            Err(e) => state.status_message = format!("Error: {e}"),
+        },
+        Message::NewShareName(val) => {
+            if let Some((_, ref mut name)) = state.new_share {
+                *name = val;
+            }
+        }
+        Message::ConfirmShare => {
+            let Some((ref path, ref name)) = state.new_share else {
+                return Task::none();
+            };
+            let (path, name) = (path.clone(), name.trim().to_string());
+            let done = format!("Sharing {} as {name}", path.display());
+            return edit_shares(state, done, move |b| b.share_folder(&name, &path));
+        }
+        Message::StartRename(name) => {
+            state.new_share = None;
+            state.renaming = Some((name.clone(), name));
+        }
+        Message::RenameText(val) => {
+            if let Some((_, ref mut new)) = state.renaming {
+                *new = val;
+            }
+        }
+        Message::ConfirmRename => {
+            let Some((old, new)) = state.renaming.take() else {
+                return Task::none();
+            };
+            let new = new.trim().to_string();
+            if new == old {
+                return Task::none();
+            }
+            // Keep the rename open until it is known to have worked
+            state.renaming = Some((old.clone(), new.clone()));
+            let done = format!("Renamed {old} to {new}");
+            return edit_shares(state, done, move |b| b.rename_share(&old, &new));
+        }
+        Message::CancelShareEdit => {
+            state.new_share = None;
+            state.renaming = None;
+        }
+        Message::Unshare(name) => {
+            let done = format!("Stopped sharing {name}");
+            return edit_shares(state, done, move |b| b.unshare_folder(&name));
+        }
+        Message::SharesEdited(done, result) => match result {
+            Ok(shares) => {
+                state.shares = Ok(shares);
+                state.new_share = None;
+                state.renaming = None;
+                state.status_message = done;
+            }
+            Err(e) => state.status_message = format!("Error: {e}"),
+        },
+        Message::SetServeKind(kind) => state.serve_form.kind = kind,
+        Message::ServePort(val) => state.serve_form.port = val,
+        Message::ServeMount(val) => state.serve_form.mount = val,
+        Message::ServeTarget(val) => state.serve_form.target = val,
+        Message::AddServe => {
+            let form = &state.serve_form;
+            let kind = SERVE_KINDS
+                .get(form.kind)
+                .copied()
+                .unwrap_or(ServeKind::Https);
+            let port = match (form.port.trim(), kind) {
+                ("", ServeKind::Https) => Some(443),
+                (port, _) => port.parse::<u16>().ok().filter(|&p| p != 0),
+            };
+            let Some(port) = port else {
+                state.status_message = format!("Error: \"{}\" is not a port", form.port.trim());
+                return Task::none();
+            };
+            let mapping = ServeMapping {
+                kind,
+                port,
+                mount: form.mount.trim().to_string(),
+                target: form.target.trim().to_string(),
+            };
+            return edit_serve(state, ServeEdit::Add(mapping));
+        }
+        Message::RemoveServe(endpoint) => {
+            return edit_serve(state, ServeEdit::Remove(endpoint));
+        }
+        Message::ToggleFunnel(endpoint, on) => {
+            return edit_serve(state, ServeEdit::Funnel(endpoint, on));
+        }
+        Message::ServeEdited(edit, result) => match result {
+            Ok(endpoints) => {
+                state.serve = Ok(endpoints);
+                if matches!(edit, ServeEdit::Add(_)) {
+                    state.serve_form = ServeForm {
+                        kind: state.serve_form.kind,
+                        ..Default::default()
+                    };
+                }
+                state.status_message = match edit {
+                    ServeEdit::Add(_) => "Serve mapping added".to_string(),
+                    ServeEdit::Remove(endpoint) => format!("Stopped serving {}", endpoint.url),
+                    ServeEdit::Funnel(endpoint, true) => {
+                        format!("{} is public through Funnel", endpoint.url)
+                    }
+                    ServeEdit::Funnel(endpoint, false) => {
+                        format!("{} is only on your tailnet", endpoint.url)
+                    }
+                };
+            }
+            Err(e) => state.status_message = format!("Error: {e}"),
+        },
+        Message::SwitchProfile(id) => {
+            return change_profile(state, ProfileChange::Switched, move |b| b.switch_profile(&id));
+        }
+        Message::AddProfile => {
+            return change_profile(state, ProfileChange::Added, |b| {
+                // The applet shows the auth URL from the IPN bus or status
+                b.add_profile().map(|_| ())
+            });
+        }
+        Message::Logout => {
+            return change_profile(state, ProfileChange::LoggedOut, |b| b.logout());
+        }
+        Message::ProfileChanged(change, result) => match result {
+            Ok(snapshot) => {
+                apply_snapshot(state, *snapshot);
+                state.status_message = match change {
+                    ProfileChange::Switched => format!("Switched to {}", state.login_name),
+                    ProfileChange::Added => {
+                        "Finish logging in to the new account from the panel applet".to_string()
+                    }
+                    ProfileChange::LoggedOut => "Logged out".to_string(),
+                };
+            }
+            Err(e) => state.status_message = format!("Error: {e}"),
+        },
+        Message::RunNetcheck => {
+            if state.netcheck_running {
+                return Task::none();
+            }
+            state.netcheck_running = true;
+            // netcheck takes a few seconds
+            return run_backend(state, |b| b.netcheck(), Message::NetcheckDone);
+        }
+        Message::NetcheckDone(result) => {
+            state.netcheck_running = false;
+            state.netcheck = Some(result);
+        }
+        Message::CopyNetcheckJson => {
+            if let Some(Ok(ref report)) = state.netcheck {
+                state.status_message = "Netcheck report copied".to_string();
+                return cosmic::iced::clipboard::write(report.json.clone());
             }
         }
         Message::OpenAdminConsole => {
@@ -67,17 +497,182 @@ pub fn update(state: &mut State, message: Message) {
             });
         }
         Message::Reload => {
-            match tailscale::get_prefs() {
-                Ok(prefs) => {
-                    state.accept_dns = prefs.accept_dns;
-                    state.accept_routes = prefs.accept_routes;
-                    state.login_name = prefs.login_name;
-                    state.status_message = "Settings reloaded".to_string();
-                }
-                Err(e) => state.status_message = format!("Error reloading: {e}"),
+            let self_dns = state.self_dns.clone();
+            return run_backend(
+                state,
+                move |b| read_snapshot(b, &self_dns).map(Box::new),
+                Message::Reloaded,
+            );
+        }
+        Message::Reloaded(result) => match result {
+            Ok(snapshot) => {
+                apply_snapshot(state, *snapshot);
+                state.status_message = "Settings reloaded".to_string();
             }
+            Err(e) => state.status_message = format!("Error reloading: {e}"),
+        },
+    }
+    Task::none()
+}
+
+/// Run `call` against the backend on its own thread, as tailscaled can take
+/// seconds to answer, and deliver its result as `done`.
+fn run_backend<T: Send + 'static>(
+    state: &State,
+    call: impl FnOnce(&dyn TailscaleBackend) -> Result<T, TailscaleError> + Send + 'static,
+    done: impl FnOnce(Result<T, TailscaleError>) -> Message + Send + 'static,
+) -> Task<Message> {
+    let backend = state.backend.clone();
+    let (tx, rx) = oneshot::channel();
+    std::thread::spawn(move || {
+        let _ = tx.send(call(backend.as_ref()));
+    });
+    Task::perform(
+        async move {
+            rx.await.unwrap_or_else(|_| {
+                Err(TailscaleError::CommandFailed("tailscaled did not answer".to_string()))
+            })
+        },
+        done,
+    )
+}
+
+/// Apply a Taildrive change off the UI thread, then list the shares again.
+fn edit_shares(
+    state: &State,
+    done: String,
+    edit: impl FnOnce(&dyn TailscaleBackend) -> Result<(), TailscaleError> + Send + 'static,
+) -> Task<Message> {
+    run_backend(
+        state,
+        move |b| {
+            edit(b)?;
+            b.drive_shares()
+        },
+        move |result| Message::SharesEdited(done, result),
+    )
+}
+
+fn save_notifications(state: &mut State, change: impl FnOnce(&mut NotificationSettings)) {
+    state.config.update(|config| change(&mut config.notifications));
+    state.status_message = "Notification settings updated".to_string();
+}
+
+fn save_probing(state: &mut State, change: impl FnOnce(&mut ProbeSettings)) {
+    state.config.update(|config| change(&mut config.probing));
+    state.status_message = "Service probing updated".to_string();
+}
+
+/// Advertise `routes`, and the exit node if `exit_node` is set, off the UI
+/// thread. They are kept once [`Message::RoutesSet`] reports success.
+fn set_routes(state: &State, routes: Vec<String>, exit_node: bool) -> Task<Message> {
+    run_backend(
+        state,
+        move |b| {
+            b.set_advertised_routes(&routes, exit_node)?;
+            Ok((routes, exit_node))
+        },
+        Message::RoutesSet,
+    )
+}
+
+/// What changed between the advertised routes in `state` and the new ones.
+fn routes_done(state: &State, routes: &[String], exit_node: bool) -> String {
+    if exit_node != state.advertise_exit_node {
+        if exit_node {
+            "Offering this device as an exit node".to_string()
+        } else {
+            "No longer offering this device as an exit node".to_string()
         }
+    } else if let Some(added) = routes.iter().find(|&r| !state.advertise_routes.contains(r)) {
+        format!("Advertising {added}")
+    } else if let Some(removed) = state.advertise_routes.iter().find(|&r| !routes.contains(r)) {
+        format!("Stopped advertising {removed}")
+    } else {
+        "Advertised routes updated".to_string()
+    }
+}
+
+/// Apply a Serve change off the UI thread, then re-read the config.
+fn edit_serve(state: &State, edit: ServeEdit) -> Task<Message> {
+    let self_dns = state.self_dns.clone();
+    let edited = edit.clone();
+    run_backend(
+        state,
+        move |b| {
+            b.edit_serve(&self_dns, &edit)?;
+            b.serve_config(&self_dns)
+        },
+        move |result| Message::ServeEdited(edited, result),
+    )
+}
+
+fn save_taildrop(state: &mut State, change: impl FnOnce(&mut TaildropSettings)) {
+    state.config.update(|config| change(&mut config.taildrop));
+    state.status_message = "Taildrop settings updated".to_string();
+}
+
+/// Entries of a comma-separated list, without blanks.
+fn split_list(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Make an account change off the UI thread, then read everything again.
+fn change_profile(
+    state: &State,
+    change: ProfileChange,
+    call: impl FnOnce(&dyn TailscaleBackend) -> Result<(), TailscaleError> + Send + 'static,
+) -> Task<Message> {
+    let self_dns = state.self_dns.clone();
+    run_backend(
+        state,
+        move |b| {
+            call(b)?;
+            read_snapshot(b, &self_dns).map(Box::new)
+        },
+        move |result| Message::ProfileChanged(change, result),
+    )
+}
+
+/// Read prefs, status, profiles, shares and Serve mappings. `self_dns` is
+/// used for Serve when the status cannot be read.
+fn read_snapshot(
+    backend: &dyn TailscaleBackend,
+    self_dns: &str,
+) -> Result<BackendSnapshot, TailscaleError> {
+    let prefs = backend.prefs()?;
+    let self_node = backend.status().map(|s| s.self_node).ok();
+    let self_dns = self_node.as_ref().map_or(self_dns, |n| n.dns_name.as_str());
+    Ok(BackendSnapshot {
+        serve: backend.serve_config(self_dns),
+        profiles: backend.profiles().ok(),
+        shares: backend.drive_shares(),
+        self_node,
+        prefs,
+    })
+}
+
+fn apply_snapshot(state: &mut State, snapshot: BackendSnapshot) {
+    if let Some(profiles) = snapshot.profiles {
+        state.profiles = profiles;
+    }
+    state.shares = snapshot.shares;
+    if let Some(node) = snapshot.self_node {
+        state.self_dns = node.dns_name;
+        state.approved_routes = node.primary_routes;
+        state.exit_node_approved = node.exit_node_option;
     }
+    state.serve = snapshot.serve;
+    let prefs = snapshot.prefs;
+    state.accept_dns = prefs.accept_dns;
+    state.accept_routes = prefs.accept_routes;
+    state.advertise_exit_node = prefs.advertise_exit_node;
+    state.advertise_routes = prefs.advertise_routes;
+    state.login_name = prefs.login_name;
 }
 
 pub fn view(state: &State) -> Element<'_, Message> {
@@ -90,13 +685,34 @@ pub fn view(state: &State) -> Element<'_, Message> {
         state.login_name.clone()
     };
 
-    let account_section = settings::section()
+    let mut account_section = settings::section()
         .title("Account")
         .add(settings::item(
             "Logged in as",
             text::body(account_label),
         ));
 
+    for profile in state.profiles.iter().filter(|p| !p.current) {
+        let label = if profile.tailnet.is_empty() {
+            profile.account.clone()
+        } else {
+            format!("{} ({})", profile.account, profile.tailnet)
+        };
+        account_section = account_section.add(settings::item(
+            label,
+            button::standard("Switch").on_press(Message::SwitchProfile(profile.id.clone())),
+        ));
+    }
+
+    account_section = account_section.add(settings::item_row(vec![
+        button::standard("Add account")
+            .on_press(Message::AddProfile)
+            .into(),
+        button::destructive("Log out")
+            .on_press_maybe((!state.login_name.is_empty()).then_some(Message::Logout))
+            .into(),
+    ]));
+
     // Network section
     let network_section = settings::section()
         .title("Network")
@@ -109,6 +725,335 @@ pub fn view(state: &State) -> Element<'_, Message> {
             toggler(state.accept_routes).on_toggle(Message::ToggleAcceptRoutes),
         ));
 
+    // Routing section: what this device offers the tailnet
+    let approval = |approved: bool| {
+        if approved {
+            "Approved"
+        } else {
+            "Awaiting approval"
+        }
+    };
+    let mut routing_section = settings::section()
+        .title("Exit Node and Subnet Routes")
+        .add(settings::item(
+            "Offer this device as an exit node",
+            toggler(state.advertise_exit_node).on_toggle(Message::ToggleAdvertiseExitNode),
+        ));
+    let mut awaiting = false;
+    if state.advertise_exit_node {
+        awaiting |= !state.exit_node_approved;
+        routing_section = routing_section.add(settings::item(
+            "Exit node",
+            text::caption(approval(state.exit_node_approved)),
+        ));
+    }
+    for route in &state.advertise_routes {
+        let approved = state.approved_routes.contains(route);
+        awaiting |= !approved;
+        routing_section = routing_section.add(settings::item(
+            route,
+            row![
+                text::caption(approval(approved)),
+                button::standard("Remove").on_press(Message::RemoveRoute(route.clone())),
+            ]
+            .spacing(8)
+            .align_y(Alignment::Center),
+        ));
+    }
+    let new_route = tailscale::parse_route(&state.new_route);
+    routing_section = routing_section.add(settings::item_row(vec![
+        text_input("192.168.1.0/24", &state.new_route)
+            .on_input(Message::NewRoute)
+            .into(),
+        button::standard("Advertise Route")
+            .on_press_maybe(new_route.is_ok().then_some(Message::AddRoute))
+            .into(),
+    ]));
+    if let Err(ref e) = new_route {
+        if !state.new_route.trim().is_empty() {
+            routing_section =
+                routing_section.add(settings::item("Invalid route", text::caption(e.as_str())));
+        }
+    }
+    if awaiting {
+        routing_section = routing_section.add(settings::item(
+            "Approve routes and exit nodes in the admin console",
+            button::suggested("Open Admin Console").on_press(Message::OpenAdminConsole),
+        ));
+    }
+
+    // Applet section
+    let warning_days = state.config.key_expiry_warning_days;
+    let applet_section = settings::section().title("Applet").add(settings::item(
+        "Warn before this device's key expires",
+        dropdown(
+            &KEY_EXPIRY_WARNING_LABELS,
+            KEY_EXPIRY_WARNING_DAYS
+                .iter()
+                .position(|&d| d == warning_days),
+            Message::SetKeyExpiryWarning,
+        ),
+    ));
+
+    // Notifications section
+    let notify = &state.config.notifications;
+    let notifications_section = settings::section()
+        .title("Notifications")
+        .add(settings::item(
+            "Tunnel disconnects or reconnects",
+            toggler(notify.tunnel).on_toggle(Message::NotifyTunnel),
+        ))
+        .add(settings::item(
+            "Favorite peer goes online or offline",
+            toggler(notify.favorite_peers).on_toggle(Message::NotifyFavoritePeers),
+        ))
+        .add(settings::item(
+            "Key is about to expire",
+            toggler(notify.key_expiry).on_toggle(Message::NotifyKeyExpiry),
+        ))
+        .add(settings::item(
+            "Exit node becomes unreachable",
+            toggler(notify.exit_node).on_toggle(Message::NotifyExitNode),
+        ));
+
+    // Service probing section
+    let probing = &state.config.probing;
+    let probing_section = settings::section()
+        .title("Service Probing")
+        .add(settings::item(
+            "Check peers for open services",
+            toggler(probing.enabled).on_toggle(Message::ToggleProbing),
+        ))
+        .add(settings::item(
+            "Only my devices",
+            toggler(probing.only_mine).on_toggle(Message::ToggleProbeOnlyMine),
+        ))
+        .add(settings::item(
+            "Only peers tagged",
+            text_input("server, dev", &state.probe_tags)
+                .on_input(Message::ProbeTags)
+                .on_submit(|_| Message::CommitProbeLists)
+                .on_unfocus(Message::CommitProbeLists),
+        ))
+        .add(settings::item(
+            "Only these hosts",
+            text_input("nas, 100.64.0.3", &state.probe_hosts)
+                .on_input(Message::ProbeHosts)
+                .on_submit(|_| Message::CommitProbeLists)
+                .on_unfocus(Message::CommitProbeLists),
+        ));
+
+    // Taildrop section
+    let default_dir = TaildropSettings::default()
+        .save_dir()
+        .display()
+        .to_string();
+    let taildrop_section = settings::section()
+        .title("Taildrop")
+        .add(settings::item(
+            "Save received files without asking",
+            toggler(state.config.taildrop.auto_accept).on_toggle(Message::ToggleAutoAccept),
+        ))
+        .add(settings::item(
+            "Save to folder",
+            text_input(default_dir, &state.taildrop_dir).on_input(Message::TaildropDir),
+        ));
+
+    // Taildrive section
+    let mut shares_section = settings::section().title("Shared Folders");
+    match state.shares {
+        Ok(ref shares) => {
+            for share in shares {
+                let item: Element<'_, Message> = match state.renaming {
+                    Some((ref old, ref new)) if *old == share.name => {
+                        let valid = tailscale::valid_share_name(new.trim());
+                        settings::item_row(vec![
+                            text_input("Name", new).on_input(Message::RenameText).into(),
+                            button::suggested("Rename")
+                                .on_press_maybe(valid.then_some(Message::ConfirmRename))
+                                .into(),
+                            button::standard("Cancel")
+                                .on_press(Message::CancelShareEdit)
+                                .into(),
+                        ])
+                        .into()
+                    }
+                    _ => settings::item(
+                        &share.name,
+                        row![
+                            text::caption(&share.path),
+                            button::standard("Rename")
+                                .on_press(Message::StartRename(share.name.clone())),
+                            button::destructive("Remove")
+                                .on_press(Message::Unshare(share.name.clone())),
+                        ]
+                        .spacing(8)
+                        .align_y(Alignment::Center),
+                    )
+                    .into(),
+                };
+                shares_section = shares_section.add(item);
+            }
+            if shares.is_empty() && state.new_share.is_none() {
+                shares_section = shares_section.add(settings::item(
+                    "No folders shared",
+                    text::caption("Peers with access can browse shared folders"),
+                ));
+            }
+        }
+        Err(ref e) => {
+            shares_section = shares_section.add(settings::item(
+                "Taildrive unavailable",
+                text::body(e.to_string()),
+            ));
+        }
+    }
+    shares_section = match state.new_share {
+        Some((ref path, ref name)) => {
+            let valid = tailscale::valid_share_name(name.trim());
+            shares_section
+                .add(settings::item(
+                    format!("Share {}", path.display()),
+                    text::caption("Names use a-z, 0-9, _, () and spaces"),
+                ))
+                .add(settings::item_row(vec![
+                    text_input("Name", name)
+                        .on_input(Message::NewShareName)
+                        .into(),
+                    button::suggested("Share")
+                        .on_press_maybe(valid.then_some(Message::ConfirmShare))
+                        .into(),
+                    button::standard("Cancel")
+                        .on_press(Message::CancelShareEdit)
+                        .into(),
+                ]))
+        }
+        None => shares_section.add(settings::item_row(vec![
+            button::standard("Add Folder...")
+                .on_press(Message::AddShare)
+                .into(),
+        ])),
+    };
+
+    // Serve section
+    let mut serve_section = settings::section().title("Serve");
+    match state.serve {
+        Ok(ref endpoints) => {
+            for endpoint in endpoints {
+                let mut controls = row![text::caption(format!("→ {}", endpoint.target))]
+                    .spacing(8)
+                    .align_y(Alignment::Center);
+                if endpoint.foreground {
+                    controls = controls.push(text::caption("(foreground)"));
+                } else {
+                    if endpoint.can_funnel() {
+                        let toggled = endpoint.clone();
+                        controls = controls.push(text::caption("Funnel")).push(
+                            toggler(endpoint.funnel)
+                                .on_toggle(move |on| Message::ToggleFunnel(toggled.clone(), on)),
+                        );
+                    }
+                    controls = controls.push(
+                        button::destructive("Remove")
+                            .on_press(Message::RemoveServe(endpoint.clone())),
+                    );
+                }
+                serve_section = serve_section.add(settings::item(&endpoint.url, controls));
+            }
+            if endpoints.is_empty() {
+                serve_section = serve_section.add(settings::item(
+                    "Nothing served",
+                    text::caption("Share a local port or folder with your tailnet"),
+                ));
+            }
+        }
+        Err(ref e) => {
+            serve_section = serve_section.add(settings::item(
+                "Serve unavailable",
+                text::body(e.to_string()),
+            ));
+        }
+    }
+    let https = SERVE_KINDS.get(state.serve_form.kind) == Some(&ServeKind::Https);
+    let mut form: Vec<Element<'_, Message>> = vec![
+        dropdown(
+            &SERVE_KIND_LABELS,
+            Some(state.serve_form.kind),
+            Message::SetServeKind,
+        )
+        .into(),
+        text_input(if https { "443" } else { "Port" }, &state.serve_form.port)
+            .on_input(Message::ServePort)
+            .into(),
+    ];
+    if https {
+        form.push(
+            text_input("/", &state.serve_form.mount)
+                .on_input(Message::ServeMount)
+                .into(),
+        );
+    }
+    form.push(
+        text_input(
+            if https {
+                "3000 or /path/to/folder"
+            } else {
+                "22 or localhost:22"
+            },
+            &state.serve_form.target,
+        )
+        .on_input(Message::ServeTarget)
+        .into(),
+    );
+    form.push(
+        button::suggested("Add")
+            .on_press_maybe(
+                (!state.serve_form.target.trim().is_empty()).then_some(Message::AddServe),
+            )
+            .into(),
+    );
+    serve_section = serve_section.add(settings::item_row(form));
+
+    // Diagnostics section
+    let mut diagnostics_section = settings::section().title("Diagnostics").add(settings::item(
+        "Network check (UDP, NAT type, DERP latency)",
+        button::standard(if state.netcheck_running {
+            "Running..."
+        } else {
+            "Run netcheck"
+        })
+        .on_press_maybe((!state.netcheck_running).then_some(Message::RunNetcheck)),
+    ));
+
+    match state.netcheck {
+        Some(Ok(ref report)) => {
+            for (label, value) in report.summary() {
+                diagnostics_section =
+                    diagnostics_section.add(settings::item(label, text::body(value)));
+            }
+            let preferred = report.preferred_derp.as_ref().map(|r| r.id);
+            for (region, latency) in &report.region_latency {
+                let name = if preferred == Some(region.id) {
+                    format!("DERP {} (nearest)", region.name)
+                } else {
+                    format!("DERP {}", region.name)
+                };
+                diagnostics_section = diagnostics_section
+                    .add(settings::item(name, text::body(format!("{latency:.1} ms"))));
+            }
+            diagnostics_section = diagnostics_section.add(settings::item_row(vec![
+                button::standard("Copy JSON")
+                    .on_press(Message::CopyNetcheckJson)
+                    .into(),
+            ]));
+        }
+        Some(Err(ref e)) => {
+            diagnostics_section = diagnostics_section
+                .add(settings::item("Netcheck failed", text::body(e.to_string())));
+        }
+        None => {}
+    }
+
     // Actions section
     let actions_section = settings::section()
         .title("Management")
@@ -127,6 +1072,14 @@ pub fn view(state: &State) -> Element<'_, Message> {
         page_title.into(),
         account_section.into(),
         network_section.into(),
+        routing_section.into(),
+        applet_section.into(),
+        notifications_section.into(),
+        probing_section.into(),
+        taildrop_section.into(),
+        shares_section.into(),
+        serve_section.into(),
+        diagnostics_section.into(),
         actions_section.into(),
     ];
 
@@ -136,3 +1089,188 @@ pub fn view(state: &State) -> Element<'_, Message> {
 
     settings::view_column(content_items).into()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::FakeBackend;
+    use std::path::Path;
+    use std::time::Duration;
+
+    fn fixture_page() -> (State, Arc<FakeBackend>) {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("resources/fixtures");
+        let fake = Arc::new(FakeBackend::from_fixture_dir(&dir).unwrap());
+        (init_with(fake.clone(), AppConfig::default()), fake)
+    }
+
+    /// Wait for the thread `update` started to reach the backend.
+    fn eventually(check: impl Fn() -> bool) {
+        for _ in 0..200 {
+            if check() {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        panic!("the backend call never happened");
+    }
+
+    fn failed(reason: &str) -> TailscaleError {
+        TailscaleError::CommandFailed(reason.to_string())
+    }
+
+    #[test]
+    fn accept_dns_toggle() {
+        let (mut state, fake) = fixture_page();
+        assert!(state.accept_dns);
+
+        let _ = update(&mut state, Message::ToggleAcceptDns(false));
+        eventually(|| !fake.prefs().unwrap().accept_dns);
+        // Only the result changes the page
+        assert!(state.accept_dns);
+        let _ = update(&mut state, Message::AcceptDnsSet(Ok(false)));
+        assert!(!state.accept_dns);
+        assert_eq!(state.status_message, "Accept DNS updated");
+
+        let _ = update(&mut state, Message::AcceptDnsSet(Err(failed("denied"))));
+        assert!(!state.accept_dns);
+        assert!(state.status_message.starts_with("Error: "));
+        assert!(state.status_message.contains("denied"));
+    }
+
+    #[test]
+    fn accept_routes_toggle() {
+        let (mut state, fake) = fixture_page();
+        assert!(!state.accept_routes);
+
+        let _ = update(&mut state, Message::ToggleAcceptRoutes(true));
+        eventually(|| fake.prefs().unwrap().accept_routes);
+        let _ = update(&mut state, Message::AcceptRoutesSet(Ok(true)));
+        assert!(state.accept_routes);
+        assert_eq!(state.status_message, "Accept routes updated");
+
+        let _ = update(&mut state, Message::AcceptRoutesSet(Err(failed("denied"))));
+        assert!(state.accept_routes);
+        assert!(state.status_message.contains("denied"));
+    }
+
+    #[test]
+    fn invalid_route_is_not_sent() {
+        let (mut state, fake) = fixture_page();
+        let before = fake.prefs().unwrap().advertise_routes;
+
+        state.new_route = "10.0.0.0".to_string();
+        let _ = update(&mut state, Message::AddRoute);
+        assert!(state.status_message.starts_with("Error: "));
+        assert_eq!(state.new_route, "10.0.0.0");
+        assert_eq!(state.advertise_routes, before);
+        assert_eq!(fake.prefs().unwrap().advertise_routes, before);
+    }
+
+    #[test]
+    fn routes_set_updates_state() {
+        let (mut state, fake) = fixture_page();
+        state.new_route = "10.30.0.0/16".to_string();
+        let _ = update(&mut state, Message::AddRoute);
+        eventually(|| {
+            let routes = fake.prefs().unwrap().advertise_routes;
+            routes.contains(&"10.30.0.0/16".to_string())
+        });
+
+        let mut routes = state.advertise_routes.clone();
+        routes.push("10.30.0.0/16".to_string());
+        let _ = update(&mut state, Message::RoutesSet(Ok((routes.clone(), false))));
+        assert_eq!(state.advertise_routes, routes);
+        assert_eq!(state.status_message, "Advertising 10.30.0.0/16");
+        assert!(state.new_route.is_empty());
+
+        let _ = update(&mut state, Message::RoutesSet(Ok((routes.clone(), true))));
+        assert!(state.advertise_exit_node);
+        assert_eq!(state.status_message, "Offering this device as an exit node");
+
+        let _ = update(&mut state, Message::RoutesSet(Err(failed("denied"))));
+        assert_eq!(state.advertise_routes, routes);
+        assert!(state.advertise_exit_node);
+    }
+
+    #[test]
+    fn serve_edited_updates_state() {
+        let (mut state, _) = fixture_page();
+        state.serve_form = ServeForm {
+            kind: 1,
+            port: "5432".to_string(),
+            mount: String::new(),
+            target: "localhost:5432".to_string(),
+        };
+        let mapping = ServeMapping {
+            kind: ServeKind::Tcp,
+            port: 5432,
+            mount: String::new(),
+            target: "localhost:5432".to_string(),
+        };
+        let endpoint = ServeEndpoint {
+            url: "tcp://laptop.tail1234.ts.net:5432".to_string(),
+            target: "localhost:5432".to_string(),
+            funnel: false,
+            port: 5432,
+            mount: None,
+            foreground: false,
+        };
+
+        let edit = ServeEdit::Add(mapping.clone());
+        let _ = update(&mut state, Message::ServeEdited(edit, Err(failed("busy"))));
+        // The form is kept to try again
+        assert_eq!(state.serve_form.port, "5432");
+        assert!(state.status_message.contains("busy"));
+
+        let edit = ServeEdit::Add(mapping);
+        let done = Ok(vec![endpoint.clone()]);
+        let _ = update(&mut state, Message::ServeEdited(edit, done));
+        assert_eq!(state.status_message, "Serve mapping added");
+        assert_eq!(state.serve.as_ref().unwrap().len(), 1);
+        assert_eq!(state.serve_form.kind, 1);
+        assert!(state.serve_form.port.is_empty());
+
+        let edit = ServeEdit::Remove(endpoint);
+        let _ = update(&mut state, Message::ServeEdited(edit, Ok(Vec::new())));
+        assert_eq!(state.status_message, "Stopped serving tcp://laptop.tail1234.ts.net:5432");
+        assert!(state.serve.as_ref().unwrap().is_empty());
+    }
+
+    #[test]
+    fn shares_edited_updates_state() {
+        let (mut state, _) = fixture_page();
+        state.new_share = Some(("/home/alex/Photos".into(), "photos".to_string()));
+
+        let done = "Sharing /home/alex/Photos as photos".to_string();
+        let _ = update(&mut state, Message::SharesEdited(done.clone(), Err(failed("no"))));
+        assert!(state.new_share.is_some());
+        assert!(state.status_message.starts_with("Error: "));
+
+        let share = DriveShare {
+            name: "photos".to_string(),
+            path: "/home/alex/Photos".to_string(),
+        };
+        let listed = Ok(vec![share.clone()]);
+        let _ = update(&mut state, Message::SharesEdited(done.clone(), listed));
+        assert!(state.new_share.is_none());
+        assert_eq!(state.shares.as_ref().unwrap(), &vec![share]);
+        assert_eq!(state.status_message, done);
+    }
+
+    #[test]
+    fn probe_lists_saved_only_when_changed() {
+        let (mut state, _) = fixture_page();
+
+        let _ = update(&mut state, Message::ProbeTags("tag:server, nas".to_string()));
+        assert!(state.config.probing.tags.is_empty());
+        let _ = update(&mut state, Message::CommitProbeLists);
+        assert_eq!(state.config.probing.tags, ["tag:server", "nas"]);
+        assert_eq!(state.status_message, "Service probing updated");
+
+        // Leaving the field again without typing saves nothing
+        state.status_message.clear();
+        let _ = update(&mut state, Message::ProbeTags("tag:server,nas ".to_string()));
+        let _ = update(&mut state, Message::CommitProbeLists);
+        assert!(state.status_message.is_empty());
+    }
+}
@@ -1,24 +1,70 @@
+use serde::de::IgnoredAny;
 use serde::Deserialize;
-use std::collections::HashMap;
-use std::io::{Read, Write};
-use std::net::TcpStream;
-use std::process::Command;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
 use std::time::Duration;
 
+use crate::config::{Fingerprint, Launcher, ServiceDef};
+use crate::fingerprint;
+use crate::localapi::{LocalApi, LocalApiError};
+
 const PROBE_TIMEOUT: Duration = Duration::from_millis(300);
+/// Pings sent before giving up on a direct path, as `tailscale ping` does.
+const PING_COUNT: u32 = 10;
+const PING_INTERVAL: Duration = Duration::from_secs(1);
 
-#[derive(Debug, Clone, Default)]
+/// Services found open on a peer.
+#[derive(Debug, Clone, Default, Deserialize)]
 pub struct ServiceInfo {
-    pub ssh: bool,
-    pub vnc: bool,
+    #[serde(default)]
+    pub found: Vec<FoundService>,
+    /// HTTPS URLs the peer serves at its MagicDNS name with a valid
+    /// certificate, i.e. through Tailscale Serve or Funnel
+    #[serde(default)]
+    pub served: Vec<String>,
+    /// The peer advertises Funnel, so some of what it serves is public
+    #[serde(default)]
+    pub funnel: bool,
+    /// Why `served` could not be checked, e.g. curl is not installed
+    #[serde(skip)]
+    pub served_error: Option<String>,
+    /// The probe settings rule this peer out, so `found` is always empty
+    #[serde(skip)]
+    pub not_probed: bool,
+}
+
+impl ServiceInfo {
+    /// The open service with this registry ID.
+    pub fn get(&self, id: &str) -> Option<&FoundService> {
+        self.found.iter().find(|f| f.id == id)
+    }
+
+    pub fn has(&self, id: &str) -> bool {
+        self.get(id).is_some()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct FoundService {
+    /// [`ServiceDef::id`] of the service
+    pub id: String,
+    pub port: u16,
+    /// Set for services launched with [`Launcher::Vnc`]
+    #[serde(default)]
     pub vnc_type: VncType,
-    pub rdp: bool,
-    pub nomachine: bool,
-    pub http: bool,
-    pub https: bool,
+    /// Recognized app, e.g. "Jellyfin"
+    #[serde(default)]
+    pub app: Option<String>,
+    /// What the server said about itself, e.g. "OpenSSH 9.6p1 (Ubuntu-3)"
+    #[serde(default)]
+    pub details: Option<String>,
 }
 
-#[derive(Debug, Clone, Default, PartialEq)]
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
 pub enum VncType {
     #[default]
     None,
@@ -26,9 +72,113 @@ pub enum VncType {
     Other,
 }
 
-#[derive(Debug, Clone)]
+/// tailscaled's connection state (ipn.State).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BackendState {
+    #[default]
+    NoState,
+    NeedsLogin,
+    NeedsMachineAuth,
+    Stopped,
+    Starting,
+    Running,
+}
+
+impl BackendState {
+    /// Parse the `BackendState` name from status JSON.
+    pub fn from_name(name: &str) -> Self {
+        match name {
+            "NeedsLogin" => Self::NeedsLogin,
+            "NeedsMachineAuth" => Self::NeedsMachineAuth,
+            "Stopped" => Self::Stopped,
+            "Starting" => Self::Starting,
+            "Running" => Self::Running,
+            _ => Self::NoState,
+        }
+    }
+
+    /// Convert the numeric ipn.State used on the IPN bus.
+    fn from_ipn(state: i64) -> Self {
+        match state {
+            2 => Self::NeedsLogin,
+            3 => Self::NeedsMachineAuth,
+            4 => Self::Stopped,
+            5 => Self::Starting,
+            6 => Self::Running,
+            _ => Self::NoState,
+        }
+    }
+
+    /// Short user-facing description.
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::NoState => "Starting up",
+            Self::NeedsLogin => "Logged out",
+            Self::NeedsMachineAuth => "Waiting for admin approval",
+            Self::Stopped => "Disconnected",
+            Self::Starting => "Connecting...",
+            Self::Running => "Connected",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TailscaleError {
+    /// Neither the LocalAPI socket nor the `tailscale` binary is present.
+    NotInstalled,
+    /// tailscaled is not running.
+    DaemonNotRunning,
+    /// tailscaled refused the change: we are neither root nor the operator.
+    PermissionDenied(String),
+    /// Output from tailscaled could not be parsed.
+    Parse(String),
+    CommandFailed(String),
+}
+
+impl fmt::Display for TailscaleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotInstalled => write!(f, "Tailscale is not installed"),
+            Self::DaemonNotRunning => write!(f, "tailscaled is not running"),
+            Self::PermissionDenied(e) => write!(f, "Permission denied: {e}"),
+            Self::Parse(e) => write!(f, "{e}"),
+            Self::CommandFailed(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl From<LocalApiError> for TailscaleError {
+    fn from(e: LocalApiError) -> Self {
+        match e {
+            LocalApiError::Unavailable(_) => Self::DaemonNotRunning,
+            LocalApiError::Status(403, body) => Self::PermissionDenied(body.trim().to_string()),
+            e => Self::CommandFailed(e.to_string()),
+        }
+    }
+}
+
+impl TailscaleError {
+    /// Classify the stderr of a failed `tailscale` command.
+    fn from_cli(cmd: &str, stderr: &str) -> Self {
+        let lower = stderr.to_lowercase();
+        if lower.contains("doesn't appear to be running")
+            || lower.contains("failed to connect to local tailscale")
+        {
+            Self::DaemonNotRunning
+        } else if lower.contains("access denied")
+            || lower.contains("permission denied")
+            || lower.contains("--operator")
+        {
+            Self::PermissionDenied(stderr.trim().to_string())
+        } else {
+            Self::CommandFailed(format!("tailscale {cmd} failed: {}", stderr.trim()))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
 pub struct TailscaleStatus {
-    pub backend_state: String,
+    pub backend_state: BackendState,
     pub version: String,
     pub self_node: NodeInfo,
     pub peers: Vec<PeerInfo>,
@@ -36,14 +186,24 @@ pub struct TailscaleStatus {
     pub exit_node_active: bool,
     pub exit_node_name: String,
     pub cert_domains: Vec<String>,
+    /// URL to visit to finish logging in, while a login is pending.
+    pub auth_url: String,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct NodeInfo {
     pub hostname: String,
     pub dns_name: String,
     pub tailscale_ips: Vec<String>,
     pub relay: String,
+    pub user_id: i64,
+    /// When this node's key expires (unix seconds); None if it never does.
+    pub key_expiry: Option<i64>,
+    /// Advertised subnet routes the tailnet sends through this node, i.e.
+    /// the approved ones
+    pub primary_routes: Vec<String>,
+    /// Peers may use this node as an exit node (its advertisement is approved)
+    pub exit_node_option: bool,
 }
 
 impl NodeInfo {
@@ -72,17 +232,39 @@ impl NodeInfo {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct PeerInfo {
+    /// Stable node ID, unchanged across renames and key rotation.
+    pub id: String,
     pub hostname: String,
     pub dns_name: String,
     pub tailscale_ips: Vec<String>,
     pub os: String,
     pub online: bool,
     pub exit_node: bool,
+    /// The peer advertises itself as an exit node we may use.
+    pub exit_node_option: bool,
+    /// Geographic location, set for location-based exit nodes such as Mullvad.
+    pub location: Option<Location>,
     pub ssh_enabled: bool,
-    #[allow(dead_code)]
+    /// Home DERP region; traffic goes through it when there is no direct path.
     pub relay: String,
+    /// Endpoint of the direct path, empty while relayed.
+    pub cur_addr: String,
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+    /// Unix timestamps; `None` when tailscaled reports the zero time.
+    pub last_seen: Option<i64>,
+    pub last_handshake: Option<i64>,
+    pub key_expiry: Option<i64>,
+    /// Subnet routes this peer is serving.
+    pub primary_routes: Vec<String>,
+    /// ACL tags (e.g. "tag:server"); tagged nodes are owned by the tailnet.
+    pub tags: Vec<String>,
+    /// Owner of the node, comparable with the self node's `user_id`.
+    pub user_id: i64,
+    /// Owner's login name, from the status user map.
+    pub owner: String,
     pub services: ServiceInfo,
 }
 
@@ -100,41 +282,412 @@ impl PeerInfo {
     pub fn dns_display(&self) -> String {
         self.dns_name.trim_end_matches('.').to_string()
     }
+}
 
-    /// HTTPS URL for this peer (requires MagicDNS).
-    pub fn https_url(&self) -> String {
-        let dns = self.dns_display();
-        if !dns.is_empty() {
-            format!("https://{dns}")
+#[derive(Debug, Clone, Default)]
+pub struct Location {
+    pub country: String,
+    pub country_code: String,
+    pub city: String,
+    /// Higher is better when choosing among nodes in the same city.
+    pub priority: i64,
+}
+
+/// The exit node tailscaled recommends for this machine.
+#[derive(Debug, Clone)]
+pub struct ExitNodeSuggestion {
+    /// Stable node ID; empty when the suggestion came from the CLI.
+    pub id: String,
+    pub name: String,
+}
+
+impl ExitNodeSuggestion {
+    pub fn matches(&self, peer: &PeerInfo) -> bool {
+        if !self.id.is_empty() {
+            self.id == peer.id
         } else {
-            String::new()
+            !self.name.is_empty() && self.name.trim_end_matches('.') == peer.dns_display()
+        }
+    }
+}
+
+/// One mapping in this node's Serve config.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServeEndpoint {
+    /// Where the tailnet reaches it, e.g. "https://laptop.tail1234.ts.net/grafana"
+    pub url: String,
+    /// What it serves: a proxied URL, a local path, or a TCP address
+    pub target: String,
+    /// Also published to the internet through Funnel
+    pub funnel: bool,
+    pub port: u16,
+    /// Mount point of a web handler, e.g. "/docs"; None for TCP forwarding
+    pub mount: Option<String>,
+    /// Set up by a `tailscale serve` running in the foreground, which removes
+    /// it when it exits
+    pub foreground: bool,
+}
+
+impl ServeEndpoint {
+    /// Whether Funnel can be turned on or off for this mapping from here.
+    pub fn can_funnel(&self) -> bool {
+        !self.foreground && SERVE_PORTS.contains(&self.port)
+    }
+}
+
+/// How a new Serve mapping is reached.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ServeKind {
+    Https,
+    Tcp,
+}
+
+/// A mapping to add to this node's Serve config.
+#[derive(Debug, Clone)]
+pub struct ServeMapping {
+    pub kind: ServeKind,
+    pub port: u16,
+    /// Mount point for HTTPS, e.g. "/" or "/docs"
+    pub mount: String,
+    /// A local port, "host:port" or URL, or a directory to serve over HTTPS
+    pub target: String,
+}
+
+/// A change to this node's Serve config.
+#[derive(Debug, Clone)]
+pub enum ServeEdit {
+    Add(ServeMapping),
+    Remove(ServeEndpoint),
+    /// Publish a mapping to the internet through Funnel, or stop
+    Funnel(ServeEndpoint, bool),
+}
+
+/// A peer that accepts files from us over Taildrop.
+#[derive(Debug, Clone)]
+pub struct FileTarget {
+    /// Stable node ID; empty when the list came from the CLI.
+    pub id: String,
+    pub name: String,
+    pub ips: Vec<String>,
+}
+
+impl FileTarget {
+    pub fn matches(&self, peer: &PeerInfo) -> bool {
+        if !self.id.is_empty() {
+            self.id == peer.id
+        } else {
+            self.ips.iter().any(|ip| peer.tailscale_ips.contains(ip))
         }
     }
 }
 
+/// A file received over Taildrop, waiting for us to save or discard it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WaitingFile {
+    pub name: String,
+    pub size: u64,
+}
+
+/// A folder this machine shares with the tailnet over Taildrive.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DriveShare {
+    pub name: String,
+    pub path: String,
+}
+
+/// Location-based exit nodes in one country, grouped by city.
+#[derive(Debug, Clone)]
+pub struct ExitNodeCountry {
+    pub name: String,
+    /// ISO 3166-1 alpha-2 code, e.g. "SE".
+    pub code: String,
+    pub cities: Vec<ExitNodeCity>,
+}
+
+impl ExitNodeCountry {
+    /// Flag emoji built from the country code's regional indicator symbols.
+    pub fn flag(&self) -> String {
+        if self.code.len() != 2 || !self.code.chars().all(|c| c.is_ascii_alphabetic()) {
+            return String::new();
+        }
+        self.code
+            .to_ascii_uppercase()
+            .chars()
+            .filter_map(|c| char::from_u32(0x1F1E6 + (c as u32 - 'A' as u32)))
+            .collect()
+    }
+}
+
 #[derive(Debug, Clone)]
+pub struct ExitNodeCity {
+    pub name: String,
+    /// The node to use for this city: online first, then highest priority.
+    pub best: PeerInfo,
+    pub node_count: usize,
+}
+
+/// One message from the IPN notification bus, reduced to what the UI reacts to.
+#[derive(Debug, Clone, Default)]
+pub struct IpnNotify {
+    /// New backend state, if it changed.
+    pub state: Option<BackendState>,
+    pub prefs_changed: bool,
+    pub netmap_changed: bool,
+    /// Login URL tailscaled wants the user to open.
+    pub browse_to_url: Option<String>,
+    /// Taildrop files arrived and are waiting to be picked up.
+    pub files_waiting: bool,
+}
+
+#[derive(Debug, Clone, Default)]
 pub struct TailscalePrefs {
     pub accept_dns: bool,
     pub accept_routes: bool,
     pub login_name: String,
+    pub exit_node_id: String,
+    pub exit_node_allow_lan_access: bool,
+    /// Subnet routes this node offers, without the exit node's default routes
+    pub advertise_routes: Vec<String>,
+    pub advertise_exit_node: bool,
+    /// Block incoming connections from the tailnet
+    pub shields_up: bool,
+    /// Run the Tailscale SSH server
+    pub run_ssh: bool,
+}
+
+/// A logged-in account (tailscaled login profile) that can be switched to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoginProfile {
+    pub id: String,
+    pub tailnet: String,
+    /// Login name of the account (e.g. "alex@example.com")
+    pub account: String,
+    pub current: bool,
+}
+
+/// How a ping reply reached us.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PingPath {
+    /// Peer-to-peer, via this endpoint (e.g. "192.168.1.20:41641")
+    Direct(String),
+    /// Through a DERP relay, by region code (e.g. "syd")
+    Derp(String),
+}
+
+/// One reply to a disco ping.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pong {
+    pub latency_ms: f64,
+    pub path: PingPath,
+}
+
+/// A DERP relay region.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DerpRegion {
+    pub id: i64,
+    pub code: String,
+    pub name: String,
+}
+
+/// Result of `tailscale netcheck`: what the local network allows and how far
+/// each DERP region is.
+#[derive(Debug, Clone)]
+pub struct NetcheckReport {
+    pub udp: bool,
+    pub ipv4: bool,
+    pub ipv6: bool,
+    /// Public address as seen by the DERP servers (e.g. "203.0.113.5:41641")
+    pub global_v4: String,
+    pub global_v6: String,
+    /// NAT maps us to different ports per destination ("hard NAT"); `None` if
+    /// it could not be determined.
+    pub mapping_varies_by_dest_ip: Option<bool>,
+    pub preferred_derp: Option<DerpRegion>,
+    /// Latency to each region in milliseconds, fastest first.
+    pub region_latency: Vec<(DerpRegion, f64)>,
+    /// The report as tailscaled produced it, for support tickets.
+    pub json: String,
+}
+
+impl NetcheckReport {
+    /// Label/value rows summarising the report.
+    pub fn summary(&self) -> Vec<(&'static str, String)> {
+        let yes_no = |b: bool| if b { "Yes" } else { "No" }.to_string();
+        let mut rows = vec![
+            ("UDP", yes_no(self.udp)),
+            (
+                "IPv4",
+                if self.ipv4 && !self.global_v4.is_empty() {
+                    format!("Yes ({})", self.global_v4)
+                } else {
+                    yes_no(self.ipv4)
+                },
+            ),
+            (
+                "IPv6",
+                if self.ipv6 && !self.global_v6.is_empty() {
+                    format!("Yes ({})", self.global_v6)
+                } else {
+                    yes_no(self.ipv6)
+                },
+            ),
+            (
+                "Hard NAT",
+                match self.mapping_varies_by_dest_ip {
+                    Some(b) => yes_no(b),
+                    None => "Unknown".to_string(),
+                },
+            ),
+        ];
+        if let Some(ref region) = self.preferred_derp {
+            rows.push(("Nearest DERP", region.name.clone()));
+        }
+        rows
+    }
 }
 
 // --- Port probing ---
 
 fn check_port(ip: &str, port: u16) -> bool {
-    TcpStream::connect_timeout(
-        &format!("{ip}:{port}").parse().unwrap_or_else(|_| {
-            std::net::SocketAddr::from(([0, 0, 0, 0], port))
-        }),
-        PROBE_TIMEOUT,
-    )
-    .is_ok()
+    socket_addr(ip, port)
+        .is_some_and(|addr| TcpStream::connect_timeout(&addr, PROBE_TIMEOUT).is_ok())
 }
 
-fn detect_vnc_server(ip: &str) -> VncType {
-    let addr = match format!("{ip}:5900").parse() {
-        Ok(a) => a,
-        Err(_) => return VncType::None,
+/// The first line the server on `port` sends after we connect.
+fn read_banner(ip: &str, port: u16) -> Option<Vec<u8>> {
+    let addr = socket_addr(ip, port)?;
+    let stream = TcpStream::connect_timeout(&addr, PROBE_TIMEOUT).ok()?;
+    let _ = stream.set_read_timeout(Some(PROBE_TIMEOUT));
+    let mut banner = Vec::new();
+    let _ = BufReader::new(stream.take(256)).read_until(b'\n', &mut banner);
+    (!banner.is_empty()).then_some(banner)
+}
+
+/// `GET /` from the server on `port`, following up to two redirects on the
+/// same server. Returns the raw response, cut off after 64 KiB.
+fn http_get(ip: &str, port: u16, tls: bool) -> Option<Vec<u8>> {
+    if tls {
+        // No TLS stack of our own; curl is nearly always installed. Peer
+        // certificates name the MagicDNS host, not the IP, so don't verify.
+        let output = Command::new("curl")
+            .args(["-sik", "--max-time", "2", "--max-redirs", "2", "-L"])
+            .arg(format!("https://{}/", host_port(ip, port)))
+            .output()
+            .ok()?;
+        let mut body = output.stdout;
+        body.truncate(HTTP_LIMIT);
+        // With -L every hop's headers are printed; keep the last response
+        let start = find_last_status_line(&body);
+        return (!body.is_empty()).then(|| body.split_off(start));
+    }
+
+    let mut path = "/".to_string();
+    for _ in 0..3 {
+        let addr = socket_addr(ip, port)?;
+        let mut stream = TcpStream::connect_timeout(&addr, PROBE_TIMEOUT).ok()?;
+        let _ = stream.set_read_timeout(Some(HTTP_TIMEOUT));
+        let _ = stream.set_write_timeout(Some(PROBE_TIMEOUT));
+        let request = format!(
+            "GET {path} HTTP/1.0\r\nHost: {}\r\nUser-Agent: cosmic-tailscale\r\nConnection: close\r\n\r\n",
+            host_port(ip, port)
+        );
+        stream.write_all(request.as_bytes()).ok()?;
+        let mut response = Vec::new();
+        let _ = stream.take(HTTP_LIMIT as u64).read_to_end(&mut response);
+
+        let redirect = fingerprint::parse_http_response(&response)
+            .filter(|info| (300..400).contains(&info.status))
+            .and_then(|info| info.location)
+            .and_then(|location| same_server_path(&location, ip, port));
+        match redirect {
+            Some(next) => path = next,
+            None => return (!response.is_empty()).then_some(response),
+        }
+    }
+    None
+}
+
+const HTTP_TIMEOUT: Duration = Duration::from_secs(1);
+const HTTP_LIMIT: usize = 64 * 1024;
+
+/// Address of `port` on a Tailscale IP, which may be IPv6 (fd7a:115c:a1e0::/48).
+fn socket_addr(ip: &str, port: u16) -> Option<SocketAddr> {
+    ip.parse().ok().map(|ip: IpAddr| SocketAddr::new(ip, port))
+}
+
+fn host_port(ip: &str, port: u16) -> String {
+    if ip.contains(':') {
+        format!("[{ip}]:{port}")
+    } else {
+        format!("{ip}:{port}")
+    }
+}
+
+/// Offset of the final response in `curl -i -L` output, which prints the
+/// headers of every redirect hop before the last response.
+fn find_last_status_line(output: &[u8]) -> usize {
+    let mut start = 0;
+    while let Some(end) = output[start..]
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .map(|i| start + i + 4)
+    {
+        if !output[end..].starts_with(b"HTTP/") {
+            break;
+        }
+        start = end;
+    }
+    start
+}
+
+/// The path part of a redirect that stays on this server, if it does.
+fn same_server_path(location: &str, ip: &str, port: u16) -> Option<String> {
+    if location.starts_with('/') {
+        return Some(location.to_string());
+    }
+    let rest = location.strip_prefix("http://")?;
+    let (authority, path) = rest.split_once('/').unwrap_or((rest, ""));
+    (authority == host_port(ip, port) || (port == 80 && authority == ip))
+        .then(|| format!("/{path}"))
+}
+
+/// Server version, page title or app name for an open service.
+fn fingerprint_service(
+    kind: Fingerprint,
+    ip: &str,
+    port: u16,
+    banner: Option<&[u8]>,
+) -> (Option<String>, Option<String>) {
+    match kind {
+        Fingerprint::Ssh => {
+            let banner = match banner {
+                Some(b) => Some(b.to_vec()),
+                None => read_banner(ip, port),
+            };
+            let details = banner
+                .as_deref()
+                .and_then(fingerprint::parse_ssh_banner)
+                .map(|b| b.label());
+            (None, details)
+        }
+        Fingerprint::Http | Fingerprint::Https => {
+            let info = http_get(ip, port, kind == Fingerprint::Https)
+                .as_deref()
+                .and_then(fingerprint::parse_http_response);
+            match info {
+                Some(info) => (
+                    fingerprint::recognize_app(&info).map(str::to_string),
+                    Some(fingerprint::http_label(&info)),
+                ),
+                None => (None, None),
+            }
+        }
+    }
+}
+
+fn detect_vnc_server(ip: &str, port: u16) -> VncType {
+    let Some(addr) = socket_addr(ip, port) else {
+        return VncType::None;
     };
     let mut stream = match TcpStream::connect_timeout(&addr, PROBE_TIMEOUT) {
         Ok(s) => s,
@@ -145,7 +698,7 @@ fn detect_vnc_server(ip: &str) -> VncType {
 
     // Read RFB version banner (12 bytes like "RFB 003.008\n")
     let mut banner = [0u8; 12];
-    if stream.read_exact(&mut banner).is_err() {
+    if stream.read_exact(&mut banner).is_err() || !banner.starts_with(b"RFB ") {
         return VncType::None;
     }
     // Echo back the banner as our version reply
@@ -169,24 +722,136 @@ fn detect_vnc_server(ip: &str) -> VncType {
     }
 }
 
-pub fn probe_services(ip: &str) -> ServiceInfo {
-    let mut info = ServiceInfo::default();
+/// Check `ip` for each service in the registry, using the first open port,
+/// and `dns_name` for Tailscale Serve endpoints.
+pub fn probe_services(ip: &str, dns_name: &str, services: &[ServiceDef]) -> ServiceInfo {
+    let mut found = Vec::new();
+    for def in services {
+        for &port in &def.ports {
+            // The VNC handshake also tells RealVNC servers apart
+            let vnc_type = if def.launch == Launcher::Vnc {
+                detect_vnc_server(ip, port)
+            } else {
+                VncType::None
+            };
+            let mut banner = None;
+            let open = match def.banner {
+                _ if def.launch == Launcher::Vnc => vnc_type != VncType::None,
+                Some(ref prefix) => {
+                    banner = read_banner(ip, port);
+                    banner
+                        .as_ref()
+                        .is_some_and(|b| b.starts_with(prefix.as_bytes()))
+                }
+                None => check_port(ip, port),
+            };
+            if open {
+                let (app, details) = match def.fingerprint {
+                    Some(kind) => fingerprint_service(kind, ip, port, banner.as_deref()),
+                    None => (None, None),
+                };
+                found.push(FoundService {
+                    id: def.id.clone(),
+                    port,
+                    vnc_type,
+                    app,
+                    details,
+                });
+                break;
+            }
+        }
+    }
+    // Only ask curl and tailscaled about Serve when one of its ports answers
+    let serve_ports: Vec<u16> = SERVE_PORTS
+        .into_iter()
+        .filter(|&port| check_port(ip, port))
+        .collect();
+    let (served, served_error) = match probe_served(ip, dns_name, &serve_ports) {
+        Ok(served) => (served, None),
+        Err(e) => (Vec::new(), Some(e)),
+    };
+    ServiceInfo {
+        found,
+        served,
+        funnel: !serve_ports.is_empty() && peer_funnel(ip),
+        served_error,
+        not_probed: false,
+    }
+}
+
+/// Ports Tailscale Serve listens on for HTTPS by default, and the only ones
+/// Funnel allows.
+const SERVE_PORTS: [u16; 3] = [443, 8443, 10000];
+
+/// HTTPS endpoints on `dns_name`, among the `open_ports` found answering,
+/// that present a certificate valid for that name. Only Tailscale Serve (or
+/// a server using `tailscale cert`) has one.
+fn probe_served(ip: &str, dns_name: &str, open_ports: &[u16]) -> Result<Vec<String>, String> {
+    let host = dns_name.trim_end_matches('.');
+    if host.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut served = Vec::new();
+    for &port in open_ports {
+        // Connect to the IP we probed, but check the cert against the name
+        let status = Command::new("curl")
+            .args(["-s", "-o", "/dev/null", "--max-time", "2", "--resolve"])
+            .arg(format!("{host}:{port}:{ip}"))
+            .arg(format!("https://{host}:{port}/"))
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status();
+        match status {
+            Ok(s) if s.success() => served.push(match port {
+                443 => format!("https://{host}"),
+                _ => format!("https://{host}:{port}"),
+            }),
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err("curl not found, so Serve endpoints can't be checked".to_string());
+            }
+            Err(e) => return Err(format!("Failed to run curl: {e}")),
+        }
+    }
+    Ok(served)
+}
+
+/// Whether the peer at `ip` has Funnel turned on, going by the Hostinfo it
+/// advertises to the tailnet.
+fn peer_funnel(ip: &str) -> bool {
+    localapi_get(&format!("/localapi/v0/whois?addr={ip}"), &["whois", "--json", ip])
+        .is_ok_and(|body| parse_whois_funnel(&body))
+}
 
-    info.ssh = check_port(ip, 22);
+fn parse_whois_funnel(json: &[u8]) -> bool {
+    serde_json::from_slice::<RawWhoIs>(json)
+        .is_ok_and(|w| w.node.is_some_and(|n| n.hostinfo.ingress_enabled))
+}
 
-    let vnc_type = detect_vnc_server(ip);
-    info.vnc = vnc_type != VncType::None;
-    info.vnc_type = vnc_type;
+// --- Serde structs for parsing `tailscale status --json` / LocalAPI status ---
 
-    info.rdp = check_port(ip, 3389);
-    info.nomachine = check_port(ip, 4000);
-    info.http = check_port(ip, 80);
-    info.https = check_port(ip, 443);
+/// LocalAPI `whois` / `tailscale whois --json`, reduced to what we read.
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct RawWhoIs {
+    #[serde(default)]
+    node: Option<RawWhoIsNode>,
+}
 
-    info
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct RawWhoIsNode {
+    #[serde(default)]
+    hostinfo: RawHostinfo,
 }
 
-// --- Serde structs for parsing `tailscale status --json` ---
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct RawHostinfo {
+    /// Set while any of the node's Serve ports are exposed through Funnel
+    #[serde(default)]
+    ingress_enabled: bool,
+}
 
 #[derive(Deserialize)]
 #[serde(rename_all = "PascalCase")]
@@ -202,11 +867,17 @@ struct RawStatus {
     current_tailnet: Option<RawTailnet>,
     #[serde(default)]
     cert_domains: Option<Vec<String>>,
+    #[serde(default, rename = "AuthURL")]
+    auth_url: String,
+    #[serde(default)]
+    user: HashMap<String, RawUserProfile>,
 }
 
 #[derive(Deserialize)]
 #[serde(rename_all = "PascalCase")]
 struct RawPeer {
+    #[serde(default, rename = "ID")]
+    id: String,
     #[serde(default)]
     host_name: String,
     #[serde(default, rename = "DNSName")]
@@ -220,9 +891,44 @@ struct RawPeer {
     #[serde(default)]
     exit_node: bool,
     #[serde(default)]
+    exit_node_option: bool,
+    #[serde(default)]
+    location: Option<RawLocation>,
+    #[serde(default)]
     capabilities: Vec<String>,
     #[serde(default)]
     relay: String,
+    #[serde(default)]
+    cur_addr: String,
+    #[serde(default)]
+    rx_bytes: u64,
+    #[serde(default)]
+    tx_bytes: u64,
+    #[serde(default)]
+    last_seen: String,
+    #[serde(default)]
+    last_handshake: String,
+    #[serde(default)]
+    key_expiry: Option<String>,
+    #[serde(default)]
+    primary_routes: Option<Vec<String>>,
+    #[serde(default)]
+    tags: Option<Vec<String>>,
+    #[serde(default, rename = "UserID")]
+    user_id: i64,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct RawLocation {
+    #[serde(default)]
+    country: String,
+    #[serde(default)]
+    country_code: String,
+    #[serde(default)]
+    city: String,
+    #[serde(default)]
+    priority: i64,
 }
 
 #[derive(Deserialize)]
@@ -232,15 +938,25 @@ struct RawTailnet {
     name: String,
 }
 
-// Serde structs for parsing `tailscale debug prefs`
+// Serde structs for parsing `tailscale debug prefs` / LocalAPI prefs
 
 #[derive(Deserialize)]
 #[serde(rename_all = "PascalCase")]
 struct RawPrefs {
-    #[serde(default)]
+    #[serde(default, rename = "CorpDNS")]
     corp_dns: bool,
     #[serde(default)]
     route_all: bool,
+    #[serde(default, rename = "ExitNodeID")]
+    exit_node_id: String,
+    #[serde(default, rename = "ExitNodeAllowLANAccess")]
+    exit_node_allow_lan_access: bool,
+    #[serde(default)]
+    shields_up: bool,
+    #[serde(default, rename = "RunSSH")]
+    run_ssh: bool,
+    #[serde(default)]
+    advertise_routes: Option<Vec<String>>,
     #[serde(default)]
     config: Option<RawConfig>,
 }
@@ -259,58 +975,324 @@ struct RawUserProfile {
     login_name: String,
 }
 
-pub fn get_status() -> Result<TailscaleStatus, String> {
-    let output = Command::new("tailscale")
-        .args(["status", "--json"])
-        .output()
-        .map_err(|e| format!("Failed to run tailscale: {e}"))?;
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct RawSuggestion {
+    #[serde(default, rename = "ID")]
+    id: String,
+    #[serde(default)]
+    name: String,
+}
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(format!("tailscale status failed: {stderr}"));
-    }
+// Serde struct for LocalAPI ping results
 
-    let raw: RawStatus = serde_json::from_slice(&output.stdout)
-        .map_err(|e| format!("Failed to parse tailscale JSON: {e}"))?;
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct RawPingResult {
+    #[serde(default)]
+    err: String,
+    #[serde(default)]
+    latency_seconds: f64,
+    #[serde(default)]
+    endpoint: String,
+    #[serde(default, rename = "DERPRegionCode")]
+    derp_region_code: String,
+}
 
-    let cert_domains = raw.cert_domains.unwrap_or_default();
+// Serde structs for `tailscale netcheck --format=json` and the DERP map
 
-    let self_node = NodeInfo {
-        hostname: raw.self_node.host_name,
-        dns_name: raw.self_node.dns_name,
-        tailscale_ips: raw.self_node.tailscale_ips,
-        relay: raw.self_node.relay,
-    };
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct RawNetcheck {
+    #[serde(default, rename = "UDP")]
+    udp: bool,
+    #[serde(default, rename = "IPv4")]
+    ipv4: bool,
+    #[serde(default, rename = "IPv6")]
+    ipv6: bool,
+    #[serde(default, rename = "GlobalV4")]
+    global_v4: String,
+    #[serde(default, rename = "GlobalV6")]
+    global_v6: String,
+    #[serde(default, rename = "MappingVariesByDestIP")]
+    mapping_varies_by_dest_ip: Option<bool>,
+    #[serde(default, rename = "PreferredDERP")]
+    preferred_derp: i64,
+    /// Nanoseconds, keyed by region ID
+    #[serde(default)]
+    region_latency: HashMap<String, i64>,
+}
 
-    let mut exit_node_name = String::new();
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct RawDerpMap {
+    #[serde(default)]
+    regions: HashMap<String, RawDerpRegion>,
+}
 
-    let mut peers: Vec<PeerInfo> = raw
-        .peer
-        .into_values()
-        .map(|p| {
-            let ssh_enabled = p.capabilities.iter().any(|c| c.contains("/cap/ssh"));
-            let is_exit = p.exit_node;
-            let display = if !p.dns_name.is_empty() {
-                p.dns_name.split('.').next().unwrap_or(&p.host_name).to_string()
-            } else {
-                p.host_name.clone()
-            };
-            if is_exit {
-                exit_node_name = display;
-            }
-            PeerInfo {
-                hostname: p.host_name,
-                dns_name: p.dns_name,
-                tailscale_ips: p.tailscale_ips,
-                os: p.os,
-                online: p.online,
-                exit_node: p.exit_node,
-                ssh_enabled,
-                relay: p.relay,
-                services: ServiceInfo::default(),
-            }
-        })
-        .collect();
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct RawDerpRegion {
+    #[serde(default, rename = "RegionID")]
+    region_id: i64,
+    #[serde(default)]
+    region_code: String,
+    #[serde(default)]
+    region_name: String,
+}
+
+// Serde structs for LocalAPI login profiles
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct RawProfile {
+    #[serde(rename = "ID")]
+    id: String,
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    network_profile: Option<RawNetworkProfile>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct RawNetworkProfile {
+    #[serde(default)]
+    domain_name: String,
+}
+
+// Serde structs for LocalAPI serve-config / `tailscale serve status --json`
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "PascalCase", default)]
+struct RawServeConfig {
+    /// Keyed by port
+    #[serde(rename = "TCP")]
+    tcp: HashMap<String, RawTcpHandler>,
+    /// Keyed by "host:port"
+    web: HashMap<String, RawWebServer>,
+    /// Keyed by "host:port"
+    allow_funnel: HashMap<String, bool>,
+    /// `tailscale serve` sessions running in the foreground, by session ID
+    foreground: HashMap<String, RawServeConfig>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "PascalCase", default)]
+struct RawTcpHandler {
+    #[serde(rename = "HTTP")]
+    http: bool,
+    #[serde(rename = "TCPForward")]
+    tcp_forward: String,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "PascalCase", default)]
+struct RawWebServer {
+    /// Keyed by mount point, e.g. "/"
+    handlers: HashMap<String, RawHttpHandler>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "PascalCase", default)]
+struct RawHttpHandler {
+    path: String,
+    proxy: String,
+    text: String,
+}
+
+// Serde structs for LocalAPI file-targets
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct RawFileTarget {
+    node: RawFileTargetNode,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct RawFileTargetNode {
+    #[serde(rename = "StableID")]
+    stable_id: String,
+    #[serde(default)]
+    name: String,
+    /// CIDRs, e.g. "100.64.0.2/32"
+    #[serde(default)]
+    addresses: Vec<String>,
+}
+
+// Serde struct for LocalAPI files (apitype.WaitingFile)
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct RawWaitingFile {
+    name: String,
+    #[serde(default)]
+    size: u64,
+}
+
+// Serde struct for LocalAPI drive/shares (drive.Share)
+
+#[derive(Deserialize)]
+struct RawDriveShare {
+    name: String,
+    #[serde(default)]
+    path: String,
+}
+
+// Serde struct for messages on the IPN bus (`watch-ipn-bus` / `debug watch-ipn`)
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct RawNotify {
+    #[serde(default)]
+    state: Option<i64>,
+    #[serde(default)]
+    prefs: Option<IgnoredAny>,
+    #[serde(default)]
+    net_map: Option<IgnoredAny>,
+    #[serde(default, rename = "BrowseToURL")]
+    browse_to_url: Option<String>,
+    #[serde(default)]
+    files_waiting: Option<IgnoredAny>,
+}
+
+// --- Daemon access ---
+//
+// Each call goes to tailscaled's LocalAPI first and only spawns the CLI when
+// the socket cannot be opened (e.g. a non-standard socket path or a sandbox).
+
+/// Spawn `tailscale <args>`, mapping a missing binary to `NotInstalled`.
+fn spawn_cli(args: &[&str], stdout: Stdio) -> Result<Child, TailscaleError> {
+    Command::new("tailscale")
+        .args(args)
+        .stdout(stdout)
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => TailscaleError::NotInstalled,
+            _ => TailscaleError::CommandFailed(format!(
+                "Failed to run tailscale {}: {e}",
+                args.join(" ")
+            )),
+        })
+}
+
+/// Run `tailscale <args>` and return its stdout.
+fn run_cli(args: &[&str]) -> Result<Vec<u8>, TailscaleError> {
+    let output = spawn_cli(args, Stdio::piped())?
+        .wait_with_output()
+        .map_err(|e| TailscaleError::CommandFailed(e.to_string()))?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(TailscaleError::from_cli(&args.join(" "), &stderr));
+    }
+
+    Ok(output.stdout)
+}
+
+/// GET a LocalAPI endpoint, falling back to the equivalent CLI command.
+fn localapi_get(path: &str, cli_args: &[&str]) -> Result<Vec<u8>, TailscaleError> {
+    LocalApi::default().get_or_else(path, || run_cli(cli_args))
+}
+
+/// Apply a masked prefs edit (e.g. `{"CorpDNS": true, "CorpDNSSet": true}`),
+/// falling back to `tailscale <cli_args>`.
+fn edit_prefs(masked: serde_json::Value, cli_args: &[&str]) -> Result<(), TailscaleError> {
+    match LocalApi::default().patch_json("/localapi/v0/prefs", &masked) {
+        Ok(_) => Ok(()),
+        Err(LocalApiError::Unavailable(_)) => run_cli(cli_args).map(|_| ()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Map a `tailscale set` flag to the ipn.Prefs field it controls.
+fn pref_field(flag: &str) -> Option<&'static str> {
+    match flag {
+        "accept-dns" => Some("CorpDNS"),
+        "accept-routes" => Some("RouteAll"),
+        "exit-node-allow-lan-access" => Some("ExitNodeAllowLANAccess"),
+        "shields-up" => Some("ShieldsUp"),
+        "ssh" => Some("RunSSH"),
+        _ => None,
+    }
+}
+
+pub fn get_status() -> Result<TailscaleStatus, TailscaleError> {
+    let json = localapi_get("/localapi/v0/status", &["status", "--json"])?;
+    parse_status(&json)
+}
+
+/// Parse the JSON served by LocalAPI `status` / `tailscale status --json`.
+pub fn parse_status(json: &[u8]) -> Result<TailscaleStatus, TailscaleError> {
+    let raw: RawStatus = serde_json::from_slice(json)
+        .map_err(|e| TailscaleError::Parse(format!("Failed to parse tailscale JSON: {e}")))?;
+
+    let cert_domains = raw.cert_domains.unwrap_or_default();
+
+    let self_node = NodeInfo {
+        hostname: raw.self_node.host_name,
+        dns_name: raw.self_node.dns_name,
+        tailscale_ips: raw.self_node.tailscale_ips,
+        relay: raw.self_node.relay,
+        user_id: raw.self_node.user_id,
+        key_expiry: raw.self_node.key_expiry.as_deref().and_then(parse_rfc3339),
+        primary_routes: raw.self_node.primary_routes.unwrap_or_default(),
+        exit_node_option: raw.self_node.exit_node_option,
+    };
+
+    let mut exit_node_name = String::new();
+
+    let mut peers: Vec<PeerInfo> = raw
+        .peer
+        .into_values()
+        .map(|p| {
+            let ssh_enabled = p.capabilities.iter().any(|c| c.contains("/cap/ssh"));
+            let is_exit = p.exit_node;
+            let display = if !p.dns_name.is_empty() {
+                p.dns_name.split('.').next().unwrap_or(&p.host_name).to_string()
+            } else {
+                p.host_name.clone()
+            };
+            if is_exit {
+                exit_node_name = display;
+            }
+            PeerInfo {
+                id: p.id,
+                hostname: p.host_name,
+                dns_name: p.dns_name,
+                tailscale_ips: p.tailscale_ips,
+                os: p.os,
+                online: p.online,
+                exit_node: p.exit_node,
+                exit_node_option: p.exit_node_option,
+                location: p.location.map(|l| Location {
+                    country: l.country,
+                    country_code: l.country_code,
+                    city: l.city,
+                    priority: l.priority,
+                }),
+                ssh_enabled,
+                relay: p.relay,
+                cur_addr: p.cur_addr,
+                rx_bytes: p.rx_bytes,
+                tx_bytes: p.tx_bytes,
+                last_seen: parse_rfc3339(&p.last_seen),
+                last_handshake: parse_rfc3339(&p.last_handshake),
+                key_expiry: p.key_expiry.as_deref().and_then(parse_rfc3339),
+                primary_routes: p.primary_routes.unwrap_or_default(),
+                tags: p.tags.unwrap_or_default(),
+                user_id: p.user_id,
+                owner: raw
+                    .user
+                    .get(&p.user_id.to_string())
+                    .map(|u| u.login_name.clone())
+                    .unwrap_or_default(),
+                services: ServiceInfo::default(),
+            }
+        })
+        .collect();
 
     // Sort: online first, then alphabetical by hostname
     peers.sort_by(|a, b| {
@@ -336,7 +1318,7 @@ pub fn get_status() -> Result<TailscaleStatus, String> {
     };
 
     Ok(TailscaleStatus {
-        backend_state: raw.backend_state,
+        backend_state: BackendState::from_name(&raw.backend_state),
         version,
         self_node,
         peers,
@@ -344,22 +1326,19 @@ pub fn get_status() -> Result<TailscaleStatus, String> {
         exit_node_active,
         exit_node_name,
         cert_domains,
+        auth_url: raw.auth_url,
     })
 }
 
-pub fn get_prefs() -> Result<TailscalePrefs, String> {
-    let output = Command::new("tailscale")
-        .args(["debug", "prefs"])
-        .output()
-        .map_err(|e| format!("Failed to run tailscale debug prefs: {e}"))?;
-
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(format!("tailscale debug prefs failed: {stderr}"));
-    }
+pub fn get_prefs() -> Result<TailscalePrefs, TailscaleError> {
+    let json = localapi_get("/localapi/v0/prefs", &["debug", "prefs"])?;
+    parse_prefs(&json)
+}
 
-    let raw: RawPrefs = serde_json::from_slice(&output.stdout)
-        .map_err(|e| format!("Failed to parse prefs JSON: {e}"))?;
+/// Parse the JSON served by LocalAPI `prefs` / `tailscale debug prefs`.
+pub fn parse_prefs(json: &[u8]) -> Result<TailscalePrefs, TailscaleError> {
+    let raw: RawPrefs = serde_json::from_slice(json)
+        .map_err(|e| TailscaleError::Parse(format!("Failed to parse prefs JSON: {e}")))?;
 
     let login_name = raw
         .config
@@ -367,57 +1346,1730 @@ pub fn get_prefs() -> Result<TailscalePrefs, String> {
         .map(|u| u.login_name)
         .unwrap_or_default();
 
+    // An exit node advertises the default routes alongside any subnets
+    let (exit_routes, advertise_routes): (Vec<String>, Vec<String>) = raw
+        .advertise_routes
+        .unwrap_or_default()
+        .into_iter()
+        .partition(|r| EXIT_ROUTES.contains(&r.as_str()));
+
     Ok(TailscalePrefs {
         accept_dns: raw.corp_dns,
         accept_routes: raw.route_all,
         login_name,
+        exit_node_id: raw.exit_node_id,
+        exit_node_allow_lan_access: raw.exit_node_allow_lan_access,
+        advertise_routes,
+        advertise_exit_node: !exit_routes.is_empty(),
+        shields_up: raw.shields_up,
+        run_ssh: raw.run_ssh,
     })
 }
 
-pub fn set_bool_pref(flag: &str, value: bool) -> Result<(), String> {
+pub fn set_bool_pref(flag: &str, value: bool) -> Result<(), TailscaleError> {
     let arg = if value {
         format!("--{flag}")
     } else {
         format!("--{flag}=false")
     };
+    let cli_args = ["set", arg.as_str()];
 
-    let output = Command::new("tailscale")
-        .args(["set", &arg])
-        .output()
-        .map_err(|e| format!("Failed to run tailscale set: {e}"))?;
+    let Some(field) = pref_field(flag) else {
+        return run_cli(&cli_args).map(|_| ());
+    };
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(format!("tailscale set {flag} failed: {stderr}"));
+    let mut masked = serde_json::Map::new();
+    masked.insert(field.to_string(), value.into());
+    masked.insert(format!("{field}Set"), true.into());
+    edit_prefs(masked.into(), &cli_args)
+}
+
+/// Routes an exit node advertises.
+const EXIT_ROUTES: [&str; 2] = ["0.0.0.0/0", "::/0"];
+
+/// Offer `routes` (subnets) to the tailnet, and this node as an exit node if
+/// `exit_node` is set. Both need approval in the admin console, unless an
+/// auto-approver covers them.
+pub fn set_advertised_routes(routes: &[String], exit_node: bool) -> Result<(), TailscaleError> {
+    let mut all = routes.to_vec();
+    if exit_node {
+        all.extend(EXIT_ROUTES.map(String::from));
+    }
+    let routes_arg = format!("--advertise-routes={}", routes.join(","));
+    let exit_arg = format!("--advertise-exit-node={exit_node}");
+    edit_prefs(
+        serde_json::json!({
+            "AdvertiseRoutes": all,
+            "AdvertiseRoutesSet": true,
+        }),
+        &["set", &routes_arg, &exit_arg],
+    )
+}
+
+/// Check a subnet route typed by the user, returning it as tailscaled
+/// expects it (e.g. "192.168.1.0/24").
+pub fn parse_route(route: &str) -> Result<String, String> {
+    let route = route.trim();
+    let Some((addr, len)) = route.split_once('/') else {
+        return Err(format!("{route} needs a prefix length, e.g. {route}/24"));
+    };
+    let addr: IpAddr = addr
+        .parse()
+        .map_err(|_| format!("{addr} is not an IP address"))?;
+    let bits = if addr.is_ipv4() { 32 } else { 128 };
+    let len: u32 = len
+        .parse()
+        .ok()
+        .filter(|&l| l <= bits)
+        .ok_or_else(|| format!("/{len} is not a prefix length for {addr}"))?;
+    if len == 0 {
+        return Err("Offer this device as an exit node to route all traffic".to_string());
     }
 
-    Ok(())
+    let network = match addr {
+        IpAddr::V4(a) => IpAddr::from((u32::from(a) & (u32::MAX << (32 - len))).to_be_bytes()),
+        IpAddr::V6(a) => IpAddr::from((u128::from(a) & (u128::MAX << (128 - len))).to_be_bytes()),
+    };
+    if network != addr {
+        return Err(format!(
+            "{route} has host bits set; the subnet is {network}/{len}"
+        ));
+    }
+    Ok(format!("{network}/{len}"))
 }
 
-pub fn connect() -> Result<String, String> {
-    let output = Command::new("tailscale")
-        .args(["up"])
-        .output()
-        .map_err(|e| format!("Failed to run tailscale up: {e}"))?;
+/// Route internet traffic through `exit_node`, or stop using one with `None`.
+pub fn set_exit_node(exit_node: Option<&PeerInfo>) -> Result<(), TailscaleError> {
+    let (id, ip) = match exit_node {
+        Some(peer) => (
+            peer.id.as_str(),
+            peer.tailscale_ips.first().map(String::as_str).unwrap_or(""),
+        ),
+        None => ("", ""),
+    };
+    let arg = format!("--exit-node={ip}");
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(format!("tailscale up failed: {stderr}"));
+    // ExitNodeID and ExitNodeIP are alternatives; clear the IP so the ID wins.
+    edit_prefs(
+        serde_json::json!({
+            "ExitNodeID": id,
+            "ExitNodeIDSet": true,
+            "ExitNodeIP": "",
+            "ExitNodeIPSet": true,
+        }),
+        &["set", &arg],
+    )
+}
+
+pub fn suggest_exit_node() -> Result<ExitNodeSuggestion, TailscaleError> {
+    match LocalApi::default().get("/localapi/v0/suggest-exit-node") {
+        Ok(json) => {
+            let raw: RawSuggestion = serde_json::from_slice(&json).map_err(|e| {
+                TailscaleError::Parse(format!("Failed to parse exit node suggestion: {e}"))
+            })?;
+            Ok(ExitNodeSuggestion {
+                id: raw.id,
+                name: raw.name,
+            })
+        }
+        Err(LocalApiError::Unavailable(_)) => {
+            let out = run_cli(&["exit-node", "suggest"])?;
+            parse_suggest_output(&String::from_utf8_lossy(&out)).ok_or_else(|| {
+                TailscaleError::CommandFailed("No exit node suggestion available".to_string())
+            })
+        }
+        Err(e) => Err(e.into()),
     }
+}
 
-    Ok("Connected".to_string())
+/// Parse an RFC 3339 timestamp as Go encodes `time.Time` (e.g.
+/// "2024-05-01T09:30:00.123456789+12:00") into Unix seconds. Go's zero time
+/// ("0001-01-01T00:00:00Z") means "never" and yields `None`.
+pub fn parse_rfc3339(s: &str) -> Option<i64> {
+    let (date, time) = s.split_once(['T', 't'])?;
+    let mut ymd = date.splitn(3, '-').map(|n| n.parse::<i64>().ok());
+    let (year, month, day) = (ymd.next()??, ymd.next()??, ymd.next()??);
+    if year <= 1 {
+        return None;
+    }
+
+    // Split off the zone: "Z", "+hh:mm" or "-hh:mm"
+    let (clock, offset) = if let Some(clock) = time.strip_suffix(['Z', 'z']) {
+        (clock, 0)
+    } else {
+        let i = time.rfind(['+', '-'])?;
+        let (h, m) = time[i + 1..].split_once(':')?;
+        let secs = h.parse::<i64>().ok()? * 3600 + m.parse::<i64>().ok()? * 60;
+        (&time[..i], if &time[i..=i] == "-" { -secs } else { secs })
+    };
+    let clock = clock.split('.').next()?;
+    let mut hms = clock.splitn(3, ':').map(|n| n.parse::<i64>().ok());
+    let (hour, min, sec) = (hms.next()??, hms.next()??, hms.next()??);
+
+    // Days since the Unix epoch (proleptic Gregorian calendar)
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146_097 + doe - 719_468;
+
+    Some(days * 86_400 + hour * 3600 + min * 60 + sec - offset)
 }
 
-pub fn disconnect() -> Result<String, String> {
-    let output = Command::new("tailscale")
-        .args(["down"])
-        .output()
-        .map_err(|e| format!("Failed to run tailscale down: {e}"))?;
+/// Parse "Suggested exit node: <name>" from `tailscale exit-node suggest`.
+fn parse_suggest_output(out: &str) -> Option<ExitNodeSuggestion> {
+    out.lines()
+        .find_map(|l| l.trim().strip_prefix("Suggested exit node:"))
+        .map(|name| ExitNodeSuggestion {
+            id: String::new(),
+            name: name.trim().to_string(),
+        })
+}
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(format!("tailscale down failed: {stderr}"));
+/// Group location-based exit nodes by country and city, as
+/// `tailscale exit-node list` does. Nodes without a location are skipped.
+pub fn group_exit_node_locations(peers: &[PeerInfo]) -> Vec<ExitNodeCountry> {
+    let mut countries: BTreeMap<(&str, &str), BTreeMap<&str, Vec<&PeerInfo>>> = BTreeMap::new();
+    for peer in peers.iter().filter(|p| p.exit_node_option) {
+        if let Some(ref loc) = peer.location {
+            countries
+                .entry((loc.country.as_str(), loc.country_code.as_str()))
+                .or_default()
+                .entry(loc.city.as_str())
+                .or_default()
+                .push(peer);
+        }
     }
 
+    countries
+        .into_iter()
+        .map(|((country, code), cities)| ExitNodeCountry {
+            name: country.to_string(),
+            code: code.to_string(),
+            cities: cities
+                .into_iter()
+                .filter_map(|(city, nodes)| {
+                    let best = nodes.iter().max_by_key(|p| {
+                        (p.online, p.location.as_ref().map_or(0, |l| l.priority))
+                    })?;
+                    Some(ExitNodeCity {
+                        name: city.to_string(),
+                        best: (*best).clone(),
+                        node_count: nodes.len(),
+                    })
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+pub fn connect() -> Result<String, TailscaleError> {
+    edit_prefs(
+        serde_json::json!({"WantRunning": true, "WantRunningSet": true}),
+        &["up"],
+    )?;
+    Ok("Connected".to_string())
+}
+
+pub fn disconnect() -> Result<String, TailscaleError> {
+    edit_prefs(
+        serde_json::json!({"WantRunning": false, "WantRunningSet": true}),
+        &["down"],
+    )?;
     Ok("Disconnected".to_string())
 }
+
+/// Start an interactive login. tailscaled then publishes an auth URL for the
+/// user to visit. The CLI fallback runs `tailscale login` in the background,
+/// since it blocks until the login completes, and returns the URL it prints.
+pub fn login() -> Result<Option<String>, TailscaleError> {
+    match LocalApi::default().post("/localapi/v0/login-interactive") {
+        Ok(_) => Ok(None),
+        Err(LocalApiError::Unavailable(_)) => login_cli(),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Run `tailscale login` until it prints the auth URL on stderr, then leave
+/// it waiting for the user in the background.
+fn login_cli() -> Result<Option<String>, TailscaleError> {
+    let mut child = spawn_cli(&["login"], Stdio::null())?;
+    let mut lines = BufReader::new(child.stderr.take().expect("stderr is piped")).lines();
+    let mut stderr = String::new();
+    while let Some(Ok(line)) = lines.next() {
+        if let Some(url) = parse_auth_url(&line) {
+            let url = url.to_string();
+            // Keep draining stderr so the CLI never blocks on a full pipe
+            std::thread::spawn(move || {
+                lines.for_each(drop);
+                child.wait()
+            });
+            return Ok(Some(url));
+        }
+        stderr.push_str(&line);
+        stderr.push('\n');
+    }
+
+    // It exited without a URL: either already logged in, or it failed
+    let status = child
+        .wait()
+        .map_err(|e| TailscaleError::CommandFailed(e.to_string()))?;
+    if status.success() {
+        Ok(None)
+    } else {
+        Err(TailscaleError::from_cli("login", &stderr))
+    }
+}
+
+/// The auth URL in a line of `tailscale login` output, if any.
+fn parse_auth_url(line: &str) -> Option<&str> {
+    line.split_whitespace().find(|w| w.starts_with("https://"))
+}
+
+// --- Ping ---
+
+/// Ping `ip` over the tailnet, calling `on_pong` for each reply.
+///
+/// Like `tailscale ping`, stops at the first direct reply or after
+/// [`PING_COUNT`] pings. Blocks for the whole run.
+pub fn ping(ip: &str, on_pong: &mut dyn FnMut(Pong)) -> Result<(), TailscaleError> {
+    let api = LocalApi::default();
+    let path = format!("/localapi/v0/ping?ip={ip}&type=disco");
+    for i in 0..PING_COUNT {
+        if i > 0 {
+            std::thread::sleep(PING_INTERVAL);
+        }
+        let json = match api.post(&path) {
+            Ok(json) => json,
+            Err(LocalApiError::Unavailable(_)) if i == 0 => return ping_cli(ip, on_pong),
+            Err(e) => return Err(e.into()),
+        };
+        let raw: RawPingResult = serde_json::from_slice(&json)
+            .map_err(|e| TailscaleError::Parse(format!("Failed to parse ping result: {e}")))?;
+        if !raw.err.is_empty() {
+            return Err(TailscaleError::CommandFailed(raw.err));
+        }
+        let path = if raw.endpoint.is_empty() {
+            PingPath::Derp(raw.derp_region_code)
+        } else {
+            PingPath::Direct(raw.endpoint)
+        };
+        let direct = matches!(path, PingPath::Direct(_));
+        on_pong(Pong {
+            latency_ms: raw.latency_seconds * 1000.0,
+            path,
+        });
+        if direct {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// `tailscale ping`, reading replies as they are printed.
+fn ping_cli(ip: &str, on_pong: &mut dyn FnMut(Pong)) -> Result<(), TailscaleError> {
+    let count = format!("-c={PING_COUNT}");
+    let args = ["ping", count.as_str(), ip];
+    let mut child = spawn_cli(&args, Stdio::piped())?;
+    if let Some(stdout) = child.stdout.take() {
+        for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+            if let Some(pong) = parse_pong_line(&line) {
+                on_pong(pong);
+            }
+        }
+    }
+    let output = child
+        .wait_with_output()
+        .map_err(|e| TailscaleError::CommandFailed(e.to_string()))?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    // Replies that all came via DERP are still a successful ping for us
+    if !output.status.success() && !stderr.contains("direct connection not established") {
+        return Err(TailscaleError::from_cli(&args.join(" "), &stderr));
+    }
+    Ok(())
+}
+
+/// Parse "pong from desktop (100.64.0.2) via DERP(syd) in 45ms" or
+/// "... via 192.168.1.20:41641 in 3ms".
+fn parse_pong_line(line: &str) -> Option<Pong> {
+    let rest = line.strip_prefix("pong from ")?;
+    let (_, rest) = rest.split_once(" via ")?;
+    let (via, latency) = rest.rsplit_once(" in ")?;
+    // A Go duration: "45ms", or "1.203s" past a second
+    let latency = latency.trim();
+    let latency_ms = match latency.strip_suffix("ms") {
+        Some(ms) => ms.parse().ok()?,
+        None => latency.strip_suffix('s')?.parse::<f64>().ok()? * 1000.0,
+    };
+    let path = match via.strip_prefix("DERP(").and_then(|r| r.strip_suffix(')')) {
+        Some(region) => PingPath::Derp(region.to_string()),
+        None => PingPath::Direct(via.to_string()),
+    };
+    Some(Pong { latency_ms, path })
+}
+
+// --- Netcheck ---
+
+/// Run `tailscale netcheck`. Takes a few seconds; region names come from the
+/// DERP map when it is available.
+pub fn netcheck() -> Result<NetcheckReport, TailscaleError> {
+    let report = run_cli(&["netcheck", "--format=json"])?;
+    let derp_map = localapi_get("/localapi/v0/derpmap", &["debug", "derp-map"]).ok();
+    parse_netcheck(&report, derp_map.as_deref())
+}
+
+pub fn parse_netcheck(
+    json: &[u8],
+    derp_map: Option<&[u8]>,
+) -> Result<NetcheckReport, TailscaleError> {
+    let raw: RawNetcheck = serde_json::from_slice(json)
+        .map_err(|e| TailscaleError::Parse(format!("Failed to parse netcheck report: {e}")))?;
+    let regions: HashMap<i64, RawDerpRegion> = derp_map
+        .and_then(|m| serde_json::from_slice::<RawDerpMap>(m).ok())
+        .map(|m| m.regions.into_values().map(|r| (r.region_id, r)).collect())
+        .unwrap_or_default();
+    let region = |id: i64| match regions.get(&id) {
+        Some(r) => DerpRegion {
+            id,
+            code: r.region_code.clone(),
+            name: r.region_name.clone(),
+        },
+        None => DerpRegion {
+            id,
+            code: String::new(),
+            name: format!("Region {id}"),
+        },
+    };
+
+    let mut region_latency: Vec<(DerpRegion, f64)> = raw
+        .region_latency
+        .iter()
+        .filter_map(|(id, ns)| Some((region(id.parse().ok()?), *ns as f64 / 1_000_000.0)))
+        .collect();
+    region_latency.sort_by(|a, b| a.1.total_cmp(&b.1));
+
+    Ok(NetcheckReport {
+        udp: raw.udp,
+        ipv4: raw.ipv4,
+        ipv6: raw.ipv6,
+        global_v4: raw.global_v4,
+        global_v6: raw.global_v6,
+        mapping_varies_by_dest_ip: raw.mapping_varies_by_dest_ip,
+        preferred_derp: (raw.preferred_derp != 0).then(|| region(raw.preferred_derp)),
+        region_latency,
+        json: String::from_utf8_lossy(json).trim().to_string(),
+    })
+}
+
+// --- Serve ---
+
+/// This node's Serve and Funnel mappings. `self_dns` names the node in the
+/// URLs of plain TCP forwards, which the config only keys by port.
+pub fn get_serve_config(self_dns: &str) -> Result<Vec<ServeEndpoint>, TailscaleError> {
+    let json = localapi_get("/localapi/v0/serve-config", &["serve", "status", "--json"])?;
+    parse_serve_config(&json, self_dns)
+}
+
+/// Parse an ipn.ServeConfig, as served by LocalAPI `serve-config` and
+/// printed by `tailscale serve status --json`.
+pub fn parse_serve_config(
+    json: &[u8],
+    self_dns: &str,
+) -> Result<Vec<ServeEndpoint>, TailscaleError> {
+    // An unset config comes back as `null` or nothing at all
+    let json = String::from_utf8_lossy(json);
+    if matches!(json.trim(), "" | "null") {
+        return Ok(Vec::new());
+    }
+    let raw: RawServeConfig = serde_json::from_str(&json)
+        .map_err(|e| TailscaleError::Parse(format!("Failed to parse serve config: {e}")))?;
+
+    let mut endpoints = Vec::new();
+    collect_serve_endpoints(&raw, self_dns.trim_end_matches('.'), false, &mut endpoints);
+    endpoints.sort_by(|a, b| a.url.cmp(&b.url));
+    endpoints.dedup();
+    Ok(endpoints)
+}
+
+fn collect_serve_endpoints(
+    raw: &RawServeConfig,
+    self_dns: &str,
+    foreground: bool,
+    out: &mut Vec<ServeEndpoint>,
+) {
+    for (host_port, server) in &raw.web {
+        let (host, port) = host_port.rsplit_once(':').unwrap_or((host_port, "443"));
+        let http = raw.tcp.get(port).is_some_and(|h| h.http);
+        let origin = match (http, port) {
+            (true, "80") => format!("http://{host}"),
+            (true, _) => format!("http://{host}:{port}"),
+            (false, "443") => format!("https://{host}"),
+            (false, _) => format!("https://{host}:{port}"),
+        };
+        let funnel = raw.allow_funnel.get(host_port).copied().unwrap_or(false);
+        for (mount, handler) in &server.handlers {
+            let target = if !handler.proxy.is_empty() {
+                handler.proxy.clone()
+            } else if !handler.path.is_empty() {
+                handler.path.clone()
+            } else {
+                format!("text \"{}\"", handler.text)
+            };
+            out.push(ServeEndpoint {
+                url: format!("{origin}{mount}"),
+                target,
+                funnel,
+                port: port.parse().unwrap_or(443),
+                mount: Some(mount.clone()),
+                foreground,
+            });
+        }
+    }
+    for (port, handler) in &raw.tcp {
+        if handler.tcp_forward.is_empty() {
+            continue;
+        }
+        let funnel = raw.allow_funnel.iter().any(|(host_port, &on)| {
+            on && host_port.rsplit_once(':').is_some_and(|(_, p)| p == port)
+        });
+        out.push(ServeEndpoint {
+            url: format!("tcp://{self_dns}:{port}"),
+            target: handler.tcp_forward.clone(),
+            funnel,
+            port: port.parse().unwrap_or(0),
+            mount: None,
+            foreground,
+        });
+    }
+    for session in raw.foreground.values() {
+        collect_serve_endpoints(session, self_dns, true, out);
+    }
+}
+
+/// Change this node's Serve config. Edits the config from LocalAPI
+/// `serve-config` and sets it back, falling back to `tailscale serve` or
+/// `tailscale funnel`.
+pub fn edit_serve(self_dns: &str, edit: &ServeEdit) -> Result<(), TailscaleError> {
+    let api = LocalApi::default();
+    match api.get("/localapi/v0/serve-config") {
+        Ok(json) => {
+            let config = apply_serve_edit(&json, self_dns, edit)?;
+            api.request("POST", "/localapi/v0/serve-config", Some(&config))?;
+            Ok(())
+        }
+        Err(LocalApiError::Unavailable(_)) => {
+            let args = serve_cli_args(edit)?;
+            let args: Vec<&str> = args.iter().map(String::as_str).collect();
+            run_cli(&args).map(|_| ())
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Apply `edit` to a JSON ipn.ServeConfig, keeping fields we don't know.
+pub fn apply_serve_edit(
+    json: &[u8],
+    self_dns: &str,
+    edit: &ServeEdit,
+) -> Result<Vec<u8>, TailscaleError> {
+    let text = String::from_utf8_lossy(json);
+    let mut config: serde_json::Value = if matches!(text.trim(), "" | "null") {
+        serde_json::json!({})
+    } else {
+        serde_json::from_str(&text)
+            .map_err(|e| TailscaleError::Parse(format!("Failed to parse serve config: {e}")))?
+    };
+    let Some(root) = config.as_object_mut() else {
+        return Err(TailscaleError::Parse(
+            "Serve config is not an object".to_string(),
+        ));
+    };
+    let host = self_dns.trim_end_matches('.');
+    let failed = |msg: String| Err(TailscaleError::CommandFailed(msg));
+
+    match edit {
+        ServeEdit::Add(mapping) => {
+            let port = mapping.port.to_string();
+            let host_port = format!("{host}:{port}");
+            let tcp_in_use = json_object(root, "TCP")
+                .get(&port)
+                .is_some_and(|h| h.get("TCPForward").is_some());
+            let web_in_use = json_object(root, "Web").contains_key(&host_port);
+            match mapping.kind {
+                ServeKind::Https => {
+                    if tcp_in_use {
+                        return failed(format!("Port {port} already forwards TCP"));
+                    }
+                    let mount = match mapping.mount.trim() {
+                        "" => "/".to_string(),
+                        m if m.starts_with('/') => m.to_string(),
+                        m => format!("/{m}"),
+                    };
+                    let handler = web_handler(&mapping.target)?;
+                    json_object(root, "TCP").insert(port, serde_json::json!({ "HTTPS": true }));
+                    let server = json_object(json_object(root, "Web"), &host_port);
+                    json_object(server, "Handlers").insert(mount, handler);
+                }
+                ServeKind::Tcp => {
+                    if web_in_use {
+                        return failed(format!("Port {port} already serves HTTPS"));
+                    }
+                    let target = expand_serve_target(&mapping.target, "tcp")?;
+                    let target = target.trim_start_matches("tcp://");
+                    json_object(root, "TCP")
+                        .insert(port, serde_json::json!({ "TCPForward": target }));
+                }
+            }
+        }
+        ServeEdit::Remove(endpoint) => {
+            let port = endpoint.port.to_string();
+            let host_port = format!("{host}:{port}");
+            if let Some(ref mount) = endpoint.mount {
+                let web = json_object(root, "Web");
+                let server = json_object(web, &host_port);
+                let handlers = json_object(server, "Handlers");
+                handlers.remove(mount);
+                if !handlers.is_empty() {
+                    return to_json(&config);
+                }
+                web.remove(&host_port);
+            }
+            json_object(root, "TCP").remove(&port);
+            json_object(root, "AllowFunnel").remove(&host_port);
+        }
+        ServeEdit::Funnel(endpoint, on) => {
+            if !SERVE_PORTS.contains(&endpoint.port) {
+                return failed(format!(
+                    "Funnel only works on ports 443, 8443 and 10000, not {}",
+                    endpoint.port
+                ));
+            }
+            let host_port = format!("{host}:{}", endpoint.port);
+            let funnel = json_object(root, "AllowFunnel");
+            if *on {
+                funnel.insert(host_port, true.into());
+            } else {
+                funnel.remove(&host_port);
+            }
+        }
+    }
+    // Leave no empty maps behind once the last mapping is gone
+    for key in ["TCP", "Web", "AllowFunnel"] {
+        if root.get(key).is_some_and(|v| v.as_object().is_some_and(|m| m.is_empty())) {
+            root.remove(key);
+        }
+    }
+    to_json(&config)
+}
+
+fn to_json(config: &serde_json::Value) -> Result<Vec<u8>, TailscaleError> {
+    serde_json::to_vec(config).map_err(|e| TailscaleError::Parse(e.to_string()))
+}
+
+/// The object under `key`, created (or replacing whatever was there) if need be.
+fn json_object<'a>(
+    parent: &'a mut serde_json::Map<String, serde_json::Value>,
+    key: &str,
+) -> &'a mut serde_json::Map<String, serde_json::Value> {
+    let value = parent
+        .entry(key)
+        .or_insert_with(|| serde_json::Value::Object(Default::default()));
+    if !value.is_object() {
+        *value = serde_json::Value::Object(Default::default());
+    }
+    match value {
+        serde_json::Value::Object(map) => map,
+        _ => unreachable!("replaced with an object above"),
+    }
+}
+
+/// A web handler for `target`: a directory to serve, or a proxy to a local
+/// port, address or URL.
+fn web_handler(target: &str) -> Result<serde_json::Value, TailscaleError> {
+    let target = target.trim();
+    if target.starts_with('/') {
+        if !Path::new(target).exists() {
+            return Err(TailscaleError::CommandFailed(format!(
+                "{target} does not exist"
+            )));
+        }
+        return Ok(serde_json::json!({ "Path": target }));
+    }
+    Ok(serde_json::json!({ "Proxy": expand_serve_target(target, "http")? }))
+}
+
+/// Expand a target as `tailscale serve` does: "3000" and "localhost:3000"
+/// become "http://127.0.0.1:3000" (or "tcp://..."). Only this machine may
+/// be proxied to.
+fn expand_serve_target(target: &str, default_scheme: &str) -> Result<String, TailscaleError> {
+    let target = target.trim();
+    if let Ok(port) = target.parse::<u16>() {
+        return Ok(format!("{default_scheme}://127.0.0.1:{port}"));
+    }
+    let (scheme, rest) = target.split_once("://").unwrap_or((default_scheme, target));
+    let schemes: &[&str] = match default_scheme {
+        "tcp" => &["tcp"],
+        _ => &["http", "https", "https+insecure"],
+    };
+    let authority = rest.split('/').next().unwrap_or(rest);
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) => (host, port.parse::<u16>().ok()),
+        None => (authority, None),
+    };
+    let port_ok = port.is_some() || (scheme != "tcp" && target.contains("://"));
+    if !schemes.contains(&scheme)
+        || !port_ok
+        || !matches!(host, "localhost" | "127.0.0.1" | "[::1]")
+    {
+        return Err(TailscaleError::CommandFailed(format!(
+            "Can't serve {target:?}: use a port, or an address or URL on this machine"
+        )));
+    }
+    Ok(format!("{scheme}://{rest}"))
+}
+
+/// The `tailscale serve` / `tailscale funnel` command making the same change.
+fn serve_cli_args(edit: &ServeEdit) -> Result<Vec<String>, TailscaleError> {
+    let listener = |endpoint: &ServeEndpoint| {
+        let mut args = match endpoint.mount {
+            Some(_) if endpoint.url.starts_with("http://") => {
+                vec![format!("--http={}", endpoint.port)]
+            }
+            Some(_) => vec![format!("--https={}", endpoint.port)],
+            None => vec![format!("--tcp={}", endpoint.port)],
+        };
+        if let Some(ref mount) = endpoint.mount {
+            args.push(format!("--set-path={mount}"));
+        }
+        args
+    };
+    let mut args = Vec::new();
+    match edit {
+        ServeEdit::Add(mapping) => {
+            args.extend(["serve".to_string(), "--bg".to_string()]);
+            match mapping.kind {
+                ServeKind::Https => {
+                    args.push(format!("--https={}", mapping.port));
+                    if !matches!(mapping.mount.trim(), "" | "/") {
+                        args.push(format!("--set-path={}", mapping.mount.trim()));
+                    }
+                }
+                ServeKind::Tcp => args.push(format!("--tcp={}", mapping.port)),
+            }
+            args.push(mapping.target.clone());
+        }
+        ServeEdit::Remove(endpoint) => {
+            args.push("serve".to_string());
+            args.extend(listener(endpoint));
+            args.push("off".to_string());
+        }
+        // Setting the mapping up again with `funnel` or `serve` sets Funnel
+        ServeEdit::Funnel(endpoint, on) => {
+            if endpoint.target.starts_with("text ") {
+                return Err(TailscaleError::CommandFailed(
+                    "Changing Funnel for a text handler needs the LocalAPI socket".to_string(),
+                ));
+            }
+            args.push(if *on { "funnel" } else { "serve" }.to_string());
+            args.push("--bg".to_string());
+            args.extend(listener(endpoint));
+            args.push(endpoint.target.clone());
+        }
+    }
+    Ok(args)
+}
+
+// --- Taildrop ---
+
+/// Peers we can send files to. Uses LocalAPI `file-targets`, falling back to
+/// `tailscale file cp --targets`.
+pub fn file_targets() -> Result<Vec<FileTarget>, TailscaleError> {
+    match LocalApi::default().get("/localapi/v0/file-targets") {
+        Ok(json) => parse_file_targets(&json),
+        Err(LocalApiError::Unavailable(_)) => {
+            let out = run_cli(&["file", "cp", "--targets"])?;
+            Ok(parse_file_targets_output(&String::from_utf8_lossy(&out)))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub fn parse_file_targets(json: &[u8]) -> Result<Vec<FileTarget>, TailscaleError> {
+    // No targets comes back as `null`
+    let raw: Option<Vec<RawFileTarget>> = serde_json::from_slice(json)
+        .map_err(|e| TailscaleError::Parse(format!("Failed to parse file targets: {e}")))?;
+    Ok(raw
+        .unwrap_or_default()
+        .into_iter()
+        .map(|t| FileTarget {
+            id: t.node.stable_id,
+            name: t.node.name.trim_end_matches('.').to_string(),
+            ips: t
+                .node
+                .addresses
+                .iter()
+                .map(|a| a.split('/').next().unwrap_or(a).to_string())
+                .collect(),
+        })
+        .collect())
+}
+
+/// Parse `tailscale file cp --targets`: "<ip>\t<name>[\t<detail>]" per line.
+fn parse_file_targets_output(output: &str) -> Vec<FileTarget> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split('\t');
+            let ip = fields.next()?.trim();
+            let name = fields.next()?.trim();
+            Some(FileTarget {
+                id: String::new(),
+                name: name.to_string(),
+                ips: vec![ip.to_string()],
+            })
+        })
+        .collect()
+}
+
+/// Send the file at `path` to `peer` over Taildrop. LocalAPI `file-put`
+/// reports bytes sent to `on_progress` as it goes; the CLI fallback
+/// (`tailscale file cp`) only reports completion.
+pub fn send_file(
+    peer: &PeerInfo,
+    path: &Path,
+    on_progress: &mut dyn FnMut(u64),
+) -> Result<(), TailscaleError> {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let open_err = |e: std::io::Error| TailscaleError::CommandFailed(format!("{name}: {e}"));
+    let mut file = std::fs::File::open(path).map_err(open_err)?;
+    let len = file.metadata().map_err(open_err)?.len();
+
+    let api_path = format!(
+        "/localapi/v0/file-put/{}/{}",
+        peer.id,
+        escape_path_segment(&name)
+    );
+    match LocalApi::default().upload(&api_path, &mut file, len, on_progress) {
+        Ok(_) => Ok(()),
+        Err(LocalApiError::Unavailable(_)) => {
+            let ip = peer.tailscale_ips.first().cloned().unwrap_or_default();
+            let target = format!("{ip}:");
+            run_cli(&["file", "cp", &path.to_string_lossy(), &target])?;
+            on_progress(len);
+            Ok(())
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+// Received files have no CLI equivalent short of `tailscale file get`, which
+// takes every waiting file at once, so these need the LocalAPI socket.
+
+/// Taildrop files waiting to be saved.
+pub fn waiting_files() -> Result<Vec<WaitingFile>, TailscaleError> {
+    let json = LocalApi::default().get("/localapi/v0/files/")?;
+    parse_waiting_files(&json)
+}
+
+pub fn parse_waiting_files(json: &[u8]) -> Result<Vec<WaitingFile>, TailscaleError> {
+    let raw: Option<Vec<RawWaitingFile>> = serde_json::from_slice(json)
+        .map_err(|e| TailscaleError::Parse(format!("Failed to parse waiting files: {e}")))?;
+    Ok(raw
+        .unwrap_or_default()
+        .into_iter()
+        .map(|f| WaitingFile {
+            name: f.name,
+            size: f.size,
+        })
+        .collect())
+}
+
+/// Save a waiting file into `dir` and remove it from the inbox. A file of
+/// the same name already there is kept; the new one gets a " (1)" suffix.
+pub fn save_waiting_file(name: &str, dir: &Path) -> Result<PathBuf, TailscaleError> {
+    let api_path = format!("/localapi/v0/files/{}", escape_path_segment(name));
+    let mut body = LocalApi::default().stream(&api_path)?;
+
+    let io_err = |e: std::io::Error| TailscaleError::CommandFailed(format!("{name}: {e}"));
+    std::fs::create_dir_all(dir).map_err(io_err)?;
+    let (path, mut file) = create_unique(dir, name).map_err(io_err)?;
+    if let Err(e) = std::io::copy(&mut body, &mut file) {
+        let _ = std::fs::remove_file(&path);
+        return Err(io_err(e));
+    }
+
+    LocalApi::default().delete(&api_path)?;
+    Ok(path)
+}
+
+/// Delete a waiting file without saving it.
+pub fn delete_waiting_file(name: &str) -> Result<(), TailscaleError> {
+    let api_path = format!("/localapi/v0/files/{}", escape_path_segment(name));
+    LocalApi::default().delete(&api_path)?;
+    Ok(())
+}
+
+/// Create `name` in `dir`, or "stem (n).ext" if that is taken, as Taildrop
+/// itself does.
+pub fn create_unique(dir: &Path, name: &str) -> std::io::Result<(PathBuf, std::fs::File)> {
+    // Never let a name climb out of `dir`
+    let name = Path::new(name)
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "file".to_string());
+    let (stem, ext) = match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => (stem.to_string(), format!(".{ext}")),
+        _ => (name.clone(), String::new()),
+    };
+
+    let mut candidate = dir.join(&name);
+    let mut n = 0;
+    loop {
+        match std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&candidate)
+        {
+            Ok(file) => return Ok((candidate, file)),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                n += 1;
+                candidate = dir.join(format!("{stem} ({n}){ext}"));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Decode %XX escapes in a URL path, leaving malformed ones as they are.
+pub fn percent_decode(path: &str) -> Vec<u8> {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(b)) => {
+                decoded.push(b);
+                i += 3;
+            }
+            (b, _) => {
+                decoded.push(b);
+                i += 1;
+            }
+        }
+    }
+    decoded
+}
+
+/// Percent-encode a file name for use as one URL path segment.
+fn escape_path_segment(segment: &str) -> String {
+    let mut out = String::with_capacity(segment.len());
+    for b in segment.bytes() {
+        if b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{b:02X}"));
+        }
+    }
+    out
+}
+
+// --- Taildrive ---
+
+/// Taildrive's WebDAV server, which tailscaled answers on this machine.
+const DRIVE_SERVER: &str = "100.100.100.100:8080";
+const DRIVE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Folders this machine shares. Uses LocalAPI `drive/shares`, falling back
+/// to `tailscale drive list`.
+pub fn drive_shares() -> Result<Vec<DriveShare>, TailscaleError> {
+    match LocalApi::default().get("/localapi/v0/drive/shares") {
+        Ok(json) => parse_drive_shares(&json),
+        Err(LocalApiError::Unavailable(_)) => {
+            let out = run_cli(&["drive", "list"])?;
+            Ok(parse_drive_list_output(&String::from_utf8_lossy(&out)))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub fn parse_drive_shares(json: &[u8]) -> Result<Vec<DriveShare>, TailscaleError> {
+    let raw: Option<Vec<RawDriveShare>> = serde_json::from_slice(json)
+        .map_err(|e| TailscaleError::Parse(format!("Failed to parse shares: {e}")))?;
+    Ok(raw
+        .unwrap_or_default()
+        .into_iter()
+        .map(|s| DriveShare {
+            name: s.name,
+            path: s.path,
+        })
+        .collect())
+}
+
+/// Parse `tailscale drive list`: "name", "path" and "as" columns, padded to
+/// the widths of the dashes under the header.
+fn parse_drive_list_output(output: &str) -> Vec<DriveShare> {
+    let mut lines = output.lines().skip_while(|l| !l.starts_with('-'));
+    let Some(rule) = lines.next() else {
+        return Vec::new();
+    };
+    // Paths may contain spaces, so cut at the column offsets
+    let name_end = rule.find(' ').unwrap_or(rule.len());
+    let path_start = rule[name_end..]
+        .find('-')
+        .map_or(rule.len(), |i| name_end + i);
+    let path_end = rule[path_start..]
+        .find(' ')
+        .map_or(rule.len(), |i| path_start + i);
+
+    lines
+        .filter_map(|line| {
+            let name = line.get(..name_end)?.trim();
+            let path = line.get(path_start..path_end.min(line.len()))?.trim();
+            (!name.is_empty()).then(|| DriveShare {
+                name: name.to_string(),
+                path: path.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Apply a change to the shares through LocalAPI `drive/shares`, falling
+/// back to `tailscale <cli_args>`.
+fn edit_shares(method: &str, body: &[u8], cli_args: &[&str]) -> Result<(), TailscaleError> {
+    match LocalApi::default().request(method, "/localapi/v0/drive/shares", Some(body)) {
+        Ok(_) => Ok(()),
+        Err(LocalApiError::Unavailable(_)) => run_cli(cli_args).map(|_| ()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Share the folder at `path` as `name`, or point an existing share of that
+/// name at it.
+pub fn share_folder(name: &str, path: &Path) -> Result<(), TailscaleError> {
+    let path = path.to_string_lossy();
+    let body = serde_json::json!({ "name": name, "path": path }).to_string();
+    edit_shares("PUT", body.as_bytes(), &["drive", "share", name, &path])
+}
+
+pub fn unshare_folder(name: &str) -> Result<(), TailscaleError> {
+    // The body is the bare name, not JSON
+    edit_shares("DELETE", name.as_bytes(), &["drive", "unshare", name])
+}
+
+pub fn rename_share(old: &str, new: &str) -> Result<(), TailscaleError> {
+    let body = serde_json::json!([old, new]).to_string();
+    edit_shares("POST", body.as_bytes(), &["drive", "rename", old, new])
+}
+
+/// Whether tailscaled accepts `name` for a share: lowercase letters, digits,
+/// underscores, parentheses and spaces.
+pub fn valid_share_name(name: &str) -> bool {
+    !name.trim().is_empty()
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b"_() ".contains(&b))
+}
+
+/// A valid share name based on a folder's name, e.g. "My-Photos" becomes
+/// "my_photos".
+pub fn suggest_share_name(path: &Path) -> String {
+    let name: String = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_lowercase() || c.is_ascii_digit() || "_() ".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    match name.trim() {
+        "" => "share".to_string(),
+        name => name.to_string(),
+    }
+}
+
+/// Names of the folders `peer_name` shares with us, listed with a WebDAV
+/// `PROPFIND` on Taildrive's local server. A peer sharing nothing (or
+/// nothing we may see) is not found there, which gives an empty list.
+pub fn peer_drives(tailnet: &str, peer_name: &str) -> Result<Vec<String>, TailscaleError> {
+    let unavailable = |e: std::io::Error| {
+        TailscaleError::CommandFailed(format!("Taildrive is not available: {e}"))
+    };
+    let dir = format!(
+        "/{}/{}/",
+        escape_path_segment(tailnet),
+        escape_path_segment(peer_name)
+    );
+    let addr = DRIVE_SERVER.parse().map_err(|_| {
+        TailscaleError::CommandFailed(format!("Bad Taildrive address {DRIVE_SERVER}"))
+    })?;
+    let mut stream = TcpStream::connect_timeout(&addr, DRIVE_TIMEOUT).map_err(unavailable)?;
+    let _ = stream.set_read_timeout(Some(DRIVE_TIMEOUT));
+    let _ = stream.set_write_timeout(Some(DRIVE_TIMEOUT));
+    // HTTP/1.0 so the reply is not chunked
+    let request = format!(
+        "PROPFIND {dir} HTTP/1.0\r\nHost: {DRIVE_SERVER}\r\nDepth: 1\r\nContent-Length: 0\r\n\r\n"
+    );
+    stream.write_all(request.as_bytes()).map_err(unavailable)?;
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).map_err(unavailable)?;
+
+    let status = fingerprint::parse_http_response(&response).map(|info| info.status);
+    match status {
+        Some(207) => {}
+        Some(404) => return Ok(Vec::new()),
+        Some(code) => {
+            return Err(TailscaleError::CommandFailed(format!(
+                "Taildrive replied with HTTP {code}"
+            )))
+        }
+        None => {
+            return Err(TailscaleError::Parse(
+                "Taildrive sent no HTTP response".to_string(),
+            ))
+        }
+    }
+    Ok(parse_propfind_children(
+        &String::from_utf8_lossy(&response),
+        &dir,
+    ))
+}
+
+/// Names of the entries directly inside `dir` in a WebDAV multistatus reply.
+pub fn parse_propfind_children(xml: &str, dir: &str) -> Vec<String> {
+    let dir = String::from_utf8_lossy(&percent_decode(dir)).into_owned();
+    let dir = dir.trim_matches('/');
+    let mut names: Vec<String> = Vec::new();
+    for piece in xml.split('<').skip(1) {
+        let Some((tag, text)) = piece.split_once('>') else {
+            continue;
+        };
+        // Any namespace prefix: <D:href>, <d:href>, <href>
+        if tag.rsplit(':').next() != Some("href") {
+            continue;
+        }
+        let href = text.replace("&amp;", "&");
+        // Hrefs may be absolute URLs
+        let href = match href.split_once("://") {
+            Some((_, rest)) => rest.find('/').map_or("", |i| &rest[i..]).to_string(),
+            None => href,
+        };
+        let path = String::from_utf8_lossy(&percent_decode(&href)).into_owned();
+        let Some(name) = path
+            .trim_matches('/')
+            .strip_prefix(dir)
+            .and_then(|rest| rest.strip_prefix('/'))
+        else {
+            continue;
+        };
+        if !name.is_empty() && !name.contains('/') && !names.iter().any(|n| n == name) {
+            names.push(name.to_string());
+        }
+    }
+    names.sort();
+    names
+}
+
+/// Where the file manager can open a peer's shared folder, through the
+/// desktop's WebDAV support (e.g. GVfs).
+pub fn drive_url(tailnet: &str, peer_name: &str, share: &str) -> String {
+    format!(
+        "dav://{DRIVE_SERVER}/{}/{}/{}",
+        escape_path_segment(tailnet),
+        escape_path_segment(peer_name),
+        escape_path_segment(share)
+    )
+}
+
+// --- Login profiles ---
+
+/// List login profiles, marking the current one.
+pub fn list_profiles() -> Result<Vec<LoginProfile>, TailscaleError> {
+    let api = LocalApi::default();
+    let parse_err = |e: serde_json::Error| {
+        TailscaleError::Parse(format!("Failed to parse login profiles: {e}"))
+    };
+    match api.get("/localapi/v0/profiles/") {
+        Ok(json) => {
+            let all: Vec<RawProfile> = serde_json::from_slice(&json).map_err(parse_err)?;
+            let current: RawProfile =
+                serde_json::from_slice(&api.get("/localapi/v0/profiles/current")?)
+                    .map_err(parse_err)?;
+            Ok(all
+                .into_iter()
+                .map(|p| LoginProfile {
+                    current: p.id == current.id,
+                    tailnet: p.network_profile.map(|n| n.domain_name).unwrap_or_default(),
+                    account: p.name,
+                    id: p.id,
+                })
+                .collect())
+        }
+        Err(LocalApiError::Unavailable(_)) => {
+            let out = run_cli(&["switch", "--list"])?;
+            Ok(parse_switch_list(&String::from_utf8_lossy(&out)))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Parse the `ID Tailnet Account` table from `tailscale switch --list`,
+/// where the current account is suffixed with `*`.
+fn parse_switch_list(out: &str) -> Vec<LoginProfile> {
+    out.lines()
+        .skip(1)
+        .filter_map(|line| {
+            let cols: Vec<&str> = line.split_whitespace().collect();
+            let (id, tailnet, account) = match cols[..] {
+                [id, tailnet, account] => (id, tailnet, account),
+                [id, account] => (id, "", account),
+                _ => return None,
+            };
+            Some(LoginProfile {
+                id: id.to_string(),
+                tailnet: tailnet.to_string(),
+                account: account.trim_end_matches('*').to_string(),
+                current: account.ends_with('*'),
+            })
+        })
+        .collect()
+}
+
+pub fn switch_profile(id: &str) -> Result<(), TailscaleError> {
+    match LocalApi::default().post(&format!("/localapi/v0/profiles/{id}")) {
+        Ok(_) => Ok(()),
+        Err(LocalApiError::Unavailable(_)) => run_cli(&["switch", id]).map(|_| ()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Create an empty profile, switch to it and start logging in to it.
+pub fn add_profile() -> Result<Option<String>, TailscaleError> {
+    match LocalApi::default().put("/localapi/v0/profiles/") {
+        Ok(_) => login(),
+        // `tailscale login` adds a profile when one is already logged in
+        Err(LocalApiError::Unavailable(_)) => login(),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Log out of the current profile, which removes it.
+pub fn logout() -> Result<(), TailscaleError> {
+    match LocalApi::default().post("/localapi/v0/logout") {
+        Ok(_) => Ok(()),
+        Err(LocalApiError::Unavailable(_)) => run_cli(&["logout"]).map(|_| ()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// ipn.NotifyWatchOpt bits: initial state, prefs and netmap, no private
+/// keys, and let tailscaled rate-limit netmap updates.
+const WATCH_MASK: u32 = 2 | 4 | 8 | 16 | 256;
+
+/// Follow the IPN notification bus, calling `on_notify` for each message.
+///
+/// Blocks until the stream ends or fails; the first message describes the
+/// current state. Uses LocalAPI `watch-ipn-bus`, falling back to
+/// `tailscale debug watch-ipn`.
+pub fn watch_ipn(on_notify: &mut dyn FnMut(IpnNotify)) -> Result<(), TailscaleError> {
+    let path = format!("/localapi/v0/watch-ipn-bus?mask={WATCH_MASK}");
+    match LocalApi::default().stream(&path) {
+        Ok(body) => read_notify_stream(body, on_notify),
+        Err(LocalApiError::Unavailable(_)) => {
+            let args = ["debug", "watch-ipn", "-initial"];
+            let mut child = spawn_cli(&args, Stdio::piped())?;
+            let result = match child.stdout.take() {
+                Some(stdout) => read_notify_stream(stdout, on_notify),
+                None => Ok(()),
+            };
+            let _ = child.kill();
+            let output = child
+                .wait_with_output()
+                .map_err(|e| TailscaleError::CommandFailed(e.to_string()))?;
+            // A watcher that dies at once (e.g. daemon down) says why on stderr
+            if !output.stderr.is_empty() {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                return Err(TailscaleError::from_cli(&args.join(" "), &stderr));
+            }
+            result
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Decode a stream of concatenated ipn.Notify JSON objects.
+fn read_notify_stream(
+    reader: impl Read,
+    on_notify: &mut dyn FnMut(IpnNotify),
+) -> Result<(), TailscaleError> {
+    for raw in serde_json::Deserializer::from_reader(reader).into_iter::<RawNotify>() {
+        let raw = raw.map_err(|e| TailscaleError::Parse(format!("IPN bus stream ended: {e}")))?;
+        on_notify(IpnNotify {
+            state: raw.state.map(BackendState::from_ipn),
+            prefs_changed: raw.prefs.is_some(),
+            netmap_changed: raw.net_map.is_some(),
+            browse_to_url: raw.browse_to_url.filter(|url| !url.is_empty()),
+            files_waiting: raw.files_waiting.is_some(),
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn socket_addr_takes_ipv4_and_ipv6() {
+        assert_eq!(
+            socket_addr("100.64.0.3", 22),
+            Some(SocketAddr::from(([100, 64, 0, 3], 22)))
+        );
+        let v6 = socket_addr("fd7a:115c:a1e0::3", 80).unwrap();
+        assert!(v6.is_ipv6());
+        assert_eq!(v6.to_string(), "[fd7a:115c:a1e0::3]:80");
+        assert_eq!(host_port("fd7a:115c:a1e0::3", 80), v6.to_string());
+        assert_eq!(socket_addr("nas.tail1234.ts.net", 22), None);
+    }
+
+    #[test]
+    fn whois_funnel_reads_ingress_enabled() {
+        let on = br#"{"Node":{"ID":1,"Hostinfo":{"Hostname":"nas","IngressEnabled":true}},"UserProfile":{}}"#;
+        assert!(parse_whois_funnel(on));
+        let off = br#"{"Node":{"ID":1,"Hostinfo":{"Hostname":"nas","WireIngress":true}}}"#;
+        assert!(!parse_whois_funnel(off));
+        assert!(!parse_whois_funnel(b"{}"));
+        assert!(!parse_whois_funnel(b"not json"));
+    }
+
+    #[test]
+    fn vnc_detection_reaches_ipv6_peers() {
+        // Not every sandbox has an IPv6 loopback
+        let Ok(listener) = std::net::TcpListener::bind("[::1]:0") else {
+            return;
+        };
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(b"RFB 003.008\n").unwrap();
+            let mut reply = [0u8; 12];
+            stream.read_exact(&mut reply).unwrap();
+            // Two security types: RealVNC (30) and VNC auth (2)
+            stream.write_all(&[2, 30, 2]).unwrap();
+        });
+        assert_eq!(detect_vnc_server("::1", port), VncType::RealVnc);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn parse_route_validates_prefixes() {
+        let ok = [
+            ("192.168.1.0/24", "192.168.1.0/24"),
+            ("  10.0.0.0/8 ", "10.0.0.0/8"),
+            ("10.1.2.3/32", "10.1.2.3/32"),
+            ("fd00:1234::/64", "fd00:1234::/64"),
+            ("2001:db8::1/128", "2001:db8::1/128"),
+        ];
+        for (input, route) in ok {
+            assert_eq!(parse_route(input).as_deref(), Ok(route), "{input}");
+        }
+
+        let err = [
+            ("192.168.1.0", "needs a prefix length, e.g. 192.168.1.0/24"),
+            ("192.168.1.0/33", "/33 is not a prefix length"),
+            ("fd00::/129", "/129 is not a prefix length"),
+            ("10.0.0.0/x", "/x is not a prefix length"),
+            ("nas/24", "nas is not an IP address"),
+            ("0.0.0.0/0", "exit node"),
+            ("::/0", "exit node"),
+            ("192.168.1.7/24", "host bits set; the subnet is 192.168.1.0/24"),
+            ("fd00::1/64", "the subnet is fd00::/64"),
+        ];
+        for (input, message) in err {
+            let e = parse_route(input).unwrap_err();
+            assert!(e.contains(message), "{input}: {e}");
+        }
+    }
+
+    const SELF_DNS: &str = "laptop.tail1234.ts.net.";
+    const SERVE_FIXTURE: &str = include_str!("../resources/fixtures/serve.json");
+
+    fn serve_edit(json: &str, edit: ServeEdit) -> Result<serde_json::Value, TailscaleError> {
+        apply_serve_edit(json.as_bytes(), SELF_DNS, &edit)
+            .map(|out| serde_json::from_slice(&out).unwrap())
+    }
+
+    fn add(kind: ServeKind, port: u16, target: &str) -> ServeEdit {
+        ServeEdit::Add(ServeMapping {
+            kind,
+            port,
+            mount: String::new(),
+            target: target.to_string(),
+        })
+    }
+
+    fn fixture_endpoint(url: &str) -> ServeEndpoint {
+        parse_serve_config(SERVE_FIXTURE.as_bytes(), SELF_DNS)
+            .unwrap()
+            .into_iter()
+            .find(|e| e.url == url)
+            .unwrap()
+    }
+
+    #[test]
+    fn serve_add_https_to_an_empty_config() {
+        let expected = serde_json::json!({
+            "TCP": { "443": { "HTTPS": true } },
+            "Web": {
+                "laptop.tail1234.ts.net:443": {
+                    "Handlers": { "/": { "Proxy": "http://127.0.0.1:3000" } }
+                }
+            }
+        });
+        for empty in ["", "null", "{}"] {
+            let config = serve_edit(empty, add(ServeKind::Https, 443, "3000")).unwrap();
+            assert_eq!(config, expected, "{empty:?}");
+        }
+    }
+
+    #[test]
+    fn serve_add_refuses_a_port_in_use() {
+        let e = serve_edit(SERVE_FIXTURE, add(ServeKind::Https, 2222, "3000")).unwrap_err();
+        assert!(e.to_string().contains("Port 2222 already forwards TCP"), "{e}");
+        let e = serve_edit(SERVE_FIXTURE, add(ServeKind::Tcp, 443, "5432")).unwrap_err();
+        assert!(e.to_string().contains("Port 443 already serves HTTPS"), "{e}");
+    }
+
+    #[test]
+    fn serve_add_tcp_forward() {
+        let edit = add(ServeKind::Tcp, 5432, "localhost:5432");
+        let config = serve_edit(SERVE_FIXTURE, edit).unwrap();
+        assert_eq!(config["TCP"]["5432"], serde_json::json!({ "TCPForward": "localhost:5432" }));
+        assert_eq!(config["TCP"]["2222"]["TCPForward"], "127.0.0.1:22");
+    }
+
+    #[test]
+    fn serve_remove_prunes_what_is_left_empty() {
+        // Another handler still uses port 443
+        let docs = fixture_endpoint("https://laptop.tail1234.ts.net/docs");
+        let config = serve_edit(SERVE_FIXTURE, ServeEdit::Remove(docs)).unwrap();
+        let handlers = &config["Web"]["laptop.tail1234.ts.net:443"]["Handlers"];
+        assert!(handlers.get("/docs").is_none());
+        assert!(handlers.get("/").is_some());
+        assert_eq!(config["TCP"]["443"]["HTTPS"], true);
+
+        // The only handler on 8443, which was also on Funnel
+        let public = fixture_endpoint("https://laptop.tail1234.ts.net:8443/");
+        let config = serve_edit(SERVE_FIXTURE, ServeEdit::Remove(public)).unwrap();
+        assert!(config["Web"].get("laptop.tail1234.ts.net:8443").is_none());
+        assert!(config["TCP"].get("8443").is_none());
+        assert!(config.get("AllowFunnel").is_none());
+
+        let ssh = fixture_endpoint("tcp://laptop.tail1234.ts.net:2222");
+        let config = serve_edit(SERVE_FIXTURE, ServeEdit::Remove(ssh)).unwrap();
+        assert!(config["TCP"].get("2222").is_none());
+
+        // Removing the last mapping leaves an empty config
+        let only = serve_edit("", add(ServeKind::Https, 443, "3000")).unwrap();
+        let endpoint = parse_serve_config(only.to_string().as_bytes(), SELF_DNS).unwrap();
+        let config = serve_edit(&only.to_string(), ServeEdit::Remove(endpoint[0].clone())).unwrap();
+        assert_eq!(config, serde_json::json!({}));
+    }
+
+    #[test]
+    fn serve_funnel_on_and_off() {
+        let root = fixture_endpoint("https://laptop.tail1234.ts.net/");
+        let config = serve_edit(SERVE_FIXTURE, ServeEdit::Funnel(root, true)).unwrap();
+        assert_eq!(config["AllowFunnel"]["laptop.tail1234.ts.net:443"], true);
+        assert_eq!(config["AllowFunnel"]["laptop.tail1234.ts.net:8443"], true);
+
+        let public = fixture_endpoint("https://laptop.tail1234.ts.net:8443/");
+        let config = serve_edit(SERVE_FIXTURE, ServeEdit::Funnel(public, false)).unwrap();
+        assert!(config.get("AllowFunnel").is_none());
+
+        let ssh = fixture_endpoint("tcp://laptop.tail1234.ts.net:2222");
+        let e = serve_edit(SERVE_FIXTURE, ServeEdit::Funnel(ssh, true)).unwrap_err();
+        assert!(e.to_string().contains("not 2222"), "{e}");
+    }
+
+    #[test]
+    fn serve_edit_keeps_unknown_fields() {
+        let json = r#"{
+            "ETag": "abc",
+            "Services": { "svc:web": { "TCP": { "443": { "HTTPS": true } } } },
+            "TCP": { "443": { "HTTPS": true, "TerminateTLS": "x" } },
+            "Web": {
+                "laptop.tail1234.ts.net:443": {
+                    "Handlers": { "/": { "Proxy": "http://127.0.0.1:3000", "Extra": 1 } }
+                }
+            }
+        }"#;
+        let config = serve_edit(json, add(ServeKind::Tcp, 5432, "5432")).unwrap();
+        assert_eq!(config["ETag"], "abc");
+        assert_eq!(config["Services"]["svc:web"]["TCP"]["443"]["HTTPS"], true);
+        assert_eq!(config["TCP"]["443"]["TerminateTLS"], "x");
+        let root = &config["Web"]["laptop.tail1234.ts.net:443"]["Handlers"]["/"];
+        assert_eq!(root["Extra"], 1);
+        assert_eq!(config["TCP"]["5432"]["TCPForward"], "127.0.0.1:5432");
+    }
+
+    #[test]
+    fn serve_targets_expand_like_the_cli() {
+        let ok = [
+            ("3000", "http", "http://127.0.0.1:3000"),
+            ("localhost:8080", "http", "http://localhost:8080"),
+            ("http://localhost", "http", "http://localhost"),
+            ("https+insecure://127.0.0.1:8443/app", "http", "https+insecure://127.0.0.1:8443/app"),
+            ("22", "tcp", "tcp://127.0.0.1:22"),
+            ("tcp://[::1]:5432", "tcp", "tcp://[::1]:5432"),
+        ];
+        for (target, scheme, expanded) in ok {
+            assert_eq!(expand_serve_target(target, scheme).unwrap(), expanded, "{target}");
+        }
+        for (target, scheme) in [
+            ("example.com:80", "http"),
+            ("http://10.0.0.1:80", "http"),
+            ("ftp://localhost:21", "http"),
+            ("localhost", "tcp"),
+            ("http://localhost:80", "tcp"),
+        ] {
+            assert!(expand_serve_target(target, scheme).is_err(), "{target}");
+        }
+    }
+
+    #[test]
+    fn drive_shares_from_localapi_fixture() {
+        let json = include_str!("../resources/fixtures/drive.json");
+        let shares = parse_drive_shares(json.as_bytes()).unwrap();
+        assert_eq!(shares.len(), 2);
+        assert_eq!(shares[1].name, "photos (2024)");
+        assert_eq!(shares[1].path, "/home/user/Pictures/2024");
+        assert!(parse_drive_shares(b"null").unwrap().is_empty());
+    }
+
+    #[test]
+    fn drive_list_output_is_cut_at_the_columns() {
+        // `tailscale drive list`, with a space in a path
+        let output = "name             path                        as\n\
+         -------------    ------------------------    ----\n\
+         documents        /home/user/My Documents     user\n\
+         photos (2024)    /home/user/Pictures/2024    user\n";
+        let shares = parse_drive_list_output(output);
+        assert_eq!(
+            shares,
+            [
+                DriveShare {
+                    name: "documents".to_string(),
+                    path: "/home/user/My Documents".to_string(),
+                },
+                DriveShare {
+                    name: "photos (2024)".to_string(),
+                    path: "/home/user/Pictures/2024".to_string(),
+                },
+            ]
+        );
+        assert!(parse_drive_list_output("No shares\n").is_empty());
+    }
+
+    #[test]
+    fn propfind_lists_children_without_the_folder_itself() {
+        // Taildrive's reply to `PROPFIND /tail1234.ts.net/nas` with Depth: 1
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<D:multistatus xmlns:D="DAV:">
+<D:response><D:href>/tail1234.ts.net/nas/</D:href><D:propstat><D:prop><D:resourcetype><D:collection/></D:resourcetype></D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>
+<D:response><D:href>/tail1234.ts.net/nas/media/</D:href><D:propstat><D:prop><D:resourcetype><D:collection/></D:resourcetype></D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>
+<D:response><D:href>/tail1234.ts.net/nas/photos%20%282024%29/</D:href><D:propstat><D:prop><D:resourcetype><D:collection/></D:resourcetype></D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>
+<D:response><D:href>http://100.100.100.100:8080/tail1234.ts.net/nas/tom%20&amp;%20jerry/</D:href><D:propstat><D:prop><D:resourcetype><D:collection/></D:resourcetype></D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>
+<D:response><D:href>/tail1234.ts.net/nas/media/movies/</D:href><D:propstat><D:prop><D:resourcetype><D:collection/></D:resourcetype></D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>
+<D:response><D:href>/tail1234.ts.net/nas/backups</D:href><D:propstat><D:prop><D:resourcetype><D:collection/></D:resourcetype></D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>
+</D:multistatus>"#;
+        assert_eq!(
+            parse_propfind_children(xml, "/tail1234.ts.net/nas"),
+            ["backups", "media", "photos (2024)", "tom & jerry"]
+        );
+        // The folder may be given percent-encoded, with a trailing slash
+        let renamed = xml.replace("/nas/", "/my%20nas/");
+        assert_eq!(parse_propfind_children(&renamed, "/tail1234.ts.net/my%20nas/").len(), 4);
+    }
+
+    #[test]
+    fn propfind_children_match_the_remote_drives_fixture() {
+        let fixture: HashMap<String, Vec<String>> =
+            serde_json::from_str(include_str!("../resources/fixtures/remote-drives.json")).unwrap();
+        let href = |share: &str| format!("<d:href>/tail1234.ts.net/nas/{share}/</d:href>");
+        let xml = format!(
+            "<d:multistatus xmlns:d=\"DAV:\"><d:response>{}</d:response><d:response>{}</d:response>\
+             <d:response>{}</d:response></d:multistatus>",
+            "<d:href>/tail1234.ts.net/nas/</d:href>",
+            href("backups"),
+            href("media"),
+        );
+        assert_eq!(parse_propfind_children(&xml, "/tail1234.ts.net/nas"), fixture["nas"]);
+    }
+
+    #[test]
+    fn pong_lines_over_derp_and_direct() {
+        let pong = |line| parse_pong_line(line).unwrap();
+        assert_eq!(
+            pong("pong from desktop (100.64.0.2) via DERP(syd) in 45ms"),
+            Pong {
+                latency_ms: 45.0,
+                path: PingPath::Derp("syd".to_string()),
+            }
+        );
+        assert_eq!(
+            pong("pong from desktop (100.64.0.2) via 192.168.1.20:41641 in 3ms"),
+            Pong {
+                latency_ms: 3.0,
+                path: PingPath::Direct("192.168.1.20:41641".to_string()),
+            }
+        );
+        assert_eq!(
+            pong("pong from nas (fd7a:115c:a1e0::3) via [2001:db8::5]:41641 in 1.25s"),
+            Pong {
+                latency_ms: 1250.0,
+                path: PingPath::Direct("[2001:db8::5]:41641".to_string()),
+            }
+        );
+        assert_eq!(parse_pong_line("pong from desktop (100.64.0.2) via DERP(syd) in"), None);
+        assert_eq!(parse_pong_line("2024/01/01 timeout waiting for ping reply"), None);
+        assert_eq!(parse_pong_line("direct connection not established"), None);
+    }
+
+    #[test]
+    fn netcheck_sorts_regions_by_latency() {
+        let report = parse_netcheck(
+            include_bytes!("../resources/fixtures/netcheck.json"),
+            Some(include_bytes!("../resources/fixtures/derpmap.json").as_slice()),
+        )
+        .unwrap();
+        assert!(report.udp && report.ipv4 && !report.ipv6);
+        assert_eq!(report.global_v4, "203.0.113.5:41641");
+        assert_eq!(report.mapping_varies_by_dest_ip, Some(false));
+        let preferred = report.preferred_derp.unwrap();
+        assert_eq!((preferred.id, preferred.code.as_str()), (17, "syd"));
+        assert_eq!(preferred.name, "Sydney");
+
+        let order: Vec<&str> = report
+            .region_latency
+            .iter()
+            .map(|(r, _)| r.code.as_str())
+            .collect();
+        assert_eq!(order, ["syd", "hkg", "sfo", "nyc"]);
+        assert!((report.region_latency[0].1 - 24.56789).abs() < 1e-6);
+    }
+
+    #[test]
+    fn netcheck_without_derp_map_names_regions_by_id() {
+        let report =
+            parse_netcheck(include_bytes!("../resources/fixtures/netcheck.json"), None).unwrap();
+        assert_eq!(report.preferred_derp.unwrap().name, "Region 17");
+        assert_eq!(report.region_latency.len(), 4);
+        assert!(parse_netcheck(b"not json", None).is_err());
+    }
+
+    fn exit_node(name: &str, country: (&str, &str), city: &str, priority: i64) -> PeerInfo {
+        PeerInfo {
+            id: name.to_string(),
+            hostname: name.to_string(),
+            online: true,
+            exit_node_option: true,
+            location: Some(Location {
+                country: country.0.to_string(),
+                country_code: country.1.to_string(),
+                city: city.to_string(),
+                priority,
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn exit_nodes_group_by_country_and_city() {
+        const SE: (&str, &str) = ("Sweden", "SE");
+        const DE: (&str, &str) = ("Germany", "DE");
+        let mut offline = exit_node("se-sto-3", SE, "Stockholm", 99);
+        offline.online = false;
+        let mut only_offline = exit_node("se-got-1", SE, "Gothenburg", 100);
+        only_offline.online = false;
+        let mut not_exit = exit_node("se-sto-9", SE, "Stockholm", 200);
+        not_exit.exit_node_option = false;
+        let mut no_location = exit_node("home", SE, "", 0);
+        no_location.location = None;
+        let peers = [
+            exit_node("se-sto-1", SE, "Stockholm", 10),
+            exit_node("se-sto-2", SE, "Stockholm", 50),
+            offline,
+            only_offline,
+            exit_node("de-fra-1", DE, "Frankfurt", 5),
+            not_exit,
+            no_location,
+        ];
+
+        let countries = group_exit_node_locations(&peers);
+        let names: Vec<&str> = countries.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["Germany", "Sweden"]);
+        assert_eq!(countries[1].code, "SE");
+
+        let sweden = &countries[1].cities;
+        let cities: Vec<(&str, &str, usize)> = sweden
+            .iter()
+            .map(|c| (c.name.as_str(), c.best.id.as_str(), c.node_count))
+            .collect();
+        // An online node beats a better offline one
+        assert_eq!(cities, [("Gothenburg", "se-got-1", 1), ("Stockholm", "se-sto-2", 3)]);
+    }
+
+    #[test]
+    fn switch_list_marks_the_current_profile() {
+        let out = "ID    Tailnet             Account\n\
+                   1a2b  example.com         alex@example.com*\n\
+                   3c4d  work.example.org    alex@work.example.org\n\
+                   5e6f  alex@gmail.com\n";
+        let profiles = parse_switch_list(out);
+        assert_eq!(
+            profiles,
+            [
+                LoginProfile {
+                    id: "1a2b".to_string(),
+                    tailnet: "example.com".to_string(),
+                    account: "alex@example.com".to_string(),
+                    current: true,
+                },
+                LoginProfile {
+                    id: "3c4d".to_string(),
+                    tailnet: "work.example.org".to_string(),
+                    account: "alex@work.example.org".to_string(),
+                    current: false,
+                },
+                LoginProfile {
+                    id: "5e6f".to_string(),
+                    tailnet: String::new(),
+                    account: "alex@gmail.com".to_string(),
+                    current: false,
+                },
+            ]
+        );
+        assert!(parse_switch_list("ID    Tailnet    Account\n").is_empty());
+    }
+
+    #[test]
+    fn auth_url_from_login_output() {
+        let out = "\nTo authenticate, visit:\n\n\thttps://login.tailscale.com/a/1a2b3c\n\n";
+        let urls: Vec<_> = out.lines().filter_map(parse_auth_url).collect();
+        assert_eq!(urls, ["https://login.tailscale.com/a/1a2b3c"]);
+        assert_eq!(parse_auth_url("Success."), None);
+    }
+}